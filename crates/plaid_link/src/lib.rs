@@ -9,15 +9,44 @@ use axum::{
     routing::get,
     Router,
 };
+use hmac::{Hmac, Mac};
 use lazy_static::lazy_static;
-use rplaid::{client::Plaid, model::*};
+use rplaid::{client::Plaid, model::*, HttpClient};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use thiserror::Error;
 use tokio::sync::broadcast;
+use tracing::warn;
 use url::Url;
 
+#[cfg(feature = "mock")]
+pub mod mock;
+
 static CLIENT_NAME: &str = "clerk";
+// Plaid rejects client names longer than this.
+// https://plaid.com/docs/api/tokens/#link-token-create-request-client-name
+const MAX_CLIENT_NAME_LENGTH: usize = 30;
 static PRODUCTS: [&str; 1] = ["transactions"];
+// Products clerk can request access to when creating a link token.
+// https://plaid.com/docs/api/tokens/#link-token-create-request-products
+static SUPPORTED_PRODUCTS: [&str; 6] = [
+    "assets",
+    "auth",
+    "identity",
+    "investments",
+    "liabilities",
+    "transactions",
+];
+// https://plaid.com/docs/api/tokens/#link-token-create-request-country-codes
+static SUPPORTED_COUNTRY_CODES: [&str; 9] = ["US", "CA", "DE", "ES", "FR", "GB", "IE", "IT", "NL"];
+// The encoded state is carried in redirect URLs, keep the context well under
+// common URL length limits.
+const MAX_CONTEXT_BYTES: usize = 1024;
+// Context keys that would be confused with the state's own fields or the link
+// flow's query parameters.
+static RESERVED_CONTEXT_KEYS: [&str; 5] = ["user_id", "country_codes", "mode", "token", "state"];
+
+type HmacSha256 = Hmac<Sha256>;
 
 lazy_static! {
     // HACK: Url doesn't provide a good way to initialize a Url from a relative
@@ -40,12 +69,18 @@ pub enum LinkError {
     LinkClientError(#[from] rplaid::client::ClientError),
     #[error("invalid string source")]
     BadRequest(#[from] std::string::FromUtf8Error),
+    #[error("nothing is waiting for the exchanged token")]
+    NoListener,
 }
 
 impl IntoResponse for LinkError {
     fn into_response(self) -> Response {
         match self {
             LinkError::InvalidArgument(s) => (StatusCode::BAD_REQUEST, Html(s)),
+            LinkError::NoListener => (
+                StatusCode::GONE,
+                Html("the link is no longer waiting for this account, start it again".into()),
+            ),
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Html("something really bad happened".into()),
@@ -115,14 +150,99 @@ pub struct State {
 }
 
 impl State {
-    pub fn to_opaque(self) -> Result<String, serde_json::Error> {
-        Ok(base64::encode_config(
-            serde_json::to_string(&self)?.as_bytes(),
-            base64::URL_SAFE,
-        ))
+    /// Checks the context fits in a redirect URL and uses no reserved keys.
+    pub fn validate(&self) -> Result<(), LinkError> {
+        let context = match &self.context {
+            Some(context) => context,
+            None => return Ok(()),
+        };
+
+        if let Some(key) = context
+            .keys()
+            .find(|key| RESERVED_CONTEXT_KEYS.contains(&key.as_str()))
+        {
+            return Err(LinkError::InvalidArgument(format!(
+                "context key {} is reserved",
+                key
+            )));
+        }
+
+        let size: usize = context.iter().map(|(k, v)| k.len() + v.len()).sum();
+        if size > MAX_CONTEXT_BYTES {
+            return Err(LinkError::InvalidArgument(format!(
+                "context is {} bytes, at most {} are allowed",
+                size, MAX_CONTEXT_BYTES
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the state for use as a query parameter, followed by a
+    /// signature when `signing` holds a key.
+    pub fn to_opaque(self, signing: &StateSigning) -> Result<String, serde_json::Error> {
+        let payload =
+            base64::encode_config(serde_json::to_string(&self)?.as_bytes(), base64::URL_SAFE);
+
+        Ok(match signing {
+            StateSigning::Hmac(key) => format!(
+                "{}.{}",
+                payload,
+                base64::encode_config(mac(key, &payload).finalize().into_bytes(), base64::URL_SAFE)
+            ),
+            StateSigning::Unsigned => payload,
+        })
+    }
+
+    /// Decodes a token written by [`State::to_opaque`], rejecting tokens
+    /// whose signature doesn't match.
+    pub fn from_opaque(token: &str, signing: &StateSigning) -> Result<Self, LinkError> {
+        let (payload, signature) = match token.split_once('.') {
+            Some((payload, signature)) => (payload, Some(signature)),
+            None => (token, None),
+        };
+
+        if let StateSigning::Hmac(key) = signing {
+            let signature = signature
+                .ok_or_else(|| LinkError::InvalidArgument("state is not signed".into()))?;
+            mac(key, payload)
+                .verify_slice(&base64::decode_config(signature, base64::URL_SAFE)?)
+                .map_err(|_| LinkError::InvalidArgument("state signature does not match".into()))?;
+        }
+
+        Ok(serde_json::from_str(&String::from_utf8(
+            base64::decode_config(payload.as_bytes(), base64::URL_SAFE)?,
+        )?)?)
     }
 }
 
+/// How opaque [`State`] tokens are protected from tampering. The exchange
+/// endpoint trusts the state it's given, so only state signed by this server
+/// should be accepted.
+#[derive(Clone)]
+pub enum StateSigning {
+    /// Sign with HMAC-SHA256 using the given key.
+    Hmac(Vec<u8>),
+    /// Don't sign, any well formed state is accepted. Only meant for local
+    /// development against older links.
+    Unsigned,
+}
+
+impl StateSigning {
+    /// Signs with a key generated for this process, tokens are only accepted
+    /// by the server that issued them.
+    pub fn random() -> Self {
+        StateSigning::Hmac(rand::random::<[u8; 32]>().to_vec())
+    }
+}
+
+fn mac(key: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+
+    mac
+}
+
 #[async_trait]
 impl<B> FromRequest<B> for State
 where
@@ -140,11 +260,16 @@ where
             .query_pairs()
             .find(|(key, value)| matches!((key.as_ref(), value), ("state", _)));
 
+        // The signing scheme is provided by the server, without one there's
+        // nothing to check the state against.
+        let signing = req
+            .extensions()
+            .get::<StateSigning>()
+            .ok_or_else(|| LinkError::InvalidArgument("state signing is not configured".into()))?;
+
         match state {
             Some((k, v)) => match (k.as_ref(), v.as_ref()) {
-                ("state", token) => Ok(serde_json::from_str(&String::from_utf8(
-                    base64::decode_config(token.as_bytes(), base64::URL_SAFE)?,
-                )?)?),
+                ("state", token) => State::from_opaque(token, signing),
                 _ => unimplemented!(),
             },
             None => Err(LinkError::InvalidArgument("no state object found".into())),
@@ -163,53 +288,176 @@ pub struct Token {
     pub state: State,
 }
 
-pub struct LinkServer {
-    pub client: Plaid,
+/// The application name shown to users in the Plaid Link UI.
+#[derive(Debug, Clone)]
+struct ClientName(String);
+
+/// The Plaid products new links request access to.
+#[derive(Debug, Clone)]
+struct Products(Vec<String>);
+
+/// Checks `name` is accepted by Plaid as a client name.
+pub fn validate_client_name(name: &str) -> Result<(), LinkError> {
+    if name.trim().is_empty() {
+        return Err(LinkError::InvalidArgument(
+            "client name must not be empty".into(),
+        ));
+    }
+
+    if name.chars().count() > MAX_CLIENT_NAME_LENGTH {
+        return Err(LinkError::InvalidArgument(format!(
+            "client name must be at most {} characters",
+            MAX_CLIENT_NAME_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+/// Checks every code in `codes` is a country Plaid Link supports, at least one
+/// code is required.
+pub fn validate_country_codes<S: AsRef<str>>(codes: &[S]) -> Result<(), LinkError> {
+    if codes.is_empty() {
+        return Err(LinkError::InvalidArgument(
+            "at least one country code is required".into(),
+        ));
+    }
+
+    for code in codes {
+        if !SUPPORTED_COUNTRY_CODES.contains(&code.as_ref()) {
+            return Err(LinkError::InvalidArgument(format!(
+                "unsupported country code {}, expected one of {}",
+                code.as_ref(),
+                SUPPORTED_COUNTRY_CODES.join(", ")
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every product in `products` can be requested through Link, at least
+/// one product is required.
+pub fn validate_products<S: AsRef<str>>(products: &[S]) -> Result<(), LinkError> {
+    if products.is_empty() {
+        return Err(LinkError::InvalidArgument(
+            "at least one product is required".into(),
+        ));
+    }
+
+    for product in products {
+        if !SUPPORTED_PRODUCTS.contains(&product.as_ref()) {
+            return Err(LinkError::InvalidArgument(format!(
+                "unsupported product {}, expected one of {}",
+                product.as_ref(),
+                SUPPORTED_PRODUCTS.join(", ")
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub struct LinkServer<T: HttpClient> {
+    pub client: Plaid<T>,
     pub link_channel: broadcast::Sender<Token>,
     pub listener: broadcast::Receiver<Token>,
+    client_name: ClientName,
+    products: Products,
+    state_signing: StateSigning,
 }
 
-impl LinkServer {
-    pub fn new(client: Plaid) -> Self {
+impl<T: HttpClient> LinkServer<T> {
+    pub fn new(client: Plaid<T>) -> Self {
         let (tx, rx) = broadcast::channel(1);
 
         Self {
             client,
             link_channel: tx,
             listener: rx,
+            client_name: ClientName(CLIENT_NAME.to_string()),
+            products: Products(PRODUCTS.iter().map(|p| p.to_string()).collect()),
+            state_signing: StateSigning::random(),
         }
     }
 
+    /// Overrides the Plaid products new links request, defaults to
+    /// "transactions".
+    pub fn with_products<S: AsRef<str>>(self, products: &[S]) -> Result<Self, LinkError> {
+        validate_products(products)?;
+
+        Ok(Self {
+            products: Products(products.iter().map(|p| p.as_ref().to_string()).collect()),
+            ..self
+        })
+    }
+
+    /// Overrides how state tokens are signed, defaults to a key generated for
+    /// this server.
+    pub fn with_state_signing(self, state_signing: StateSigning) -> Self {
+        Self {
+            state_signing,
+            ..self
+        }
+    }
+
+    /// Encodes `state` for a link URL served by this server.
+    pub fn opaque_state(&self, state: State) -> Result<String, LinkError> {
+        state.validate()?;
+
+        Ok(state.to_opaque(&self.state_signing)?)
+    }
+
+    /// Overrides the application name shown in the Plaid Link UI, defaults to
+    /// "clerk".
+    pub fn with_client_name(self, name: &str) -> Result<Self, LinkError> {
+        validate_client_name(name)?;
+
+        Ok(Self {
+            client_name: ClientName(name.to_string()),
+            ..self
+        })
+    }
+
     pub fn on_exchange(&self) -> broadcast::Receiver<Token> {
         self.link_channel.subscribe()
     }
 
     pub fn start(self) -> Router {
         Router::new()
-            .route("/link", get(initialize_link))
-            .route("/exchange/:token", get(exchange_token))
+            .route("/link", get(initialize_link::<T>))
+            .route("/exchange/:token", get(exchange_token::<T>))
             .layer(Extension(Arc::new(self.client)))
             .layer(Extension(self.link_channel))
+            .layer(Extension(self.client_name))
+            .layer(Extension(self.products))
+            .layer(Extension(self.state_signing))
     }
 }
 
-async fn initialize_link(
+async fn initialize_link<T: HttpClient>(
     mode: LinkMode,
     state: State,
-    client: Extension<Arc<Plaid>>,
+    client: Extension<Arc<Plaid<T>>>,
+    Extension(ClientName(client_name)): Extension<ClientName>,
+    Extension(Products(products)): Extension<Products>,
+    Extension(state_signing): Extension<StateSigning>,
 ) -> impl IntoResponse {
+    validate_country_codes(&state.country_codes)?;
+    state.validate()?;
     let country_codes: Vec<&str> = state.country_codes.iter().map(AsRef::as_ref).collect();
+    let products: Vec<&str> = products.iter().map(AsRef::as_ref).collect();
     let req = match &mode {
         LinkMode::Create => CreateLinkTokenRequest {
-            client_name: CLIENT_NAME,
+            client_name: &client_name,
             user: LinkUser::new(&state.user_id),
             language: "en",
             country_codes: country_codes.as_slice(),
-            products: &crate::PRODUCTS,
+            products: products.as_slice(),
             ..CreateLinkTokenRequest::default()
         },
         LinkMode::Update(token) => CreateLinkTokenRequest {
-            client_name: CLIENT_NAME,
+            client_name: &client_name,
             user: LinkUser::new(&state.user_id),
             language: "en",
             country_codes: country_codes.as_slice(),
@@ -236,7 +484,9 @@ async fn initialize_link(
                     </DOCTYPE>
                     "#,
             r.link_token,
-            state.to_opaque().map_err(LinkError::ParseError)?,
+            state
+                .to_opaque(&state_signing)
+                .map_err(LinkError::ParseError)?,
         ))),
         Err(err) => Err(LinkError::InvalidArgument(format!(
             "unexpected error {:?}",
@@ -245,10 +495,10 @@ async fn initialize_link(
     }
 }
 
-async fn exchange_token<'a>(
+async fn exchange_token<'a, T: HttpClient>(
     Path(token): Path<String>,
     state: State,
-    client: Extension<Arc<Plaid>>,
+    client: Extension<Arc<Plaid<T>>>,
     on_exchange: Extension<broadcast::Sender<Token>>,
 ) -> Result<Html<&'a str>, LinkError> {
     let res = client
@@ -256,17 +506,34 @@ async fn exchange_token<'a>(
         .await
         .map_err(LinkError::LinkClientError)?;
 
-    on_exchange
-        .send(Token {
+    broadcast_token(
+        &on_exchange,
+        Token {
             item_id: res.item_id,
             access_token: res.access_token,
             state,
-        })
-        .unwrap();
+        },
+    )?;
 
     Ok(Html("OK"))
 }
 
+/// Hands an exchanged token to whoever started the link flow, which may have
+/// stopped waiting, e.g. after timing out, before the user finished.
+fn broadcast_token(on_exchange: &broadcast::Sender<Token>, token: Token) -> Result<(), LinkError> {
+    let item_id = token.item_id.clone();
+    on_exchange.send(token).map_err(|_| {
+        warn!(
+            "Dropping token for item {}, nothing is waiting for it.",
+            item_id
+        );
+
+        LinkError::NoListener
+    })?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -330,11 +597,103 @@ mod tests {
             user_id: "foobar@tester.com".to_string(),
             context: None,
         };
+        let signing = StateSigning::random();
 
         let mut req = request_parts_from_uri(&format!(
             "http://localhost:4000/init?state={}",
-            state.clone().to_opaque().unwrap()
+            state.clone().to_opaque(&signing).unwrap()
         ));
+        req.extensions_mut().insert(signing);
         assert_eq!(State::from_request(&mut req).await.unwrap(), state)
     }
+
+    #[test]
+    fn signed_state_rejects_tampering() {
+        let state = State {
+            country_codes: vec!["US".to_string()],
+            user_id: "foobar@tester.com".to_string(),
+            context: None,
+        };
+        let signing = StateSigning::Hmac(b"secret".to_vec());
+        let token = state.clone().to_opaque(&signing).unwrap();
+        assert_eq!(State::from_opaque(&token, &signing).unwrap(), state);
+
+        let forged = State {
+            user_id: "attacker".to_string(),
+            ..state.clone()
+        };
+        let (_, signature) = token.split_once('.').unwrap();
+        let unsigned = forged.to_opaque(&StateSigning::Unsigned).unwrap();
+        assert!(State::from_opaque(&format!("{}.{}", unsigned, signature), &signing).is_err());
+        assert!(State::from_opaque(&unsigned, &signing).is_err());
+        assert!(State::from_opaque(&token, &StateSigning::Hmac(b"other".to_vec())).is_err());
+
+        // Signatures are ignored when signing is disabled.
+        assert_eq!(
+            State::from_opaque(&token, &StateSigning::Unsigned).unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn state_context_is_bounded() {
+        let state = |key: &str, value: String| State {
+            country_codes: vec!["US".to_string()],
+            user_id: "foobar@tester.com".to_string(),
+            context: Some(HashMap::from([(key.to_string(), value)])),
+        };
+
+        assert!(state("link_name", "checking".into()).validate().is_ok());
+        assert!(state("link_name", "a".repeat(MAX_CONTEXT_BYTES))
+            .validate()
+            .is_err());
+        assert!(state("user_id", "other-user".into()).validate().is_err());
+    }
+
+    #[test]
+    fn client_name_length_limits() {
+        assert!(validate_client_name("clerk").is_ok());
+        assert!(validate_client_name(&"a".repeat(MAX_CLIENT_NAME_LENGTH)).is_ok());
+        assert!(validate_client_name(&"a".repeat(MAX_CLIENT_NAME_LENGTH + 1)).is_err());
+        assert!(validate_client_name(" ").is_err());
+    }
+
+    #[test]
+    fn products_must_be_supported() {
+        assert!(validate_products(&["transactions", "investments"]).is_ok());
+        assert!(validate_products(&["transactions", "balance"]).is_err());
+        assert!(validate_products::<&str>(&[]).is_err());
+    }
+
+    #[test]
+    fn country_codes_must_be_supported() {
+        assert!(validate_country_codes(&["US", "CA"]).is_ok());
+        assert!(validate_country_codes(&["US", "JP"]).is_err());
+        assert!(validate_country_codes(&["us"]).is_err());
+        assert!(validate_country_codes::<&str>(&[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn late_exchanges_do_not_panic() {
+        let token = Token {
+            item_id: "item-id".to_string(),
+            access_token: "access-token".to_string(),
+            state: State {
+                country_codes: vec!["US".to_string()],
+                user_id: "foobar@tester.com".to_string(),
+                context: None,
+            },
+        };
+        let (tx, rx) = broadcast::channel(1);
+        drop(rx);
+
+        assert!(matches!(
+            broadcast_token(&tx, token.clone()),
+            Err(LinkError::NoListener)
+        ));
+
+        let mut rx = tx.subscribe();
+        broadcast_token(&tx, token).unwrap();
+        assert_eq!(rx.recv().await.unwrap().item_id, "item-id");
+    }
 }
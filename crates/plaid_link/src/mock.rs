@@ -0,0 +1,243 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use axum::async_trait;
+use http_types::{Body, Request, Response, StatusCode};
+use rplaid::model::{Account, AccountType, Balance};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// An HTTP client serving canned Plaid responses keyed by endpoint path, pass
+/// it to `rplaid::client::Builder::with_http_client` to exercise code that
+/// talks to Plaid without credentials.
+///
+/// Responses queued with [`MockPlaid::respond`] are served in order after any
+/// already queued, the last response for a path is repeated once the queue is
+/// drained. Request bodies are kept for [`MockPlaid::requests`].
+#[derive(Debug, Clone)]
+pub struct MockPlaid {
+    responses: Arc<Mutex<HashMap<String, VecDeque<(StatusCode, Value)>>>>,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl MockPlaid {
+    pub fn new() -> Self {
+        Self {
+            responses: Arc::default(),
+            requests: Arc::default(),
+        }
+        .replace(
+            "/link/token/create",
+            json!({
+                "link_token": "link-sandbox-1234",
+                "expiration": "2022-12-01T00:00:00Z",
+                "request_id": "mock",
+            }),
+        )
+        .replace(
+            "/item/public_token/exchange",
+            json!({
+                "access_token": "access-sandbox-1234",
+                "item_id": "mock-item-id",
+                "request_id": "mock",
+            }),
+        )
+        .replace(
+            "/institutions/get",
+            json!({
+                "institutions": [{
+                    "institution_id": "ins_mock",
+                    "name": "Mock Bank",
+                    "products": ["transactions"],
+                    "country_codes": ["US"],
+                    "url": null,
+                    "primary_color": null,
+                    "logo": null,
+                    "routing_numbers": [],
+                    "oauth": false,
+                }],
+                "total": 1,
+                "request_id": "mock",
+            }),
+        )
+        .with_item("mock-item-id", "ins_mock")
+        .with_accounts(vec![])
+        .with_transactions(Vec::<Value>::new(), "mock-cursor-1")
+    }
+
+    /// Queues `body` as the next response for requests to `path`.
+    pub fn respond(self, path: &str, body: Value) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push_back((StatusCode::Ok, body));
+
+        self
+    }
+
+    /// Serves only a Plaid error for requests to `path`, dropping queued
+    /// responses. Responses queued afterwards are served once the error has
+    /// been.
+    pub fn fail(self, path: &str, status: u16, error_type: &str, error_code: &str) -> Self {
+        let body = json!({
+            "error_type": error_type,
+            "error_code": error_code,
+            "error_message": format!("mock {}", error_code),
+            "display_message": null,
+            "request_id": "mock-request",
+        });
+
+        self.responses.lock().unwrap().insert(
+            path.to_string(),
+            VecDeque::from([(status.try_into().unwrap(), body)]),
+        );
+
+        self
+    }
+
+    /// Serves only `body` for requests to `path`, dropping queued responses.
+    pub fn replace(self, path: &str, body: Value) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), VecDeque::from([(StatusCode::Ok, body)]));
+
+        self
+    }
+
+    pub fn with_item(self, item_id: &str, institution_id: &str) -> Self {
+        self.replace(
+            "/item/get",
+            json!({
+                "item": {
+                    "item_id": item_id,
+                    "institution_id": institution_id,
+                    "webhook": null,
+                    "error": null,
+                    "available_products": [],
+                    "billed_products": ["transactions"],
+                    "consent_expiration_time": null,
+                    "update_type": "background",
+                },
+                "request_id": "mock",
+            }),
+        )
+    }
+
+    /// Serves `accounts` from both the accounts and balances endpoints.
+    pub fn with_accounts(self, accounts: Vec<Account>) -> Self {
+        let body = json!({
+            "accounts": accounts,
+            "item": null,
+            "request_id": "mock",
+        });
+
+        self.replace("/accounts/get", body.clone())
+            .replace("/accounts/balance/get", body)
+    }
+
+    /// Serves a single page of added transactions ending at `cursor`.
+    pub fn with_transactions<T: Serialize>(self, added: Vec<T>, cursor: &str) -> Self {
+        self.replace(
+            "/transactions/sync",
+            transactions_page(added, cursor, false),
+        )
+    }
+
+    /// Bodies of the requests sent to `path` so far, oldest first.
+    pub fn requests(&self, path: &str) -> Vec<Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(p, _)| p == path)
+            .map(|(_, body)| body.clone())
+            .collect()
+    }
+
+    fn next_response(&self, path: &str) -> Option<(StatusCode, Value)> {
+        let mut responses = self.responses.lock().unwrap();
+        let queue = responses.get_mut(path)?;
+
+        match queue.len() {
+            0 => None,
+            1 => queue.front().cloned(),
+            _ => queue.pop_front(),
+        }
+    }
+}
+
+/// A `/transactions/sync` page of added transactions ending at `cursor`, more
+/// pages follow when `has_more` is set. Transactions are anything serializing
+/// like Plaid's, e.g. `rplaid::model::Transaction`.
+pub fn transactions_page<T: Serialize>(added: Vec<T>, cursor: &str, has_more: bool) -> Value {
+    json!({
+        "added": added,
+        "modified": [],
+        "removed": [],
+        "next_cursor": cursor,
+        "has_more": has_more,
+        "request_id": "mock",
+    })
+}
+
+/// A USD depository account with no balances.
+pub fn mock_account(id: &str) -> Account {
+    Account {
+        account_id: id.into(),
+        name: "Mock Checking".into(),
+        r#type: AccountType::Depository,
+        official_name: None,
+        verification_status: None,
+        subtype: None,
+        mask: None,
+        balances: Balance {
+            available: None,
+            current: None,
+            iso_currency_code: Some("USD".into()),
+            limit: None,
+            unofficial_currency_code: None,
+        },
+    }
+}
+
+#[async_trait]
+impl http_client::HttpClient for MockPlaid {
+    async fn send(&self, mut req: Request) -> Result<Response, http_types::Error> {
+        let path = req.url().path().to_string();
+        let body = req.body_json().await.unwrap_or(Value::Null);
+        self.requests.lock().unwrap().push((path.clone(), body));
+
+        let mut res = match self.next_response(&path) {
+            Some((status, body)) => {
+                let mut res = Response::new(status);
+                res.set_body(Body::from_json(&body)?);
+                res
+            }
+            None => Response::new(StatusCode::NotFound),
+        };
+        res.insert_header("content-type", "application/json");
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_queued_responses_then_repeats_last() {
+        let mock = MockPlaid::new()
+            .replace("/item/get", json!({"n": 1}))
+            .respond("/item/get", json!({"n": 2}));
+
+        let ok = |body| Some((StatusCode::Ok, body));
+        assert_eq!(mock.next_response("/item/get"), ok(json!({"n": 1})));
+        assert_eq!(mock.next_response("/item/get"), ok(json!({"n": 2})));
+        assert_eq!(mock.next_response("/item/get"), ok(json!({"n": 2})));
+        assert_eq!(mock.next_response("/unknown"), None);
+    }
+}
@@ -1,9 +1,10 @@
 use hyper::Client;
-use plaid_link::State;
+use plaid_link::mock::MockPlaid;
+use plaid_link::{LinkServer, State, StateSigning};
 use rplaid::client::{Builder, Credentials, Environment};
-use rplaid::model::*;
 
-const INSTITUTION_ID: &str = "ins_129571";
+// Served by the mock for any exchange, see `MockPlaid::new`.
+const PUBLIC_TOKEN: &str = "public-sandbox-1234";
 
 fn test_state() -> State {
     State {
@@ -13,28 +14,21 @@ fn test_state() -> State {
     }
 }
 
-#[ignore]
 #[tokio::test]
 async fn can_execute_exchange_flow() -> Result<(), Box<dyn std::error::Error>> {
     let plaid = Builder::new()
         .with_credentials(Credentials {
-            client_id: env!("PLAID_CLIENT_ID").into(),
-            secret: env!("PLAID_SECRET").into(),
+            client_id: "test-client-id".into(),
+            secret: "test-secret".into(),
         })
         .with_env(Environment::Sandbox)
+        .with_http_client(MockPlaid::new())
         .build();
 
-    let token = plaid
-        .create_public_token(CreatePublicTokenRequest {
-            institution_id: INSTITUTION_ID,
-            initial_products: &["transactions"],
-            options: None,
-        })
-        .await
-        .unwrap();
-
-    let server = plaid_link::LinkServer::new(plaid);
-    let recv = server.on_exchange();
+    let server =
+        LinkServer::new(plaid).with_state_signing(StateSigning::Hmac(b"test-key".to_vec()));
+    let state = server.opaque_state(test_state())?;
+    let mut recv = server.on_exchange();
 
     let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 0));
 
@@ -42,33 +36,24 @@ async fn can_execute_exchange_flow() -> Result<(), Box<dyn std::error::Error>> {
     let addr = server.local_addr();
 
     tokio::spawn(async move {
-        // IMPORTANT: Hold a receiver until the test is done to prevent receiver errors.
-        let _recv = recv;
         server.await.unwrap();
     });
 
     let client = Client::new();
-    let link_url = format!(
-        "http://{}/link?state={}",
-        addr.to_string(),
-        test_state().to_opaque().unwrap()
-    )
-    .parse()
-    .unwrap();
-    let resp = client.get(link_url).await.unwrap();
+    let link_url = format!("http://{}/link?state={}", addr, state).parse()?;
+    let resp = client.get(link_url).await?;
 
     assert_eq!(resp.status(), 200);
 
-    let exchange_url = format!(
-        "http://{}/exchange/{}?state={}",
-        addr.to_string(),
-        token,
-        test_state().to_opaque().unwrap()
-    )
-    .parse()
-    .unwrap();
-    let resp = client.get(exchange_url).await.unwrap();
+    let exchange_url =
+        format!("http://{}/exchange/{}?state={}", addr, PUBLIC_TOKEN, state).parse()?;
+    let resp = client.get(exchange_url).await?;
     assert_eq!(resp.status(), 200);
 
+    let token = recv.recv().await?;
+    assert_eq!(token.item_id, "mock-item-id");
+    assert_eq!(token.access_token, "access-sandbox-1234");
+    assert_eq!(token.state, test_state());
+
     Ok(())
 }
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS postings (
+  id TEXT NOT NULL,
+  txn_id TEXT NOT NULL,
+  account TEXT NOT NULL,
+  amount TEXT NOT NULL,
+  currency TEXT NOT NULL,
+
+  FOREIGN KEY (txn_id) REFERENCES transactions (id),
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS int_transactions_links (
+  txn_id TEXT NOT NULL,
+  item_id TEXT NOT NULL,
+  upstream_id TEXT NOT NULL,
+
+  FOREIGN KEY (txn_id) REFERENCES transactions (id),
+  FOREIGN KEY (item_id) REFERENCES plaid_links (id),
+  PRIMARY KEY (txn_id),
+  UNIQUE (item_id, upstream_id)
+);
@@ -0,0 +1,21 @@
+ALTER TABLE transactions
+  ADD COLUMN date TEXT;
+ALTER TABLE transactions
+  ADD COLUMN narration TEXT;
+ALTER TABLE transactions
+  ADD COLUMN payee TEXT;
+ALTER TABLE transactions
+  ADD COLUMN status TEXT;
+
+UPDATE transactions SET
+  date = JSON_EXTRACT(source, '$.date'),
+  narration = JSON_EXTRACT(source, '$.name'),
+  payee = JSON_EXTRACT(source, '$.merchant_name'),
+  status = CASE WHEN JSON_EXTRACT(source, '$.pending') THEN 'PENDING' ELSE 'RESOLVED' END;
+
+CREATE TABLE IF NOT EXISTS meta (
+  key TEXT NOT NULL,
+  value TEXT NOT NULL,
+
+  PRIMARY KEY (key)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE plaid_links
+  ADD column products TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE accounts
+  ADD COLUMN official_name TEXT;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS balance_snapshots (
+  account_id TEXT NOT NULL,
+  taken_at TEXT NOT NULL,
+  available TEXT,
+  current TEXT,
+  currency TEXT NOT NULL,
+
+  FOREIGN KEY (account_id) REFERENCES accounts (id),
+  PRIMARY KEY (account_id, taken_at)
+);
@@ -0,0 +1,21 @@
+-- Location and payment details extracted from the upstream source so they can
+-- be queried and indexed without parsing JSON. The source stays authoritative,
+-- these are computed from it and can't drift.
+ALTER TABLE transactions
+  ADD COLUMN location_city TEXT
+  GENERATED ALWAYS AS (JSON_EXTRACT(source, '$.location.city')) VIRTUAL;
+
+ALTER TABLE transactions
+  ADD COLUMN location_region TEXT
+  GENERATED ALWAYS AS (JSON_EXTRACT(source, '$.location.region')) VIRTUAL;
+
+ALTER TABLE transactions
+  ADD COLUMN location_country TEXT
+  GENERATED ALWAYS AS (JSON_EXTRACT(source, '$.location.country')) VIRTUAL;
+
+ALTER TABLE transactions
+  ADD COLUMN payment_processor TEXT
+  GENERATED ALWAYS AS (JSON_EXTRACT(source, '$.payment_meta.payment_processor')) VIRTUAL;
+
+CREATE INDEX transactions_location_idx
+  ON transactions (location_country, location_region, location_city);
@@ -0,0 +1,4 @@
+-- When the institution was last fetched from upstream, rows saved before this
+-- column existed are treated as stale.
+ALTER TABLE institutions
+  ADD COLUMN last_synced TEXT;
@@ -0,0 +1,6 @@
+-- Tags and key/value metadata attached to a transaction, stored as a JSON
+-- array and object respectively.
+ALTER TABLE transactions
+  ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE transactions
+  ADD COLUMN meta TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,16 @@
+-- The latest investment holdings reported for each account, replaced every
+-- time holdings are fetched.
+CREATE TABLE IF NOT EXISTS holdings (
+  account_id TEXT NOT NULL,
+  security_id TEXT NOT NULL,
+  name TEXT,
+  ticker TEXT,
+  quantity TEXT NOT NULL,
+  price TEXT NOT NULL,
+  value TEXT NOT NULL,
+  currency TEXT NOT NULL,
+  fetched_at TEXT NOT NULL,
+
+  FOREIGN KEY (account_id) REFERENCES accounts (id),
+  PRIMARY KEY (account_id, security_id)
+);
@@ -0,0 +1,13 @@
+-- The latest credit card and loan details reported for each account, an
+-- account has no row when the institution reports no detail for it.
+CREATE TABLE IF NOT EXISTS liabilities (
+  account_id TEXT NOT NULL PRIMARY KEY,
+  apr TEXT,
+  minimum_payment TEXT,
+  next_due_date TEXT,
+  last_statement_balance TEXT,
+  currency TEXT NOT NULL,
+  fetched_at TEXT NOT NULL,
+
+  FOREIGN KEY (account_id) REFERENCES accounts (id)
+);
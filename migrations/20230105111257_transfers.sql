@@ -0,0 +1,11 @@
+-- Pairs of transactions joined into a single transfer between tracked
+-- accounts. The outflow keeps both accounts' postings and the inflow is kept
+-- as a tombstone so syncs don't add it again.
+CREATE TABLE IF NOT EXISTS transfers (
+  txn_id TEXT NOT NULL PRIMARY KEY,
+  joined_id TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL,
+
+  FOREIGN KEY (txn_id) REFERENCES transactions (id),
+  FOREIGN KEY (joined_id) REFERENCES transactions (id)
+);
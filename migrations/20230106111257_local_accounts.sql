@@ -0,0 +1,4 @@
+-- Accounts tracked without Plaid, e.g. imported from OFX files, belong to
+-- this placeholder link so they satisfy the same constraints as linked ones.
+INSERT OR IGNORE INTO plaid_links (id, alias, access_token, link_state, products)
+  VALUES ('local', 'local', '', 'ACTIVE', '');
@@ -0,0 +1,17 @@
+-- Plaid's categories extracted from the upstream source so transactions can be
+-- filtered by them without parsing JSON. `category` is Plaid's legacy
+-- hierarchy as a JSON array, the personal finance category is its successor.
+ALTER TABLE transactions
+  ADD COLUMN category TEXT
+  GENERATED ALWAYS AS (JSON_EXTRACT(source, '$.category')) VIRTUAL;
+
+ALTER TABLE transactions
+  ADD COLUMN category_primary TEXT
+  GENERATED ALWAYS AS (JSON_EXTRACT(source, '$.personal_finance_category.primary')) VIRTUAL;
+
+ALTER TABLE transactions
+  ADD COLUMN category_detailed TEXT
+  GENERATED ALWAYS AS (JSON_EXTRACT(source, '$.personal_finance_category.detailed')) VIRTUAL;
+
+CREATE INDEX transactions_category_idx
+  ON transactions (category_primary, category_detailed);
@@ -0,0 +1,3 @@
+-- The placeholder link is never shown, leave "local" free as an alias for a
+-- real link.
+UPDATE plaid_links SET alias = NULL WHERE id = 'local';
@@ -0,0 +1,8 @@
+-- Aliases name accounts in exported journals so no two accounts may share
+-- one. Accounts without an alias fall back to their institution's name.
+UPDATE accounts SET alias = NULL
+  WHERE alias IS NOT NULL
+    AND rowid NOT IN (SELECT MIN(rowid) FROM accounts WHERE alias IS NOT NULL GROUP BY alias);
+
+CREATE UNIQUE INDEX accounts_alias_idx
+  ON accounts (alias) WHERE alias IS NOT NULL;
@@ -1,46 +1,67 @@
+use std::collections::HashMap;
 use std::io::prelude::*;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc};
 use clap::ArgMatches;
-use futures_lite::stream::StreamExt;
-use lazy_static::lazy_static;
+use futures::stream::{self, StreamExt};
 use rplaid::model::*;
-use rusty_money::{
-    iso::{self, Currency},
-    Money,
-};
+use rust_decimal::Decimal;
+use rusty_money::{iso::Currency, Money};
 use tabwriter::TabWriter;
 
-use crate::plaid::{default_plaid_client, Link};
+use crate::core::{BalanceSnapshot, Liability};
+use crate::currency::{find_currency, resolve_currency, Commodity};
+use crate::plaid::{default_plaid_client, Link, OutputFormat};
+use crate::rates::{Rates, Total};
+use crate::retry::with_retry;
 use crate::settings::Settings;
+use crate::store::{link::LOCAL_ITEM_ID, SqliteStore};
+use crate::upstream::investments::Investments;
+use crate::upstream::liabilities::Liabilities;
+use crate::upstream::{HoldingSource, LiabilitySource};
 
-lazy_static! {
-    static ref ZERO_DOLLARS: Money<'static, Currency> = Money::from_minor(0_i64, iso::USD);
-}
-
-async fn print(settings: Settings) -> Result<()> {
+async fn print(settings: Settings, format: OutputFormat) -> Result<()> {
     let link_controller =
         crate::plaid::LinkController::new(crate::store::SqliteStore::new(&settings.db_file).await?)
             .await?;
 
     let stdout = std::io::stdout().lock();
 
-    link_controller.display_accounts_table(stdout)
+    link_controller.display_accounts_table(stdout, settings.account_name_source, format)
 }
 
-async fn balances(settings: Settings) -> Result<()> {
-    let mut store = crate::store::SqliteStore::new(&settings.db_file).await?;
-    let plaid = default_plaid_client(&settings.plaid);
-
-    let links: Vec<Link> = store.links().list().await?;
+/// Assets less liabilities, converted to a single reporting currency.
+#[derive(Debug, PartialEq)]
+struct NetWorth {
+    assets: Money<'static, Currency>,
+    liabilities: Money<'static, Currency>,
+}
 
-    let mut futures = vec![];
-    for link in links {
-        futures.push(plaid.balances(link.access_token));
+impl NetWorth {
+    fn net(&self) -> Money<'static, Currency> {
+        Money::from_decimal(
+            *self.assets.amount() - *self.liabilities.amount(),
+            self.assets.currency(),
+        )
     }
+}
+
+/// Fetches current balances for every link's accounts and records a snapshot
+/// of each one already in the store.
+async fn fetch_balances(settings: &Settings, store: &mut SqliteStore) -> Result<Vec<Account>> {
+    let default_currency = find_currency(&settings.default_currency)?;
+    let plaid = default_plaid_client(&settings.plaid)?;
+
+    let links: Vec<Link> = store.links().list().await?;
 
-    let results = futures_lite::stream::iter(futures)
-        .then(|f| f)
+    let plaid = &plaid;
+    let policy = &settings.plaid.retry;
+    let results = stream::iter(links)
+        .map(|link| async move {
+            with_retry(policy, || plaid.balances(link.access_token.clone())).await
+        })
+        .buffer_unordered(settings.plaid.max_concurrent_requests.max(1))
         .collect::<Vec<_>>()
         .await;
 
@@ -50,81 +71,530 @@ async fn balances(settings: Settings) -> Result<()> {
             accounts.push(account);
         }
     }
+    // Links finish in any order, sort so tables print the same way each run.
+    accounts.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| a.account_id.cmp(&b.account_id))
+    });
+
+    let taken_at = Utc::now();
+    for account in &accounts {
+        // Snapshots reference stored accounts, anything not yet pulled by a
+        // sync or link status is skipped.
+        if store.accounts().by_id(&account.account_id).await?.is_none() {
+            continue;
+        }
+
+        store
+            .balances()
+            .save(&BalanceSnapshot {
+                account_id: account.account_id.clone(),
+                taken_at,
+                available: account.balances.available,
+                current: account.balances.current,
+                currency: commodity(account, default_currency).code().to_string(),
+            })
+            .await?;
+    }
+
+    Ok(accounts)
+}
+
+async fn balances(settings: Settings) -> Result<()> {
+    let rates = Rates::new(&settings.rates)?;
+    let base_currency = find_currency(&settings.base_currency)?;
+    let default_currency = find_currency(&settings.default_currency)?;
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let accounts = fetch_balances(&settings, &mut store).await?;
 
     let stdout = std::io::stdout().lock();
     let mut tw = TabWriter::new(stdout);
 
     writeln!(tw, "Assets")?;
-    writeln!(tw, "Name\tAvailable\tCurrent")?;
-    for account in accounts
-        .iter()
-        .filter(|account| account.r#type == AccountType::Depository)
-    {
-        let currency_code = account
-            .balances
-            .iso_currency_code
-            .as_deref()
-            .and_then(iso::find)
-            .unwrap_or(iso::USD);
+    write_balances(&mut tw, &accounts, AccountType::Depository, default_currency)?;
+
+    writeln!(tw, "\nLiabililties")?;
+    write_balances(&mut tw, &accounts, AccountType::Credit, default_currency)?;
+
+    let mut net = vec![];
+    let mut unofficial = vec![];
+    for account in &accounts {
+        let current = match account.r#type {
+            AccountType::Depository => account.balances.current.unwrap_or_default(),
+            AccountType::Credit => -account.balances.current.unwrap_or_default(),
+            _ => continue,
+        };
+
+        match commodity(account, default_currency) {
+            Commodity::Iso(currency) => net.push(Money::from_decimal(current, currency)),
+            Commodity::Unofficial(code) => unofficial.push((code, current)),
+        }
+    }
+
+    writeln!(tw, "\nTotal")?;
+    match rates.total(&net, base_currency) {
+        Total::Converted(total) => writeln!(tw, "Net\t\t{}", total)?,
+        Total::Subtotals(subtotals) => {
+            for subtotal in subtotals {
+                writeln!(
+                    tw,
+                    "Net ({})\t\t{}",
+                    subtotal.currency().iso_alpha_code,
+                    subtotal
+                )?;
+            }
+        }
+    }
+    // Conversion rates only cover ISO currencies, report anything else as is.
+    for (code, amount) in unofficial {
+        let commodity = Commodity::Unofficial(code);
         writeln!(
             tw,
-            "{}\t{}\t{}",
-            account.name,
-            account
-                .balances
-                .available
-                .map(|amount| { Money::from_decimal(amount, currency_code) })
-                .as_ref()
-                .unwrap_or(&ZERO_DOLLARS),
-            account
-                .balances
-                .current
-                .map(|amount| { Money::from_decimal(amount, currency_code) })
-                .as_ref()
-                .unwrap_or(&ZERO_DOLLARS),
+            "Net ({})\t\t{}",
+            commodity.code(),
+            commodity.format(amount)
         )?;
     }
 
-    writeln!(tw, "\nLiabililties")?;
+    tw.flush()?;
+
+    Ok(())
+}
+
+/// Sums current balances into assets and liabilities in the `base`
+/// currency, failing when an account's currency has no configured rate.
+fn net_worth(
+    accounts: &[Account],
+    rates: &Rates,
+    base: &'static Currency,
+    default: &'static Currency,
+) -> Result<NetWorth> {
+    let mut assets = Decimal::ZERO;
+    let mut liabilities = Decimal::ZERO;
+    for account in accounts {
+        let total = match account.r#type {
+            AccountType::Depository | AccountType::Investment | AccountType::Brokerage => {
+                &mut assets
+            }
+            AccountType::Credit | AccountType::Loan => &mut liabilities,
+            _ => continue,
+        };
+
+        let current = account.balances.current.unwrap_or_default();
+        let commodity = commodity(account, default);
+        // Rates only cover ISO currencies, anything else can't be converted.
+        let converted = match &commodity {
+            Commodity::Iso(currency) => {
+                rates.convert(&Money::from_decimal(current, *currency), base)
+            }
+            Commodity::Unofficial(_) => None,
+        };
+        match converted {
+            Some(money) => *total += *money.amount(),
+            None => {
+                return Err(anyhow!(
+                    "no rate to convert {} from {code} to {base}, add {code}_{base} to [rates]",
+                    account.name,
+                    code = commodity.code(),
+                    base = base.iso_alpha_code,
+                ))
+            }
+        }
+    }
+
+    Ok(NetWorth {
+        assets: Money::from_decimal(assets, base),
+        liabilities: Money::from_decimal(liabilities, base),
+    })
+}
+
+async fn print_net_worth(settings: Settings) -> Result<()> {
+    let rates = Rates::new(&settings.rates)?;
+    let base_currency = find_currency(&settings.base_currency)?;
+    let default_currency = find_currency(&settings.default_currency)?;
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let accounts = fetch_balances(&settings, &mut store).await?;
+
+    let worth = net_worth(&accounts, &rates, base_currency, default_currency)?;
+
+    let mut tw = TabWriter::new(std::io::stdout().lock());
+    writeln!(tw, "Assets\t{}", worth.assets)?;
+    writeln!(tw, "Liabilities\t{}", worth.liabilities)?;
+    writeln!(tw, "Net Worth\t{}", worth.net())?;
+    tw.flush()?;
+
+    Ok(())
+}
+
+async fn balances_as_of(settings: Settings, date: &str) -> Result<()> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| anyhow!("invalid date {}, expected YYYY-MM-DD: {}", date, e))?;
+    let default_currency = find_currency(&settings.default_currency)?;
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+
+    let stdout = std::io::stdout().lock();
+    let mut tw = TabWriter::new(stdout);
+    writeln!(tw, "Name\tAvailable\tCurrent\tAs Of")?;
+    for account in store.accounts().list().await? {
+        let name = account.display_name(settings.account_name_source);
+        match store.balances().as_of(&account.id, date).await? {
+            Some(snapshot) => {
+                let commodity = resolve_currency(Some(&snapshot.currency), None, default_currency);
+                writeln!(
+                    tw,
+                    "{}\t{}\t{}\t{}",
+                    name,
+                    commodity.format(snapshot.available.unwrap_or_default()),
+                    commodity.format(snapshot.current.unwrap_or_default()),
+                    snapshot.taken_at.format("%Y-%m-%d %H:%M"),
+                )?;
+            }
+            None => writeln!(tw, "{}\t-\t-\tno snapshot on or before {}", name, date)?,
+        }
+    }
+
+    tw.flush()?;
+
+    Ok(())
+}
+
+async fn balance_history(settings: Settings) -> Result<()> {
+    let default_currency = find_currency(&settings.default_currency)?;
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+
+    let stdout = std::io::stdout().lock();
+    let mut tw = TabWriter::new(stdout);
+    writeln!(tw, "Name\tTaken At\tAvailable\tCurrent")?;
+    for account in store.accounts().list().await? {
+        let name = account.display_name(settings.account_name_source);
+        for snapshot in store.balances().history(&account.id).await? {
+            let commodity = resolve_currency(Some(&snapshot.currency), None, default_currency);
+            writeln!(
+                tw,
+                "{}\t{}\t{}\t{}",
+                name,
+                snapshot.taken_at.format("%Y-%m-%d %H:%M"),
+                commodity.format(snapshot.available.unwrap_or_default()),
+                commodity.format(snapshot.current.unwrap_or_default()),
+            )?;
+        }
+    }
+
+    tw.flush()?;
+
+    Ok(())
+}
+
+/// Fetches holdings for every link with the investments product and replaces
+/// what was previously stored for their accounts.
+async fn holdings(settings: Settings) -> Result<()> {
+    let default_currency = find_currency(&settings.default_currency)?;
+    let plaid = default_plaid_client(&settings.plaid)?;
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+
+    for link in store.links().list().await? {
+        if !link.supports_investments() {
+            continue;
+        }
+
+        let source = Investments::new(
+            &plaid,
+            link.access_token.clone(),
+            &settings.default_currency,
+        );
+        let holdings = with_retry(&settings.plaid.retry, || source.holdings()).await?;
+        let account_ids: Vec<String> = store
+            .accounts()
+            .by_item(&link.item_id)
+            .await?
+            .into_iter()
+            .map(|account| account.id)
+            .collect();
+        store.holdings().replace(&account_ids, &holdings).await?;
+    }
+
+    let names: HashMap<String, String> = store
+        .accounts()
+        .list()
+        .await?
+        .into_iter()
+        .map(|account| {
+            let name = account
+                .display_name(settings.account_name_source)
+                .to_string();
+            (account.id, name)
+        })
+        .collect();
+
+    let stdout = std::io::stdout().lock();
+    let mut tw = TabWriter::new(stdout);
+    writeln!(tw, "Account\tSecurity\tQuantity\tPrice\tValue")?;
+    for holding in store.holdings().list().await? {
+        let commodity = resolve_currency(Some(&holding.currency), None, default_currency);
+        let security = match (&holding.name, &holding.ticker) {
+            (Some(name), Some(ticker)) => format!("{} ({})", name, ticker),
+            (Some(name), None) => name.clone(),
+            (None, Some(ticker)) => ticker.clone(),
+            (None, None) => holding.security_id.clone(),
+        };
+        writeln!(
+            tw,
+            "{}\t{}\t{}\t{}\t{}",
+            names
+                .get(&holding.account_id)
+                .unwrap_or(&holding.account_id),
+            security,
+            holding.quantity.normalize(),
+            commodity.format(holding.price),
+            commodity.format(holding.value),
+        )?;
+    }
+
+    tw.flush()?;
+
+    Ok(())
+}
+
+/// Fetches credit card and loan details for every link with the liabilities
+/// product and prints them for each credit normal account, details the
+/// institution doesn't report are shown as "-".
+async fn liabilities(settings: Settings) -> Result<()> {
+    let default_currency = find_currency(&settings.default_currency)?;
+    let plaid = default_plaid_client(&settings.plaid)?;
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+
+    for link in store.links().list().await? {
+        if !link.supports_liabilities() {
+            continue;
+        }
+
+        let source = Liabilities::new(
+            &plaid,
+            link.access_token.clone(),
+            &settings.default_currency,
+        );
+        let liabilities = with_retry(&settings.plaid.retry, || source.liabilities()).await?;
+        let account_ids: Vec<String> = store
+            .accounts()
+            .by_item(&link.item_id)
+            .await?
+            .into_iter()
+            .map(|account| account.id)
+            .collect();
+        store
+            .liabilities()
+            .replace(&account_ids, &liabilities)
+            .await?;
+    }
+
+    let details: HashMap<String, Liability> = store
+        .liabilities()
+        .list()
+        .await?
+        .into_iter()
+        .map(|liability| (liability.account_id.clone(), liability))
+        .collect();
+
+    let stdout = std::io::stdout().lock();
+    let mut tw = TabWriter::new(stdout);
+    writeln!(
+        tw,
+        "Account\tAPR\tMinimum Payment\tNext Due\tLast Statement Balance"
+    )?;
+    for account in store.accounts().list().await? {
+        if account.ty != "CREDIT_NORMAL" {
+            continue;
+        }
+
+        let name = account.display_name(settings.account_name_source);
+        let liability = match details.get(&account.id) {
+            Some(liability) => liability,
+            None => {
+                writeln!(tw, "{}\t-\t-\t-\t-", name)?;
+                continue;
+            }
+        };
+        let commodity = resolve_currency(Some(&liability.currency), None, default_currency);
+        let amount = |amount: Option<Decimal>| {
+            amount
+                .map(|a| commodity.format(a))
+                .unwrap_or_else(|| "-".into())
+        };
+        writeln!(
+            tw,
+            "{}\t{}\t{}\t{}\t{}",
+            name,
+            liability
+                .apr
+                .map(|apr| format!("{}%", apr.normalize()))
+                .unwrap_or_else(|| "-".into()),
+            amount(liability.minimum_payment),
+            liability
+                .next_due_date
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "-".into()),
+            amount(liability.last_statement_balance),
+        )?;
+    }
+
+    tw.flush()?;
+
+    Ok(())
+}
+
+/// Creates an account tracked without Plaid, e.g. cash, for transactions
+/// entered with `txn add`.
+async fn add_manual(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    // SAFETY: The name is a required positional argument.
+    let name = matches.value_of("name").unwrap();
+    let account = crate::core::Account {
+        id: ulid::Ulid::new().to_string(),
+        name: name.to_string(),
+        official_name: None,
+        ty: match matches.value_of("type") {
+            Some("credit") => "CREDIT_NORMAL",
+            _ => "DEBIT_NORMAL",
+        }
+        .into(),
+        alias: None,
+    };
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    store.accounts().save(LOCAL_ITEM_ID, &account).await?;
+    println!("Added account {} ({}).", account.name, account.id);
+
+    Ok(())
+}
+
+/// Sets the name an account is shown and exported under, or clears it to go
+/// back to the institution's name.
+async fn rename(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    // SAFETY: The account id is a required positional argument.
+    let account_id = matches.value_of("account_id").unwrap();
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let mut account = store
+        .accounts()
+        .by_id(account_id)
+        .await?
+        .ok_or_else(|| anyhow!("no account found with id {}", account_id))?;
+    account.alias = matches.value_of("name").map(str::to_string);
+    store.accounts().update(&account).await?;
+
+    match &account.alias {
+        Some(alias) => println!("Renamed {} to {}.", account.name, alias),
+        None => println!("Cleared the name of {}.", account.name),
+    }
+
+    Ok(())
+}
+
+fn commodity(account: &Account, default: &'static Currency) -> Commodity {
+    resolve_currency(
+        account.balances.iso_currency_code.as_deref(),
+        account.balances.unofficial_currency_code.as_deref(),
+        default,
+    )
+}
+
+fn write_balances<W: Write>(
+    tw: &mut W,
+    accounts: &[Account],
+    ty: AccountType,
+    default: &'static Currency,
+) -> Result<()> {
     writeln!(tw, "Name\tAvailable\tCurrent")?;
-    for account in accounts
-        .iter()
-        .filter(|account| account.r#type == AccountType::Credit)
-    {
-        let currency_code = account
-            .balances
-            .iso_currency_code
-            .as_deref()
-            .and_then(iso::find)
-            .unwrap_or(iso::USD);
+    for account in accounts.iter().filter(|account| account.r#type == ty) {
+        let commodity = commodity(account, default);
         writeln!(
             tw,
             "{}\t{}\t{}",
             account.name,
-            account
-                .balances
-                .available
-                .map(|amount| { Money::from_decimal(amount, currency_code) })
-                .as_ref()
-                .unwrap_or(&ZERO_DOLLARS),
-            account
-                .balances
-                .current
-                .map(|amount| { Money::from_decimal(amount, currency_code) })
-                .as_ref()
-                .unwrap_or(&ZERO_DOLLARS),
+            commodity.format(account.balances.available.unwrap_or_default()),
+            commodity.format(account.balances.current.unwrap_or_default()),
         )?;
     }
 
-    tw.flush()?;
-
     Ok(())
 }
 
 pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()> {
     match matches.subcommand() {
-        Some(("balances", _link_matches)) => balances(settings).await,
-        None => print(settings).await,
+        Some(("balances", balance_matches)) => {
+            if balance_matches.is_present("history") {
+                return balance_history(settings).await;
+            }
+
+            match balance_matches.value_of("as_of") {
+                Some(date) => balances_as_of(settings, date).await,
+                None => balances(settings).await,
+            }
+        }
+        Some(("net-worth", _)) => print_net_worth(settings).await,
+        Some(("holdings", _)) => holdings(settings).await,
+        Some(("liabilities", _)) => liabilities(settings).await,
+        Some(("add-manual", add_matches)) => add_manual(settings, add_matches).await,
+        Some(("rename", rename_matches)) => rename(settings, rename_matches).await,
+        None => {
+            let format = matches
+                .value_of("output")
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default();
+
+            print(settings, format).await
+        }
         _ => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rusty_money::iso;
+
+    use super::*;
+
+    fn account(ty: AccountType, current: i64, currency: &str) -> Account {
+        Account {
+            account_id: "account-id".into(),
+            name: "Test Account".into(),
+            r#type: ty,
+            official_name: None,
+            verification_status: None,
+            subtype: None,
+            mask: None,
+            balances: Balance {
+                available: None,
+                current: Some(Decimal::new(current, 0)),
+                iso_currency_code: Some(currency.into()),
+                limit: None,
+                unofficial_currency_code: None,
+            },
+        }
+    }
+
+    #[test]
+    fn net_worth_converts_to_base_currency() {
+        let mut table = HashMap::new();
+        table.insert("EUR_USD".to_string(), Decimal::new(150, 2));
+        let rates = Rates::new(&table).unwrap();
+
+        let accounts = vec![
+            account(AccountType::Depository, 100, "USD"),
+            account(AccountType::Investment, 10, "EUR"),
+            account(AccountType::Credit, 30, "USD"),
+            account(AccountType::Loan, 20, "USD"),
+        ];
+        let worth = net_worth(&accounts, &rates, iso::USD, iso::USD).unwrap();
+        assert_eq!(worth.assets, Money::from_major(115, iso::USD));
+        assert_eq!(worth.liabilities, Money::from_major(50, iso::USD));
+        assert_eq!(worth.net(), Money::from_major(65, iso::USD));
+    }
+
+    #[test]
+    fn net_worth_requires_rates() {
+        let accounts = vec![account(AccountType::Depository, 100, "GBP")];
+        assert!(net_worth(&accounts, &Rates::default(), iso::USD, iso::USD).is_err());
+    }
+}
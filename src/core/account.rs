@@ -1,10 +1,80 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use rplaid::model::{self, AccountType};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tracing::warn;
+
+/// Which of the names reported by the institution to show for an account.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountNameSource {
+    /// The name set by the user or institution, e.g. "Plaid Checking".
+    #[default]
+    Display,
+    /// The official product name, e.g. "Plaid Gold Standard 0% Interest
+    /// Checking", falling back to the display name when missing.
+    Official,
+}
 
 #[derive(Debug, Clone)]
 pub struct Account {
     pub id: String,
     pub name: String,
+    pub official_name: Option<String>,
     pub ty: String,
+    /// A name set with `account rename`, used in place of the institution's
+    /// names wherever the account is shown.
+    pub alias: Option<String>,
+}
+
+/// An account's balance as reported by the institution at a point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceSnapshot {
+    pub account_id: String,
+    pub taken_at: DateTime<Utc>,
+    pub available: Option<Decimal>,
+    pub current: Option<Decimal>,
+    pub currency: String,
+}
+
+/// A position in a security held by an investment account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holding {
+    pub account_id: String,
+    pub security_id: String,
+    pub name: Option<String>,
+    pub ticker: Option<String>,
+    pub quantity: Decimal,
+    /// The price of one unit as last reported by the institution.
+    pub price: Decimal,
+    pub value: Decimal,
+    pub currency: String,
+}
+
+/// Repayment details for a credit card or loan account, any detail the
+/// institution doesn't report is left unset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Liability {
+    pub account_id: String,
+    /// The annual percentage rate, e.g. 19.99 for 19.99%.
+    pub apr: Option<Decimal>,
+    pub minimum_payment: Option<Decimal>,
+    pub next_due_date: Option<NaiveDate>,
+    pub last_statement_balance: Option<Decimal>,
+    pub currency: String,
+}
+
+impl Account {
+    pub fn display_name(&self, source: AccountNameSource) -> &str {
+        if let Some(alias) = &self.alias {
+            return alias;
+        }
+
+        match (source, &self.official_name) {
+            (AccountNameSource::Official, Some(official)) if !official.is_empty() => official,
+            _ => &self.name,
+        }
+    }
 }
 
 impl From<model::Account> for Account {
@@ -14,13 +84,63 @@ impl From<model::Account> for Account {
             AccountType::Depository | AccountType::Investment | AccountType::Brokerage => {
                 "DEBIT_NORMAL"
             }
-            _ => unimplemented!(),
+            // Other accounts, e.g. prepaid or escrow, are more often assets
+            // than debts.
+            other => {
+                warn!(
+                    "Unsupported type {:?} for account {}, treating it as debit normal.",
+                    other, model.account_id
+                );
+
+                "DEBIT_NORMAL"
+            }
         };
 
         Self {
             id: model.account_id,
             name: model.name,
+            official_name: model.official_name,
             ty: ty.into(),
+            alias: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn official_name_falls_back_to_name() {
+        let mut account = Account {
+            id: "account-1".into(),
+            name: "Checking".into(),
+            official_name: Some("Gold Standard Checking".into()),
+            ty: "DEBIT_NORMAL".into(),
+            alias: None,
+        };
+
+        assert_eq!(account.display_name(AccountNameSource::Display), "Checking");
+        assert_eq!(
+            account.display_name(AccountNameSource::Official),
+            "Gold Standard Checking"
+        );
+
+        account.official_name = None;
+        assert_eq!(account.display_name(AccountNameSource::Official), "Checking");
+
+        account.alias = Some("Joint Checking".into());
+        assert_eq!(
+            account.display_name(AccountNameSource::Official),
+            "Joint Checking"
+        );
+    }
+
+    #[test]
+    fn other_accounts_are_debit_normal() {
+        let mut model = crate::plaid::mock::mock_account("account-1");
+        model.r#type = AccountType::Other;
+
+        assert_eq!(Account::from(model).ty, "DEBIT_NORMAL");
+    }
+}
@@ -1,5 +1,7 @@
 mod account;
+mod transfer;
 mod txn;
 
-pub use account::Account;
-pub use txn::{Status, Transaction};
+pub use account::{Account, AccountNameSource, BalanceSnapshot, Holding, Liability};
+pub use transfer::{detect_transfers, Transfer, MAX_DAYS_APART};
+pub use txn::{Posting, Status, Transaction, UnknownStatus};
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+
+use ulid::Ulid;
+
+use super::{Posting, Status, Transaction};
+
+/// The most days apart the two sides of a transfer are expected to post,
+/// covering transfers that clear over a weekend.
+pub const MAX_DAYS_APART: i64 = 3;
+
+/// Money moved between two tracked accounts, reported upstream as two
+/// separate transactions.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    /// The transaction the money left from, kept as the joined transaction.
+    pub outflow: Ulid,
+    /// The transaction the money arrived in, superseded by the joined one.
+    pub inflow: Ulid,
+    /// The outflow with both accounts' postings, replacing the
+    /// uncategorized counter postings of either side.
+    pub joined: Transaction,
+}
+
+/// Pairs transactions moving the same amount out of one tracked account and
+/// into another within [`MAX_DAYS_APART`] days. Only resolved transactions
+/// posting to a single account in `accounts` are considered, each is used in
+/// at most one transfer and is paired with the closest match by date.
+pub fn detect_transfers(txns: &[Transaction], accounts: &HashSet<&str>) -> Vec<Transfer> {
+    let candidates: Vec<(&Transaction, &Posting)> = txns
+        .iter()
+        .filter(|txn| matches!(txn.status, Status::Resolved))
+        .filter_map(|txn| {
+            let mut tracked = txn
+                .postings
+                .iter()
+                .filter(|p| accounts.contains(p.account.as_str()));
+            match (tracked.next(), tracked.next()) {
+                (Some(posting), None) => Some((txn, posting)),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let mut used = HashSet::new();
+    let mut transfers = vec![];
+    for (outflow, out) in &candidates {
+        if !out.amount.is_sign_negative() || used.contains(&outflow.id) {
+            continue;
+        }
+
+        let inflow = candidates
+            .iter()
+            .filter(|(txn, posting)| {
+                !used.contains(&txn.id)
+                    && posting.account != out.account
+                    && posting.currency == out.currency
+                    && posting.amount == -out.amount
+            })
+            .map(|(txn, posting)| (txn, posting, (txn.date - outflow.date).num_days().abs()))
+            .filter(|(_, _, days)| *days <= MAX_DAYS_APART)
+            .min_by_key(|(_, _, days)| *days);
+
+        if let Some((inflow, into, _)) = inflow {
+            used.insert(outflow.id);
+            used.insert(inflow.id);
+
+            let mut joined = (*outflow).clone();
+            joined.postings = vec![(*out).clone(), (*into).clone()];
+            transfers.push(Transfer {
+                outflow: outflow.id,
+                inflow: inflow.id,
+                joined,
+            });
+        }
+    }
+
+    transfers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn txn(account: &str, amount: i64, day: u32) -> Transaction {
+        let posting = |account: &str, amount: i64| Posting {
+            account: account.into(),
+            amount: Decimal::new(amount, 0),
+            currency: "USD".into(),
+        };
+        let counter = if amount < 0 {
+            "Expenses:Uncategorized"
+        } else {
+            "Income:Uncategorized"
+        };
+
+        Transaction {
+            id: Ulid::new(),
+            status: Status::Resolved,
+            date: NaiveDate::from_ymd_opt(2022, 5, day).unwrap(),
+            payee: None,
+            narration: "Transfer".into(),
+            postings: vec![posting(account, amount), posting(counter, -amount)],
+            tags: vec![],
+            meta: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn joins_opposite_amounts_across_accounts() {
+        let accounts = HashSet::from(["checking", "savings"]);
+        let out = txn("checking", -100, 2);
+        let far = txn("savings", 100, 10);
+        let near = txn("savings", 100, 3);
+        let same_account = txn("checking", 100, 2);
+
+        let transfers =
+            detect_transfers(&[out.clone(), far, near.clone(), same_account], &accounts);
+
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].outflow, out.id);
+        assert_eq!(transfers[0].inflow, near.id);
+        assert_eq!(
+            transfers[0].joined.postings,
+            vec![out.postings[0].clone(), near.postings[0].clone()]
+        );
+    }
+
+    #[test]
+    fn ignores_untracked_and_pending_transactions() {
+        let accounts = HashSet::from(["checking"]);
+        let mut pending = txn("checking", -100, 2);
+        pending.status = Status::Pending;
+
+        assert!(detect_transfers(
+            &[txn("checking", -50, 2), txn("brokerage", 50, 2)],
+            &accounts
+        )
+        .is_empty());
+        assert!(detect_transfers(
+            &[pending, txn("savings", 100, 2)],
+            &HashSet::from(["checking", "savings"])
+        )
+        .is_empty());
+    }
+}
@@ -1,7 +1,12 @@
+use std::collections::BTreeMap;
+
 use chrono::naive::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
 use ulid::Ulid;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Status {
     Resolved,
     Pending,
@@ -17,22 +22,40 @@ impl ToString for Status {
     }
 }
 
-impl From<String> for Status {
-    fn from(value: String) -> Status {
+/// A status string read from storage that isn't one of [`Status`]'s values.
+#[derive(Debug, thiserror::Error)]
+#[error("unexpected transaction status {0:?}")]
+pub struct UnknownStatus(pub String);
+
+impl TryFrom<String> for Status {
+    type Error = UnknownStatus;
+
+    fn try_from(value: String) -> Result<Status, UnknownStatus> {
         match value.as_str() {
-            "RESOLVED" => Status::Resolved,
-            "PENDING" => Status::Pending,
-            // TODO(allancalix): change to try_from instead?
-            _ => unreachable!("unexpected status value"),
+            "RESOLVED" => Ok(Status::Resolved),
+            "PENDING" => Ok(Status::Pending),
+            _ => Err(UnknownStatus(value)),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Transaction {
     pub id: Ulid,
     pub status: Status,
     pub date: NaiveDate,
     pub payee: Option<String>,
     pub narration: String,
+    pub postings: Vec<Posting>,
+    /// Written as Ledger tags, e.g. `; :travel:work:`.
+    pub tags: Vec<String>,
+    /// Written as Ledger metadata, e.g. `; Trip: Berlin`.
+    pub meta: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Posting {
+    pub account: String,
+    pub amount: Decimal,
+    pub currency: String,
 }
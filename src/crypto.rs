@@ -0,0 +1,61 @@
+use std::io::{Read, Write};
+
+use age::secrecy::Secret;
+use anyhow::{anyhow, Result};
+
+/// Environment variable consulted for the passphrase before prompting.
+const PASSPHRASE_ENV: &str = "CLERK_PASSPHRASE";
+
+/// Reads the passphrase used to protect exported secrets from the environment,
+/// prompting on the terminal when it isn't set.
+pub fn passphrase() -> Result<String> {
+    match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => Ok(rpassword::prompt_password("Passphrase: ")?),
+    }
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase` using the age
+/// format, the output can be decrypted with `age -d` as well.
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        return Err(anyhow!("passphrase must not be empty"));
+    }
+
+    let encryptor = age::Encryptor::with_user_passphrase(Secret::new(passphrase.to_string()));
+
+    let mut encrypted = vec![];
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+
+    Ok(encrypted)
+}
+
+pub fn decrypt(encrypted: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let decryptor = match age::Decryptor::new(encrypted)? {
+        age::Decryptor::Passphrase(decryptor) => decryptor,
+        _ => return Err(anyhow!("file was not encrypted with a passphrase")),
+    };
+
+    let mut decrypted = vec![];
+    decryptor
+        .decrypt(&Secret::new(passphrase.to_string()), None)?
+        .read_to_end(&mut decrypted)?;
+
+    Ok(decrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_with_passphrase() {
+        let encrypted = encrypt(b"access-token", "correct horse").unwrap();
+
+        assert_ne!(encrypted.as_slice(), b"access-token");
+        assert_eq!(decrypt(&encrypted, "correct horse").unwrap(), b"access-token");
+        assert!(decrypt(&encrypted, "battery staple").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rusty_money::{
+    iso::{self, Currency},
+    Money,
+};
+use tracing::warn;
+
+/// The currency an amount is denominated in. Plaid reports ISO currencies
+/// through `iso_currency_code` and anything else, e.g. crypto, through
+/// `unofficial_currency_code`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Commodity {
+    Iso(&'static Currency),
+    Unofficial(String),
+}
+
+impl Commodity {
+    pub fn code(&self) -> &str {
+        match self {
+            Commodity::Iso(currency) => currency.iso_alpha_code,
+            Commodity::Unofficial(code) => code,
+        }
+    }
+
+    pub fn format(&self, amount: Decimal) -> String {
+        match self {
+            Commodity::Iso(currency) => Money::from_decimal(amount, *currency).to_string(),
+            Commodity::Unofficial(code) => format!("{} {}", amount, code),
+        }
+    }
+}
+
+/// Resolves the commodity for an amount, preferring the ISO code, then the
+/// unofficial code, and finally `default` when Plaid reports neither.
+pub fn resolve_currency(
+    iso_code: Option<&str>,
+    unofficial_code: Option<&str>,
+    default: &'static Currency,
+) -> Commodity {
+    match iso_code.or(unofficial_code) {
+        Some(code) => match iso::find(code) {
+            Some(currency) => Commodity::Iso(currency),
+            None => Commodity::Unofficial(code.to_uppercase()),
+        },
+        None => {
+            warn!(
+                "No currency reported, defaulting to {}.",
+                default.iso_alpha_code
+            );
+
+            Commodity::Iso(default)
+        }
+    }
+}
+
+pub fn find_currency(code: &str) -> Result<&'static Currency> {
+    iso::find(code).ok_or_else(|| anyhow!("unknown currency {}", code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_iso_codes() {
+        assert_eq!(
+            resolve_currency(Some("EUR"), None, iso::USD),
+            Commodity::Iso(iso::EUR)
+        );
+        assert_eq!(
+            resolve_currency(Some("EUR"), Some("BTC"), iso::USD),
+            Commodity::Iso(iso::EUR)
+        );
+    }
+
+    #[test]
+    fn resolves_crypto_codes() {
+        let commodity = resolve_currency(None, Some("btc"), iso::USD);
+
+        assert_eq!(commodity, Commodity::Unofficial("BTC".to_string()));
+        assert_eq!(commodity.format(Decimal::new(15, 1)), "1.5 BTC");
+    }
+
+    #[test]
+    fn missing_codes_use_default() {
+        assert_eq!(
+            resolve_currency(None, None, iso::CAD),
+            Commodity::Iso(iso::CAD)
+        );
+    }
+}
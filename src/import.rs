@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+
+use crate::core::Account;
+use crate::settings::Settings;
+use crate::store::{link::LOCAL_ITEM_ID, SqliteStore};
+use crate::upstream::ofx::{self, Statement};
+use crate::upstream::plaid::SyncOptions;
+use crate::upstream::{TransactionEvent, TransactionSource};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ImportReport {
+    account_id: String,
+    account_created: bool,
+    added: usize,
+    /// Transactions already imported, refreshed rather than duplicated.
+    refetched: usize,
+}
+
+/// Imports a statement's transactions into `account_id`, or when unset into
+/// a local account named after the statement's account number, created on
+/// the first import.
+async fn import_statement(
+    settings: &Settings,
+    store: &mut SqliteStore,
+    statement: Statement,
+    account_id: Option<&str>,
+    name: Option<&str>,
+) -> Result<ImportReport> {
+    let mut account_created = false;
+    let account_id = match account_id {
+        Some(id) => {
+            store
+                .accounts()
+                .by_id(id)
+                .await?
+                .ok_or_else(|| anyhow!("no account found with id {}", id))?;
+
+            id.to_string()
+        }
+        None => {
+            let number = statement
+                .account_id
+                .as_deref()
+                .ok_or_else(|| anyhow!("the statement has no <ACCTID>, pass --account"))?;
+            let id = format!("ofx-{}", number);
+            if store.accounts().by_id(&id).await?.is_none() {
+                let account = Account {
+                    id: id.clone(),
+                    name: name
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("OFX {}", number)),
+                    official_name: None,
+                    ty: if statement.credit_card {
+                        "CREDIT_NORMAL"
+                    } else {
+                        "DEBIT_NORMAL"
+                    }
+                    .into(),
+                    alias: None,
+                };
+                store.accounts().save(LOCAL_ITEM_ID, &account).await?;
+                account_created = true;
+            }
+
+            id
+        }
+    };
+    let mut report = ImportReport {
+        account_id,
+        account_created,
+        ..ImportReport::default()
+    };
+
+    let mut source = ofx::Source::new(statement, &report.account_id, SyncOptions::from(settings));
+    for event in source.transactions().await? {
+        let entry = match event {
+            TransactionEvent::Added(entry) => entry,
+            _ => continue,
+        };
+
+        // FITIDs identify a transaction across statements, the same way Plaid
+        // transaction ids do, so overlapping statements don't duplicate.
+        let upstream_id = source.upstream_id(&entry.source);
+        match store.txns().by_upstream_id(&upstream_id).await? {
+            Some(id) => {
+                store.txns().update(&id, &entry).await?;
+                report.refetched += 1;
+            }
+            None => {
+                store
+                    .txns()
+                    .save(LOCAL_ITEM_ID, &upstream_id, &report.account_id, &entry)
+                    .await?;
+                report.added += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+async fn import_ofx(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    // SAFETY: The file is a required positional argument.
+    let path = matches.value_of("file").unwrap();
+    let input = std::fs::read(path).map_err(|e| anyhow!("failed to read {}: {}", path, e))?;
+    // OFX 1.x files are often Latin-1, anything outside ASCII in them is
+    // only ever names and memos.
+    let statement = ofx::parse(&String::from_utf8_lossy(&input))?;
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let report = import_statement(
+        &settings,
+        &mut store,
+        statement,
+        matches.value_of("account"),
+        matches.value_of("name"),
+    )
+    .await?;
+
+    if report.account_created {
+        println!("Created local account {}.", report.account_id);
+    }
+    println!(
+        "Imported {} into {}: added={} refetched={}",
+        path, report.account_id, report.added, report.refetched
+    );
+
+    Ok(())
+}
+
+pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()> {
+    match matches.subcommand() {
+        Some(("ofx", ofx_matches)) => import_ofx(settings, ofx_matches).await,
+        None => unreachable!("subcommand is required"),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Transaction;
+    use crate::settings::test_settings;
+    use crate::store::test_support::TestStore;
+    use crate::store::TransactionFilter;
+
+    const STATEMENT: &str = "<OFX><CREDITCARDMSGSRSV1><CCSTMTRS><CURDEF>USD
+<CCACCTFROM><ACCTID>4111</CCACCTFROM>
+<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20230105<TRNAMT>-5.00<FITID>A<NAME>Coffee</STMTTRN>
+<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20230106<TRNAMT>-7.00<FITID>B<NAME>Lunch</STMTTRN>
+</CCSTMTRS></CREDITCARDMSGSRSV1></OFX>";
+
+    async fn stored(store: &mut TestStore) -> Vec<Transaction> {
+        store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reimporting_dedupes_on_fitid() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+
+        let first = import_statement(
+            &settings,
+            store.db(),
+            ofx::parse(STATEMENT).unwrap(),
+            None,
+            Some("Visa"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            first,
+            ImportReport {
+                account_id: "ofx-4111".into(),
+                account_created: true,
+                added: 2,
+                refetched: 0,
+            }
+        );
+        let account = store
+            .db()
+            .accounts()
+            .by_id("ofx-4111")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.name, "Visa");
+        assert_eq!(account.ty, "CREDIT_NORMAL");
+
+        let second = import_statement(
+            &settings,
+            store.db(),
+            ofx::parse(STATEMENT).unwrap(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(!second.account_created);
+        assert_eq!((second.added, second.refetched), (0, 2));
+        assert_eq!(stored(&mut store).await.len(), 2);
+        // The placeholder link owning local accounts isn't a Plaid link.
+        assert!(store.db().links().list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn imports_into_existing_accounts() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let account = store.new_account(&link.item_id).await;
+
+        let report = import_statement(
+            &settings,
+            store.db(),
+            ofx::parse(STATEMENT).unwrap(),
+            Some(&account.id),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.added, 2);
+        assert!(stored(&mut store)
+            .await
+            .iter()
+            .all(|txn| txn.postings[0].account == account.id));
+
+        assert!(import_statement(
+            &settings,
+            store.db(),
+            ofx::parse(STATEMENT).unwrap(),
+            Some("missing"),
+            None,
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::core::{Account, Status, Transaction};
+
+/// Periods transactions can be grouped under when writing a journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Month,
+    Year,
+}
+
+impl GroupBy {
+    fn period(&self, txn: &Transaction) -> String {
+        match self {
+            GroupBy::Month => txn.date.format("%Y-%m").to_string(),
+            GroupBy::Year => txn.date.format("%Y").to_string(),
+        }
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "month" => Ok(GroupBy::Month),
+            "year" => Ok(GroupBy::Year),
+            _ => Err(anyhow!("unknown period {}, expected month or year", value)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Writes a comment header before the transactions of each period.
+    pub group_by: Option<GroupBy>,
+    /// Writes a comment noting the clerk version and the given generation time
+    /// at the top of the journal.
+    pub generated_at: Option<DateTime<Utc>>,
+    /// Directives written verbatim before any transactions, e.g. `account` or
+    /// `commodity` declarations.
+    pub preamble: Option<String>,
+}
+
+/// Writes `txns` as Ledger journal entries. Postings against tracked accounts
+/// are named after the account, any other posting account is assumed to
+/// already be a Ledger account name. Transactions are expected to be sorted
+/// by date.
+pub fn write_transactions<W: Write>(
+    mut wr: W,
+    txns: &[Transaction],
+    accounts: &[Account],
+    opts: &Options,
+) -> Result<()> {
+    let accounts: HashMap<&str, &Account> = accounts.iter().map(|a| (a.id.as_str(), a)).collect();
+
+    write_preamble(&mut wr, opts)?;
+
+    let mut current_period = None;
+    for (i, txn) in txns.iter().enumerate() {
+        if i > 0 {
+            writeln!(wr)?;
+        }
+
+        if let Some(group_by) = &opts.group_by {
+            let period = group_by.period(txn);
+            if current_period.as_ref() != Some(&period) {
+                writeln!(wr, "; === {} ===", period)?;
+                current_period = Some(period);
+            }
+        }
+
+        write_transaction(&mut wr, txn, &accounts)?;
+    }
+
+    wr.flush()?;
+
+    Ok(())
+}
+
+fn write_preamble<W: Write>(wr: &mut W, opts: &Options) -> Result<()> {
+    let mut written = false;
+    if let Some(generated_at) = &opts.generated_at {
+        writeln!(
+            wr,
+            "; Generated by clerk {} on {}",
+            env!("CARGO_PKG_VERSION"),
+            generated_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )?;
+        written = true;
+    }
+
+    if let Some(preamble) = opts.preamble.as_deref().map(str::trim_end) {
+        if !preamble.is_empty() {
+            writeln!(wr, "{}", preamble)?;
+            written = true;
+        }
+    }
+
+    if written {
+        writeln!(wr)?;
+    }
+
+    Ok(())
+}
+
+fn write_transaction<W: Write>(
+    wr: &mut W,
+    txn: &Transaction,
+    accounts: &HashMap<&str, &Account>,
+) -> Result<()> {
+    let flag = match txn.status {
+        Status::Resolved => '*',
+        Status::Pending => '!',
+    };
+    writeln!(
+        wr,
+        "{} {} {}",
+        txn.date.format("%Y-%m-%d"),
+        flag,
+        sanitize(&txn.narration)
+    )?;
+
+    if let Some(payee) = &txn.payee {
+        writeln!(wr, "    ; Payee: {}", sanitize(payee))?;
+    }
+
+    if !txn.tags.is_empty() {
+        let tags: Vec<String> = txn.tags.iter().map(|tag| tag_name(tag)).collect();
+        writeln!(wr, "    ; :{}:", tags.join(":"))?;
+    }
+
+    for (key, value) in &txn.meta {
+        writeln!(wr, "    ; {}: {}", tag_name(key), sanitize(value))?;
+    }
+
+    for posting in &txn.postings {
+        writeln!(
+            wr,
+            "    {}  {} {}",
+            account_name(&posting.account, accounts),
+            posting.amount,
+            posting.currency
+        )?;
+    }
+
+    Ok(())
+}
+
+fn account_name(account: &str, accounts: &HashMap<&str, &Account>) -> String {
+    match accounts.get(account) {
+        Some(acc) => {
+            let root = match acc.ty.as_str() {
+                "CREDIT_NORMAL" => "Liabilities",
+                _ => "Assets",
+            };
+
+            let name = acc.alias.as_deref().unwrap_or(&acc.name);
+            format!("{}:{}", root, sanitize(name).replace(':', "-"))
+        }
+        None => account.to_string(),
+    }
+}
+
+// Tags and metadata keys end at whitespace or a colon.
+fn tag_name(value: &str) -> String {
+    sanitize(value).replace([' ', ':'], "-")
+}
+
+// Ledger ends an account name at the first run of two spaces, collapse any
+// whitespace so names and descriptions can't break the entry.
+fn sanitize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{NaiveDate, TimeZone};
+    use rust_decimal::Decimal;
+    use ulid::Ulid;
+
+    use super::*;
+    use crate::core::Posting;
+
+    #[test]
+    fn writes_ledger_entries() {
+        let accounts = vec![Account {
+            id: "account-1".into(),
+            name: "Plaid  Credit: Card".into(),
+            official_name: None,
+            ty: "CREDIT_NORMAL".into(),
+            alias: None,
+        }];
+        let txns = vec![
+            Transaction {
+                id: Ulid::new(),
+                status: Status::Resolved,
+                date: NaiveDate::from_ymd_opt(2022, 5, 1).unwrap(),
+                payee: Some("Starbucks".into()),
+                narration: "STARBUCKS   STORE 123".into(),
+                postings: vec![
+                    Posting {
+                        account: "account-1".into(),
+                        amount: Decimal::new(-450, 2),
+                        currency: "USD".into(),
+                    },
+                    Posting {
+                        account: "Expenses:Coffee".into(),
+                        amount: Decimal::new(450, 2),
+                        currency: "USD".into(),
+                    },
+                ],
+                tags: vec!["coffee".into(), "work trip".into()],
+                meta: BTreeMap::from([("Trip".to_string(), "Berlin".to_string())]),
+            },
+            Transaction {
+                id: Ulid::new(),
+                status: Status::Pending,
+                date: NaiveDate::from_ymd_opt(2022, 5, 2).unwrap(),
+                payee: None,
+                narration: "Pending".into(),
+                postings: vec![],
+                tags: vec![],
+                meta: BTreeMap::new(),
+            },
+        ];
+
+        let mut out = vec![];
+        write_transactions(&mut out, &txns, &accounts, &Options::default()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2022-05-01 * STARBUCKS STORE 123\n    \
+             ; Payee: Starbucks\n    \
+             ; :coffee:work-trip:\n    \
+             ; Trip: Berlin\n    \
+             Liabilities:Plaid Credit- Card  -4.50 USD\n    \
+             Expenses:Coffee  4.50 USD\n\
+             \n\
+             2022-05-02 ! Pending\n"
+        );
+    }
+
+    #[test]
+    fn aliases_name_accounts() {
+        let account = Account {
+            id: "account-1".into(),
+            name: "Plaid Checking".into(),
+            official_name: None,
+            ty: "DEBIT_NORMAL".into(),
+            alias: Some("Joint: Checking".into()),
+        };
+        let accounts = HashMap::from([("account-1", &account)]);
+
+        assert_eq!(
+            account_name("account-1", &accounts),
+            "Assets:Joint- Checking"
+        );
+    }
+
+    #[test]
+    fn groups_transactions_by_period() {
+        let txn = |y, m, d| Transaction {
+            id: Ulid::new(),
+            status: Status::Resolved,
+            date: NaiveDate::from_ymd_opt(y, m, d).unwrap(),
+            payee: None,
+            narration: "Coffee".into(),
+            postings: vec![],
+            tags: vec![],
+            meta: BTreeMap::new(),
+        };
+        let txns = vec![txn(2022, 1, 5), txn(2022, 1, 20), txn(2022, 3, 1)];
+
+        let mut out = vec![];
+        let opts = Options {
+            group_by: Some(GroupBy::Month),
+            ..Options::default()
+        };
+        write_transactions(&mut out, &txns, &[], &opts).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "; === 2022-01 ===\n\
+             2022-01-05 * Coffee\n\
+             \n\
+             2022-01-20 * Coffee\n\
+             \n\
+             ; === 2022-03 ===\n\
+             2022-03-01 * Coffee\n"
+        );
+    }
+
+    #[test]
+    fn writes_header_and_preamble() {
+        let opts = Options {
+            generated_at: Some(Utc.with_ymd_and_hms(2022, 12, 1, 8, 30, 0).unwrap()),
+            preamble: Some("account Assets:Checking\n".into()),
+            ..Options::default()
+        };
+
+        let mut out = vec![];
+        write_transactions(&mut out, &[], &[], &opts).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "; Generated by clerk {} on 2022-12-01T08:30:00Z\n\
+                 account Assets:Checking\n\
+                 \n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+}
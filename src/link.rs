@@ -1,17 +1,43 @@
 use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 use crossbeam_channel::{bounded, Receiver};
-use plaid_link::{LinkMode, State};
+use plaid_link::{LinkMode, State, StateSigning};
+use qrcode::{render::unicode::Dense1x2, QrCode};
+use rplaid::client::{Environment, Plaid};
+use rplaid::HttpClient;
+use serde::{Deserialize, Serialize};
 use tokio::signal;
 use tokio::time::{sleep_until, Duration, Instant};
 
-use crate::plaid::{default_plaid_client, Link, LinkController, LinkStatus};
+use crate::crypto;
+use crate::plaid::{default_plaid_client, Link, LinkController, LinkStatus, OutputFormat};
 use crate::settings::Settings;
 use crate::store;
+use crate::upstream::plaid::PlaidError;
 
 const LINK_NAME_KEY: &str = "link_name";
+// Any routable address, only used to pick the outbound interface.
+const DISCOVERY_HOST: &str = "192.0.2.1";
+
+/// The portable representation of a link written by `link export`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedLink {
+    item_id: String,
+    alias: String,
+    institution_id: Option<String>,
+    access_token: String,
+    products: Vec<String>,
+}
+
+struct LinkOptions<'a> {
+    name: &'a str,
+    print_url_only: bool,
+    open: bool,
+    qr: bool,
+}
 
 async fn shutdown_signal(rx: Receiver<()>) {
     let ctrl_c = async {
@@ -51,11 +77,28 @@ async fn shutdown_signal(rx: Receiver<()>) {
     println!("signal received, starting graceful shutdown");
 }
 
-async fn server(settings: Settings, mode: plaid_link::LinkMode, name: &str) -> Result<()> {
-    let plaid = default_plaid_client(&settings.plaid);
+async fn server(
+    settings: Settings,
+    mode: plaid_link::LinkMode,
+    opts: LinkOptions<'_>,
+) -> Result<()> {
+    let name = opts.name;
+    plaid_link::validate_country_codes(&settings.plaid.country_codes)?;
+    let plaid = default_plaid_client(&settings.plaid)?;
 
     let (tx, rx) = bounded(1);
-    let server = plaid_link::LinkServer::new(plaid);
+    let signing = match (
+        &settings.plaid.state_secret,
+        settings.plaid.allow_unsigned_state,
+    ) {
+        (_, true) => StateSigning::Unsigned,
+        (Some(secret), false) => StateSigning::Hmac(secret.as_bytes().to_vec()),
+        (None, false) => StateSigning::random(),
+    };
+    let server = plaid_link::LinkServer::new(plaid)
+        .with_client_name(&settings.plaid.client_name)?
+        .with_products(&settings.plaid.products)?
+        .with_state_signing(signing);
 
     let mut listener = server.on_exchange();
     let mut store = store::SqliteStore::new(&settings.db_file).await?;
@@ -64,10 +107,23 @@ async fn server(settings: Settings, mode: plaid_link::LinkMode, name: &str) -> R
         plaid_link::LinkMode::Create => None,
     };
 
+    if !name.is_empty() {
+        if let Some(existing) = store.links().by_alias(name).await? {
+            if !matches!(&mode, LinkMode::Update(id) if id == &existing.item_id) {
+                return Err(store::Error::DuplicateAlias {
+                    alias: name.to_string(),
+                    item_id: existing.item_id,
+                }
+                .into());
+            }
+        }
+    }
+
     let mode = std::sync::Arc::new(mode);
     let m = mode.clone();
     let settings = std::sync::Arc::new(settings);
     let settings_capture = settings.clone();
+    let plaid = default_plaid_client(&settings.plaid)?;
     tokio::spawn(async move {
         let token = listener.recv().await.unwrap();
         let name = match token.state.context {
@@ -75,7 +131,6 @@ async fn server(settings: Settings, mode: plaid_link::LinkMode, name: &str) -> R
             None => "".to_string(),
         };
 
-        let plaid = default_plaid_client(&settings_capture.plaid);
         match m.as_ref() {
             plaid_link::LinkMode::Update(_) => {
                 let link = plaid.item(&token.access_token).await.unwrap();
@@ -88,63 +143,107 @@ async fn server(settings: Settings, mode: plaid_link::LinkMode, name: &str) -> R
                         state: LinkStatus::Active,
                         sync_cursor: None,
                         institution_id: link.institution_id,
+                        products: link.billed_products,
                     })
                     .await
                     .unwrap();
             }
             _ => {
                 let link = plaid.item(&token.access_token).await.unwrap();
-                store
-                    .links()
-                    .save(&Link {
-                        alias: name,
-                        access_token: token.access_token.clone(),
-                        item_id: token.item_id.clone(),
-                        state: LinkStatus::Active,
-                        sync_cursor: None,
-                        institution_id: link.institution_id,
-                    })
-                    .await
-                    .unwrap();
+                match store.links().by_id(&token.item_id).await.unwrap() {
+                    // Plaid returned an item we already track, refresh its
+                    // credentials instead of failing on the duplicate item.
+                    Some(existing) => {
+                        println!(
+                            "This institution is already linked as \"{}\", refreshing its access token.",
+                            existing.alias
+                        );
 
-                LinkController::initialize(plaid, &settings_capture.plaid, store)
-                    .await
-                    .unwrap();
+                        store
+                            .links()
+                            .update(&Link {
+                                access_token: token.access_token,
+                                state: LinkStatus::Active,
+                                institution_id: link.institution_id.or(existing.institution_id),
+                                products: link.billed_products,
+                                ..existing
+                            })
+                            .await
+                            .unwrap();
+                    }
+                    None => {
+                        let link = Link {
+                            alias: name,
+                            access_token: token.access_token.clone(),
+                            item_id: token.item_id.clone(),
+                            state: LinkStatus::Active,
+                            sync_cursor: None,
+                            institution_id: link.institution_id,
+                            products: link.billed_products,
+                        };
+                        store.links().save(&link).await.unwrap();
+
+                        // Surface a product mismatch now, while the user can
+                        // still pick another institution or account.
+                        if !link.supports_transactions() {
+                            println!(
+                                "Warning: transactions are not enabled for this link (products: {}), `clerk txn sync` will skip it.",
+                                link.products.join(", ")
+                            );
+                        }
+
+                        LinkController::initialize(plaid, &settings_capture.plaid, store)
+                            .await
+                            .unwrap();
+                    }
+                }
             }
         }
 
         tx.send(()).unwrap();
     });
 
-    let router = server.start();
-    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 0));
-    let server = axum::Server::bind(&addr).serve(router.into_make_service());
-
     let mut context = HashMap::new();
     context.insert(LINK_NAME_KEY.to_string(), name.to_string());
 
-    let state = State {
+    let state = server.opaque_state(State {
         country_codes: settings.plaid.country_codes.clone(),
         user_id: "test-user".to_string(),
         context: Some(context),
-    };
-    match mode.as_ref() {
-        LinkMode::Create => println!(
-            "Visit http://{}/link?state={} to link a new account.",
-            server.local_addr(),
-            state.to_opaque()?
+    })?;
+
+    let router = server.start();
+    let addr = link_addr(&settings.link_addr)?;
+    let server = axum::Server::try_bind(&addr)
+        .map_err(|e| anyhow!("failed to listen on {}: {}", addr, e))?
+        .serve(router.into_make_service());
+    let host = reachable_addr(server.local_addr());
+
+    let url = match mode.as_ref() {
+        LinkMode::Create => format!("http://{}/link?state={}", host, state),
+        LinkMode::Update(_) => format!(
+            "http://{}/link?mode=update&token={}&state={}",
+            host,
+            link.expect("must have existing link when using update")
+                .access_token,
+            state
         ),
-        LinkMode::Update(_) => {
-            println!(
-                "Visit http://{}/link?mode=update&token={}&state={} to link a new account.",
-                server.local_addr(),
-                link.expect("must have existing link when using update")
-                    .access_token,
-                state.to_opaque()?
-            )
-        }
     };
 
+    if opts.print_url_only {
+        println!("{}", url);
+    } else {
+        println!("Visit {} to link a new account.", url);
+    }
+
+    if opts.qr {
+        print_qr(&url, server.local_addr())?;
+    }
+
+    if opts.open {
+        open::that(&url)?;
+    }
+
     server
         .with_graceful_shutdown(shutdown_signal(rx))
         .await
@@ -153,9 +252,58 @@ async fn server(settings: Settings, mode: plaid_link::LinkMode, name: &str) -> R
     Ok(())
 }
 
+fn link_addr(value: &str) -> Result<SocketAddr> {
+    value.parse().map_err(|e| {
+        anyhow!(
+            "invalid link_addr \"{}\", expected an address like 127.0.0.1:8080: {}",
+            value,
+            e
+        )
+    })
+}
+
+/// The address other machines reach the server on. A server listening on all
+/// interfaces is reported under the address of the interface used for
+/// outbound traffic, nothing is sent to find it.
+fn reachable_addr(addr: SocketAddr) -> SocketAddr {
+    if !addr.ip().is_unspecified() {
+        return addr;
+    }
+
+    std::net::UdpSocket::bind((addr.ip(), 0))
+        .and_then(|socket| {
+            socket.connect((DISCOVERY_HOST, 80))?;
+            socket.local_addr()
+        })
+        .map(|local| SocketAddr::new(local.ip(), addr.port()))
+        .unwrap_or_else(|_| SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port()))
+}
+
+/// Renders `url` as a QR code for finishing the link flow from another
+/// device.
+fn print_qr(url: &str, addr: SocketAddr) -> Result<()> {
+    let code = QrCode::new(url.as_bytes())?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!("{}", code);
+
+    if addr.ip().is_loopback() {
+        println!(
+            "The link server only accepts connections from this machine, forward port {} to it (e.g. `ssh -L {}:localhost:{}`) before scanning.",
+            addr.port(),
+            addr.port(),
+            addr.port()
+        );
+    }
+
+    Ok(())
+}
+
 async fn remove(settings: Settings, item_id: &str) -> Result<()> {
     let mut store = store::SqliteStore::new(&settings.db_file).await?;
-    let plaid = default_plaid_client(&settings.plaid);
+    let plaid = default_plaid_client(&settings.plaid)?;
 
     let link = store.links().link(item_id).await?;
     plaid.item_del(&link.access_token).await?;
@@ -164,20 +312,240 @@ async fn remove(settings: Settings, item_id: &str) -> Result<()> {
     Ok(())
 }
 
-async fn status(settings: Settings) -> Result<()> {
+/// What came of asking Plaid to refresh a link's transactions.
+#[derive(Debug, PartialEq, Eq)]
+enum Refresh {
+    /// Plaid accepted the refresh, new transactions show up in a later sync.
+    Requested { request_id: String },
+    /// The institution needs the user to log in again before any data can be
+    /// fetched.
+    LoginRequired(String),
+}
+
+async fn refresh_link<T: HttpClient>(
+    plaid: &Plaid<T>,
+    store: &mut store::SqliteStore,
+    item_id: &str,
+) -> Result<Refresh> {
+    let mut link = store.links().link(item_id).await?;
+    if let LinkStatus::Degraded(reason) = &link.state {
+        return Ok(Refresh::LoginRequired(reason.clone()));
+    }
+
+    match plaid.transactions_refresh(&link.access_token).await {
+        Ok(res) => Ok(Refresh::Requested {
+            request_id: res.request_id,
+        }),
+        Err(err) => match PlaidError::from(err) {
+            PlaidError::Api {
+                error_code,
+                message,
+                ..
+            } if error_code == "ITEM_LOGIN_REQUIRED" => {
+                link.state = LinkStatus::Degraded(message.clone());
+                store.links().update(&link).await?;
+
+                Ok(Refresh::LoginRequired(message))
+            }
+            err => Err(err.into()),
+        },
+    }
+}
+
+async fn refresh(settings: Settings, item_id: &str) -> Result<()> {
+    let mut store = store::SqliteStore::new(&settings.db_file).await?;
+    let plaid = default_plaid_client(&settings.plaid)?;
+
+    match refresh_link(&plaid, &mut store, item_id).await? {
+        Refresh::Requested { request_id } => println!(
+            "Requested a refresh of {} (request_id={}), new transactions are pulled by the next `clerk txn sync`.",
+            item_id, request_id
+        ),
+        Refresh::LoginRequired(reason) => println!(
+            "{} needs to be re-authenticated before it can be refreshed: {}. Run `clerk link --update {}`.",
+            item_id, reason, item_id
+        ),
+    }
+
+    Ok(())
+}
+
+/// Reads every link's state from Plaid and records it, returning the links
+/// that need to be re-authenticated along with Plaid's reason.
+async fn check_links<T: HttpClient>(
+    plaid: &Plaid<T>,
+    store: &mut store::SqliteStore,
+) -> Result<Vec<(Link, String)>> {
+    let mut degraded = vec![];
+    for mut link in store.links().list().await? {
+        let reason = match plaid.item(&link.access_token).await {
+            Ok(item) => item
+                .error
+                .filter(|e| e.error_code.as_deref() == Some("ITEM_LOGIN_REQUIRED"))
+                .map(|e| e.error_message.unwrap_or_default()),
+            Err(err) => match PlaidError::from(err) {
+                PlaidError::Api {
+                    error_code,
+                    message,
+                    ..
+                } if error_code == "ITEM_LOGIN_REQUIRED" => Some(message),
+                err => return Err(err.into()),
+            },
+        };
+
+        let state = match &reason {
+            Some(reason) => LinkStatus::Degraded(reason.clone()),
+            None => LinkStatus::Active,
+        };
+        if link.state != state {
+            link.state = state;
+            store.links().update(&link).await?;
+        }
+
+        if let Some(reason) = reason {
+            degraded.push((link, reason));
+        }
+    }
+
+    Ok(degraded)
+}
+
+async fn check(settings: Settings) -> Result<()> {
+    let mut store = store::SqliteStore::new(&settings.db_file).await?;
+    let plaid = default_plaid_client(&settings.plaid)?;
+
+    let degraded = check_links(&plaid, &mut store).await?;
+    for (link, reason) in &degraded {
+        println!(
+            "{} ({}) needs to be re-authenticated: {}. Run `clerk link --update {}`.",
+            link.alias, link.item_id, reason, link.item_id
+        );
+    }
+
+    if !degraded.is_empty() {
+        return Err(anyhow!(
+            "{} links need to be re-authenticated",
+            degraded.len()
+        ));
+    }
+    println!("All links are active.");
+
+    Ok(())
+}
+
+async fn export(settings: Settings, path: &str) -> Result<()> {
+    let mut store = store::SqliteStore::new(&settings.db_file).await?;
+
+    let links: Vec<ExportedLink> = store
+        .links()
+        .list()
+        .await?
+        .into_iter()
+        .map(|link| ExportedLink {
+            item_id: link.item_id,
+            alias: link.alias,
+            institution_id: link.institution_id,
+            access_token: link.access_token,
+            products: link.products,
+        })
+        .collect();
+
+    let encrypted = crypto::encrypt(&serde_json::to_vec(&links)?, &crypto::passphrase()?)?;
+    std::fs::write(path, encrypted)?;
+
+    println!("Exported {} links to {}.", links.len(), path);
+
+    Ok(())
+}
+
+async fn import(settings: Settings, path: &str) -> Result<()> {
+    let mut store = store::SqliteStore::new(&settings.db_file).await?;
+    let plaid = default_plaid_client(&settings.plaid)?;
+
+    let decrypted = crypto::decrypt(&std::fs::read(path)?, &crypto::passphrase()?)?;
+    let links: Vec<ExportedLink> = serde_json::from_slice(&decrypted)?;
+
+    for exported in links {
+        if store.links().by_id(&exported.item_id).await?.is_some() {
+            println!("{}: already linked, skipping", exported.item_id);
+
+            continue;
+        }
+
+        // The cursor is left empty, transactions are pulled again on the next
+        // sync since they aren't part of the export.
+        let mut link = Link {
+            alias: exported.alias,
+            access_token: exported.access_token,
+            item_id: exported.item_id,
+            state: LinkStatus::Active,
+            sync_cursor: None,
+            institution_id: exported.institution_id,
+            products: exported.products,
+        };
+
+        match plaid.item(&link.access_token).await {
+            Ok(_) => println!("{}: ok", link.item_id),
+            Err(err) => {
+                println!(
+                    "{}: token no longer works ({}), run `clerk link --update {}` to re-authenticate",
+                    link.item_id, err, link.item_id
+                );
+                link.state = LinkStatus::Degraded(err.to_string());
+            }
+        }
+
+        store.links().save(&link).await?;
+    }
+
+    Ok(())
+}
+
+async fn status(settings: Settings, fetch_institutions: bool, format: OutputFormat) -> Result<()> {
     let store = store::SqliteStore::new(&settings.db_file).await?;
-    let plaid = default_plaid_client(&settings.plaid);
+    let plaid = default_plaid_client(&settings.plaid)?;
 
-    let link_controller = LinkController::from_upstream(plaid, &settings.plaid, store).await?;
+    let link_controller =
+        LinkController::from_upstream(plaid, &settings.plaid, store, fetch_institutions).await?;
 
     let stdout = std::io::stdout().lock();
 
-    link_controller.display_connections_table(stdout)
+    link_controller.display_connections_table(stdout, format)
 }
 
-pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()> {
+/// Parses the environment passed to `link --env`.
+fn environment(name: &str) -> Result<Environment> {
+    match name {
+        "sandbox" => Ok(Environment::Sandbox),
+        "development" => Ok(Environment::Development),
+        "production" => Ok(Environment::Production),
+        _ => Err(anyhow!(
+            "unknown environment {:?}, expected one of sandbox, development or production",
+            name
+        )),
+    }
+}
+
+pub(crate) async fn run(matches: &ArgMatches, mut settings: Settings) -> Result<()> {
+    if let Some(env) = matches.value_of("env") {
+        settings.plaid.env = environment(env)?;
+    }
+
     match matches.subcommand() {
-        Some(("status", _status_matches)) => status(settings).await,
+        Some(("status", status_matches)) => {
+            let format = status_matches
+                .value_of("output")
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or_default();
+
+            status(
+                settings,
+                !status_matches.is_present("no_institutions"),
+                format,
+            )
+            .await
+        }
         Some(("delete", remove_matches)) => {
             // SAFETY: This should be fine so long as this is a positional
             // argument as clap will prevent this code from executing without a
@@ -185,19 +553,127 @@ pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()>
             let item_id = remove_matches.value_of("item_id").unwrap();
             remove(settings, item_id).await
         }
+        Some(("check", _)) => check(settings).await,
+        Some(("refresh", refresh_matches)) => {
+            // SAFETY: The item id is a required positional argument.
+            refresh(settings, refresh_matches.value_of("item_id").unwrap()).await
+        }
+        Some(("export", export_matches)) => {
+            export(settings, export_matches.value_of("file").unwrap()).await
+        }
+        Some(("import", import_matches)) => {
+            import(settings, import_matches.value_of("file").unwrap()).await
+        }
         _ => {
-            let name = matches.value_of("name").unwrap_or("");
+            let opts = LinkOptions {
+                name: matches.value_of("name").unwrap_or(""),
+                print_url_only: matches.is_present("print_url_only"),
+                open: matches.is_present("open"),
+                qr: matches.is_present("qr"),
+            };
             match matches.value_of("update") {
                 Some(token) => {
                     server(
                         settings,
                         plaid_link::LinkMode::Update(token.to_string()),
-                        name,
+                        opts,
                     )
                     .await
                 }
-                None => server(settings, plaid_link::LinkMode::Create, name).await,
+                None => server(settings, plaid_link::LinkMode::Create, opts).await,
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::plaid::mock::MockPlaid;
+    use crate::plaid::plaid_client_with;
+    use crate::settings::test_settings;
+    use crate::store::test_support::TestStore;
+
+    #[test]
+    fn environment_from_flag() {
+        assert!(matches!(environment("sandbox"), Ok(Environment::Sandbox)));
+        assert!(matches!(
+            environment("development"),
+            Ok(Environment::Development)
+        ));
+        assert!(matches!(
+            environment("production"),
+            Ok(Environment::Production)
+        ));
+        assert!(environment("staging").is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_marks_links_needing_login() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().respond(
+                "/transactions/refresh",
+                json!({ "request_id": "refresh-request" }),
+            ),
+        );
+        assert_eq!(
+            refresh_link(&plaid, store.db(), &link.item_id)
+                .await
+                .unwrap(),
+            Refresh::Requested {
+                request_id: "refresh-request".into()
+            }
+        );
+
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().fail(
+                "/transactions/refresh",
+                400,
+                "ITEM_ERROR",
+                "ITEM_LOGIN_REQUIRED",
+            ),
+        );
+        assert_eq!(
+            refresh_link(&plaid, store.db(), &link.item_id)
+                .await
+                .unwrap(),
+            Refresh::LoginRequired("mock ITEM_LOGIN_REQUIRED".into())
+        );
+        let link = store.db().links().link(&link.item_id).await.unwrap();
+        assert!(matches!(link.state, LinkStatus::Degraded(_)));
+    }
+
+    #[tokio::test]
+    async fn check_records_link_state() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().fail("/item/get", 400, "ITEM_ERROR", "ITEM_LOGIN_REQUIRED"),
+        );
+        let degraded = check_links(&plaid, store.db()).await.unwrap();
+        assert_eq!(degraded.len(), 1);
+        assert_eq!(degraded[0].1, "mock ITEM_LOGIN_REQUIRED");
+        let stored = store.db().links().link(&link.item_id).await.unwrap();
+        assert!(matches!(stored.state, LinkStatus::Degraded(_)));
+
+        // Links are marked active again once the user re-authenticates.
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().with_item(&link.item_id, "ins_1"),
+        );
+        assert!(check_links(&plaid, store.db()).await.unwrap().is_empty());
+        let stored = store.db().links().link(&link.item_id).await.unwrap();
+        assert_eq!(stored.state, LinkStatus::Active);
+    }
+}
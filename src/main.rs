@@ -1,7 +1,16 @@
 mod accounts;
 mod core;
+mod crypto;
+mod currency;
+mod import;
+mod ledger;
 mod link;
+mod maintenance;
+mod onboard;
 mod plaid;
+mod rates;
+mod report;
+mod retry;
 mod settings;
 mod store;
 mod txn;
@@ -19,31 +28,157 @@ async fn run() -> Result<()> {
     let app = Command::new(CLIENT_NAME)
         .about("The clerk utility pulls data from an upstream source, such \
          as Plaid APIs, and generates Ledger records from the transactions.")
-        .version("0.1.0")
+        .version(env!("CARGO_PKG_VERSION"))
         .author("Allan Calix <allan@acx.dev>")
         .subcommand_required(true)
         .allow_external_subcommands(false)
         .arg(arg!(CONFIG: -c --config [FILE] "Sets a custom config file"))
         .arg(arg!(verbose: -d --debug ... "Outputs debug logging information."))
         .subcommand(Command::new("init").about("Initialize CLI for use."))
+        .subcommand(Command::new("version")
+            .about("Prints the clerk version.")
+            .arg(arg!(verbose: -v --verbose "Includes dependency and schema versions along with the config and database paths.")))
         .subcommand(Command::new("link")
             .about("Links a new account for tracking.")
             .arg(arg!(name: -n --name [ALIAS] "An alias to easily identify what accounts the link belongs to."))
             .arg(arg!(update: -u --update [ITEM_ID] "Update a link for an existing account link, must pass the access token for the expired link."))
-            .arg(arg!(env: -e --env [String] "Selects the environment to run against."))
-            .subcommand(Command::new("status").about("Displays all links and their current status."))
+            .arg(arg!(env: -e --env [ENV] "Selects the Plaid environment to run against, overriding the configured one: sandbox, development or production."))
+            .arg(arg!(print_url_only: --"print-url-only" "Prints only the link URL without any surrounding text."))
+            .arg(arg!(open: --open "Opens the link URL in the default browser."))
+            .arg(arg!(qr: --qr "Prints the link URL as a QR code to finish linking from a phone."))
+            .subcommand(Command::new("status")
+                .about("Displays all links and their current status.")
+                .arg(arg!(no_institutions: --"no-institutions" "Skips fetching institution names, showing cached names or institution ids instead."))
+                .arg(arg!(output: -o --output [FORMAT] "Prints links in the given format, defaults to a table.")
+                    .possible_values(["table", "json"])))
             .subcommand(Command::new("delete")
                 .about("Deletes a Plaid account link.")
-                .arg(arg!(item_id: <ITEM_ID> "The item ID of the link to delete."))))
+                .arg(arg!(item_id: <ITEM_ID> "The item ID of the link to delete.")))
+            .subcommand(Command::new("check")
+                .about("Checks every link with Plaid and records which need to be re-authenticated, exiting with an error if any do."))
+            .subcommand(Command::new("refresh")
+                .about("Asks Plaid to check the institution for new transactions now instead of waiting for its next scheduled update.")
+                .arg(arg!(item_id: <ITEM_ID> "The item ID of the link to refresh.")))
+            .subcommand(Command::new("export")
+                .about("Writes all links, including access tokens, to a passphrase encrypted file.")
+                .arg(arg!(file: <FILE> "The file to write links to.")))
+            .subcommand(Command::new("import")
+                .about("Restores links from a file written by `link export`.")
+                .arg(arg!(file: <FILE> "The file to read links from."))))
+        .subcommand(Command::new("onboard")
+            .about("Adds a new link's accounts and pulls its first transactions, run it again to resume after a failure.")
+            .arg(arg!(item_id: <ITEM_ID> "The item ID of the link to onboard."))
+            .arg(arg!(max_transactions: --"max-transactions" [COUNT] "Stops the initial sync after roughly this many transactions, defaults to 1000.")))
         .subcommand(Command::new("account")
             .about("Prints tracked accounts to stdout.")
+            .arg(arg!(output: -o --output [FORMAT] "Prints accounts in the given format, defaults to a table.")
+                .possible_values(["table", "json"]))
             .subcommand(Command::new("balances")
-                .about("Prints balances of all accounts. This command fetches current data and may take some time to complete.")))
+                .about("Prints balances of all accounts. This command fetches current data and may take some time to complete.")
+                .arg(arg!(as_of: --"as-of" [DATE] "Prints the latest balances recorded on or before the given day, formatted YYYY-MM-DD, without fetching."))
+                .arg(arg!(history: --history "Prints every recorded balance for each account, oldest first, without fetching.")
+                    .conflicts_with("as_of")))
+            .subcommand(Command::new("net-worth")
+                .about("Fetches current balances and prints assets, liabilities, and their difference in the base currency."))
+            .subcommand(Command::new("holdings")
+                .about("Fetches and prints the securities held by accounts of links with the investments product."))
+            .subcommand(Command::new("liabilities")
+                .about("Fetches and prints APR, minimum payment, next due date, and last statement balance of credit card and loan accounts."))
+            .subcommand(Command::new("add-manual")
+                .about("Adds an account tracked without Plaid, e.g. cash, and prints its id for `txn add`.")
+                .arg(arg!(name: <NAME> "The account's name."))
+                .arg(arg!(r#type: --type [TYPE] "Whether the account holds money or owes it, defaults to debit.")
+                    .possible_values(["debit", "credit"])))
+            .subcommand(Command::new("rename")
+                .about("Sets the name an account is shown and exported to ledgers under.")
+                .arg(arg!(account_id: <ACCOUNT_ID> "The account to rename."))
+                .arg(arg!(name: [NAME] "The new name, omit it to go back to the institution's name."))))
         .subcommand(Command::new("txn")
             .subcommand_required(true)
             .about("pulls a set of transactions to the store")
             .subcommand(Command::new("sync")
-                .about("Pulls transactions from the given range, defaults to a weeks worth of transactions going back from today.")));
+                .about("Pulls new and updated transactions since the last sync.")
+                .arg(arg!(no_accounts_first: --"no-accounts-first" "Skips refreshing accounts before pulling transactions."))
+                .arg(arg!(output: -o --output [FORMAT] "Writes each processed transaction event to stdout in the given format.")
+                    .possible_values(["json"]))
+                .arg(arg!(reset_all: --"reset-all" "Clears every link's cursor and pulls all available history again, already stored transactions are refreshed rather than duplicated."))
+                .arg(arg!(max_transactions: --"max-transactions" [COUNT] "Stops pulling a link's transactions after roughly this many, the next sync continues where this one stopped."))
+                .arg(arg!(since: --since [DATE] "Pulls transactions dated on or after this day, formatted YYYY-MM-DD, instead of syncing from the last sync. Links' sync progress is left as is.")
+                    .conflicts_with_all(&["reset_all", "max_transactions"]))
+                .arg(arg!(until: --until [DATE] "Pulls transactions dated on or before this day with --since, defaults to today.")
+                    .requires("since"))
+                .arg(arg!(detect_transfers: --"detect-transfers" "Joins matching transactions moving money between tracked accounts into single transfers after syncing.")))
+            .subcommand(Command::new("doctor")
+                .about("Reports transactions that reference accounts missing from the store.")
+                .arg(arg!(delete: --delete "Deletes transactions belonging to missing accounts."))
+                .arg(arg!(reassign: --reassign [ACCOUNT_ID] "Moves transactions belonging to missing accounts to the given account.")
+                    .conflicts_with("delete")))
+            .subcommand(Command::new("export")
+                .about("Writes stored transactions to stdout as a Ledger journal.")
+                .arg(arg!(since_last_export: --"since-last-export" "Only exports transactions added since the previous export."))
+                .arg(arg!(include_removed: --"include-removed" "Includes transactions removed upstream and kept as tombstones."))
+                .arg(arg!(group_by: --"group-by" [PERIOD] "Writes a header before each period's transactions.")
+                    .possible_values(["month", "year"]))
+                .arg(arg!(synthesize_postings: --"synthesize-postings" "Builds a posting from the upstream amount for transactions saved without postings."))
+                .arg(arg!(pending_output: --"pending-output" [FILE] "Writes pending transactions to FILE instead of stdout, the file is replaced on every export.")))
+            .subcommand(Command::new("stats")
+                .about("Prints counts of stored transactions.")
+                .arg(arg!(include_removed: --"include-removed" "Includes transactions removed upstream and kept as tombstones."))
+                .arg(arg!(by_region: --"by-region" "Breaks down transactions by the country and region they were made in."))
+                .arg(arg!(uncategorized: --uncategorized "Counts transactions posted to the uncategorized expense or income account.")))
+            .subcommand(Command::new("list")
+                .about("Prints stored transactions as a table, sorted by date.")
+                .arg(arg!(account: --account [ACCOUNT_ID] "Only prints transactions belonging to the given account."))
+                .arg(arg!(since: --since [DATE] "Only prints transactions dated on or after this day, formatted YYYY-MM-DD."))
+                .arg(arg!(until: --until [DATE] "Only prints transactions dated on or before this day, formatted YYYY-MM-DD."))
+                .arg(arg!(category: --category [CATEGORY] "Only prints transactions in the given Plaid personal finance category, primary (e.g. FOOD_AND_DRINK) or detailed (e.g. FOOD_AND_DRINK_COFFEE).")))
+            .subcommand(Command::new("search")
+                .about("Prints transactions whose description or merchant contains the query, ignoring case, most recent first.")
+                .arg(arg!(query: <QUERY> "The text to search for."))
+                .arg(arg!(limit: --limit [COUNT] "Prints at most this many matches.")))
+            .subcommand(Command::new("add")
+                .about("Adds a hand-entered transaction to an account, balanced against the uncategorized account unless another is given.")
+                .arg(arg!(account: --account <ACCOUNT_ID> "The account the money moved in or out of."))
+                .arg(arg!(amount: --amount <AMOUNT> "The amount, negative when money left the account.")
+                    .allow_hyphen_values(true))
+                .arg(arg!(narration: <NARRATION> "Describes the transaction."))
+                .arg(arg!(date: --date [DATE] "The day of the transaction, formatted YYYY-MM-DD, defaults to today."))
+                .arg(arg!(payee: --payee [PAYEE] "Who was paid or paid you."))
+                .arg(arg!(counter_account: --"counter-account" [ACCOUNT] "The account balancing the transaction, e.g. Expenses:Gifts."))
+                .arg(arg!(currency: --currency [CODE] "The currency of the amount, defaults to default_currency."))))
+        .subcommand(Command::new("ledger")
+            .about("Writes every stored transaction as a Ledger journal, sorted by date.")
+            .arg(arg!(output: -o --output [FILE] "Writes the journal to FILE instead of stdout, replacing it."))
+            .arg(arg!(group_by: --"group-by" [PERIOD] "Writes a header before each period's transactions.")
+                .possible_values(["month", "year"])))
+        .subcommand(Command::new("import")
+            .subcommand_required(true)
+            .about("Imports transactions from files for accounts Plaid doesn't support.")
+            .subcommand(Command::new("ofx")
+                .about("Imports an OFX or QFX statement into a local account, transactions already imported are refreshed rather than duplicated.")
+                .arg(arg!(file: <FILE> "The statement to import."))
+                .arg(arg!(account: --account [ACCOUNT_ID] "Imports into an existing account instead of the local account for the statement's account number."))
+                .arg(arg!(name: --name [NAME] "Names the local account created on the first import of a statement.")
+                    .conflicts_with("account"))))
+        .subcommand(Command::new("report")
+            .subcommand_required(true)
+            .about("Summarizes stored transactions.")
+            .subcommand(Command::new("spending")
+                .about("Prints money spent from tracked accounts per Plaid personal finance category in base_currency, most spent first.")
+                .arg(arg!(since: --since [DATE] "Only counts transactions dated on or after this day, formatted YYYY-MM-DD."))
+                .arg(arg!(until: --until [DATE] "Only counts transactions dated on or before this day, formatted YYYY-MM-DD.")))
+            .subcommand(Command::new("cashflow")
+                .about("Prints money into and out of tracked accounts per month in base_currency, with the net.")
+                .arg(arg!(months: --months [COUNT] "Only covers this many calendar months, including the current one."))))
+        .subcommand(Command::new("store")
+            .subcommand_required(true)
+            .about("Maintenance commands for the local store.")
+            .subcommand(Command::new("check")
+                .about("Verifies store integrity and reports any problems found.")
+                .arg(arg!(fix: --fix "Repairs missing institutions and accounts, cursors that fail to sync, and orphaned rows before checking.")))
+            .subcommand(Command::new("prune")
+                .about("Deletes transactions dated before the given day and compacts the store.")
+                .arg(arg!(before: --before <DATE> "Removes transactions dated before this day, formatted YYYY-MM-DD."))));
 
     let matches = app.get_matches();
     if matches.is_present("verbose") {
@@ -57,6 +192,11 @@ async fn run() -> Result<()> {
             .init();
     }
 
+    // Reports on the config itself, it must work even if settings don't load.
+    if let Some(("version", version_matches)) = matches.subcommand() {
+        return maintenance::version(version_matches, matches.value_of("CONFIG")).await;
+    }
+
     let s = settings::Settings::new(matches.value_of("CONFIG"))?;
     match matches.subcommand() {
         Some(("link", link_matches)) => {
@@ -65,9 +205,24 @@ async fn run() -> Result<()> {
         Some(("txn", link_matches)) => {
             txn::run(link_matches, s).await?;
         }
+        Some(("onboard", onboard_matches)) => {
+            onboard::run(onboard_matches, s).await?;
+        }
         Some(("account", link_matches)) => {
             accounts::run(link_matches, s).await?;
         }
+        Some(("ledger", ledger_matches)) => {
+            txn::journal(ledger_matches, s).await?;
+        }
+        Some(("import", import_matches)) => {
+            import::run(import_matches, s).await?;
+        }
+        Some(("report", report_matches)) => {
+            report::run(report_matches, s).await?;
+        }
+        Some(("store", store_matches)) => {
+            maintenance::run(store_matches, s).await?;
+        }
         None => unreachable!("subcommand is required"),
         _ => unreachable!(),
     }
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use clap::ArgMatches;
+use rplaid::client::Plaid;
+use rplaid::HttpClient;
+
+use crate::core::Account;
+use crate::plaid::{default_plaid_client, Link, LinkStatus};
+use crate::retry::with_retry;
+use crate::settings::{default_config_path, Settings};
+use crate::store::SqliteStore;
+use crate::txn::sync_error_key;
+
+/// Repairs what it safely can, every fix is a no-op when there is nothing to
+/// repair so this can be run repeatedly.
+async fn fix(settings: &Settings, store: &mut SqliteStore) -> Result<()> {
+    let removed = store.txns().delete_orphaned_postings().await?;
+    if removed > 0 {
+        println!("fixed: removed {} orphaned postings", removed);
+    }
+
+    let removed = store.txns().delete_orphaned_connections().await?;
+    if removed > 0 {
+        println!("fixed: removed {} orphaned connections", removed);
+    }
+
+    let plaid = default_plaid_client(&settings.plaid)?;
+    for link in store.links().list().await? {
+        let item_id = link.item_id.clone();
+        if let Err(err) = fix_link(settings, &plaid, store, link).await {
+            println!("links: could not repair link {}: {}", item_id, err);
+        }
+    }
+
+    Ok(())
+}
+
+async fn fix_link<T: HttpClient>(
+    settings: &Settings,
+    plaid: &Plaid<T>,
+    store: &mut SqliteStore,
+    mut link: Link,
+) -> Result<()> {
+    if link.institution_id.is_none() {
+        let item = plaid.item(&link.access_token).await?;
+        if let Some(institution_id) = item.institution_id {
+            println!(
+                "fixed: set institution {} for link {}",
+                institution_id, link.item_id
+            );
+
+            link.institution_id = Some(institution_id);
+            store.links().update(&link).await?;
+        }
+    }
+
+    let error_key = sync_error_key(&link.item_id);
+    if let Some(err) = store.meta().get(&error_key).await? {
+        if is_cursor_error(&err) {
+            println!(
+                "fixed: cleared cursor for link {} after sync error: {}",
+                link.item_id, err
+            );
+
+            link.sync_cursor = None;
+            store.links().update(&link).await?;
+            store.meta().delete(&error_key).await?;
+        }
+    }
+
+    // Degraded links can't list accounts until they're re-authenticated.
+    if matches!(link.state, LinkStatus::Active) {
+        let accounts = with_retry(&settings.plaid.retry, || {
+            plaid.accounts(link.access_token.clone())
+        })
+        .await?;
+        for account in accounts {
+            let account = Account::from(account);
+            if store.accounts().by_id(&account.id).await?.is_none() {
+                println!(
+                    "fixed: added missing account {} to link {}",
+                    account.id, link.item_id
+                );
+
+                store.accounts().save(&link.item_id, &account).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Plaid reports bad cursors with errors mentioning the cursor, e.g. an invalid
+// cursor or a mutation during pagination which requires restarting from one.
+fn is_cursor_error(err: &str) -> bool {
+    let err = err.to_lowercase();
+
+    err.contains("cursor") || err.contains("mutation_during_pagination")
+}
+
+async fn check(settings: Settings, fix_problems: bool) -> Result<()> {
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let mut problems = 0;
+
+    if fix_problems {
+        fix(&settings, &mut store).await?;
+    }
+
+    let integrity = store.integrity_check().await?;
+    if integrity != ["ok"] {
+        for message in &integrity {
+            println!("integrity: {}", message);
+        }
+        problems += integrity.len();
+    }
+
+    let expected = SqliteStore::expected_schema_version();
+    let applied = store.schema_version().await?;
+    if applied != expected {
+        println!(
+            "schema: database is at version {:?}, expected {:?}",
+            applied, expected
+        );
+        problems += 1;
+    }
+
+    let orphaned_accounts = store.txns().orphaned_accounts().await?;
+    for account_id in &orphaned_accounts {
+        println!(
+            "transactions: reference unknown account {}, run `clerk txn doctor` to repair",
+            account_id
+        );
+    }
+    problems += orphaned_accounts.len();
+
+    let orphaned_postings = store.txns().orphaned_postings().await?;
+    if orphaned_postings > 0 {
+        println!(
+            "postings: {} postings reference missing transactions",
+            orphaned_postings
+        );
+        problems += 1;
+    }
+
+    let orphaned_connections = store.txns().orphaned_connections().await?;
+    if orphaned_connections > 0 {
+        println!(
+            "connections: {} connections reference missing transactions",
+            orphaned_connections
+        );
+        problems += 1;
+    }
+
+    let unresolved = store.links().unresolved_institutions().await?;
+    for link in &unresolved {
+        println!(
+            "links: institution {} for link {} is not resolved, run `clerk link status` to refresh",
+            link.institution_id.as_deref().unwrap_or("<none>"),
+            link.item_id
+        );
+    }
+    problems += unresolved.len();
+
+    for link in store.links().list().await? {
+        if let Some(err) = store.meta().get(&sync_error_key(&link.item_id)).await? {
+            if is_cursor_error(&err) {
+                println!(
+                    "links: link {} failed to sync from its cursor ({}), run `clerk store check --fix` to reset it",
+                    link.item_id, err
+                );
+                problems += 1;
+            }
+        }
+    }
+
+    if problems > 0 {
+        return Err(anyhow!("store check found {} problems", problems));
+    }
+
+    println!("No problems found.");
+
+    Ok(())
+}
+
+async fn prune(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    let before = matches.value_of("before").expect("before is required");
+    let before = NaiveDate::parse_from_str(before, "%Y-%m-%d")
+        .map_err(|e| anyhow!("invalid date {}, expected YYYY-MM-DD: {}", before, e))?;
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let stats = store.txns().prune_before(before).await?;
+    store.vacuum().await?;
+
+    println!(
+        "Removed {} transactions, {} postings, and {} connections dated before {}.",
+        stats.transactions, stats.postings, stats.connections, before
+    );
+
+    Ok(())
+}
+
+/// Where the rplaid dependency is pulled from, it isn't published with a
+/// version.
+const RPLAID_SOURCE: &str = "https://github.com/allancalix/rplaid.git (main)";
+
+pub(crate) async fn version(matches: &ArgMatches, config_path: Option<&str>) -> Result<()> {
+    println!("clerk {}", env!("CARGO_PKG_VERSION"));
+    if !matches.is_present("verbose") {
+        return Ok(());
+    }
+
+    println!("rplaid: {}", RPLAID_SOURCE);
+    println!(
+        "schema (expected): {}",
+        display_version(SqliteStore::expected_schema_version())
+    );
+
+    let config_path = config_path
+        .map(str::to_string)
+        .unwrap_or_else(default_config_path);
+    // Settings may fail to load, e.g. before a config is written, report
+    // what's available rather than failing.
+    match Settings::new(Some(&config_path)) {
+        Ok(settings) => {
+            let applied = match SqliteStore::open(&settings.db_file).await {
+                Ok(mut store) => match store.schema_version().await {
+                    Ok(version) => display_version(version),
+                    Err(_) => "none, no migrations applied".to_string(),
+                },
+                Err(err) => format!("unavailable ({})", err),
+            };
+
+            println!("schema (database): {}", applied);
+            println!("config: {}", config_path);
+            println!("database: {}", settings.db_file);
+        }
+        Err(err) => println!("config: {} (failed to load: {})", config_path, err),
+    }
+
+    Ok(())
+}
+
+fn display_version(version: Option<i64>) -> String {
+    version.map_or_else(|| "none".to_string(), |v| v.to_string())
+}
+
+pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()> {
+    match matches.subcommand() {
+        Some(("check", check_matches)) => check(settings, check_matches.is_present("fix")).await,
+        Some(("prune", prune_matches)) => prune(settings, prune_matches).await,
+        None => unreachable!("command is required"),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_cursor_errors() {
+        assert!(is_cursor_error(
+            "TRANSACTIONS_SYNC_MUTATION_DURING_PAGINATION: data changed"
+        ));
+        assert!(is_cursor_error("INVALID_FIELD: cursor is not valid"));
+        assert!(!is_cursor_error("ITEM_LOGIN_REQUIRED"));
+    }
+}
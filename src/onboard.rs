@@ -0,0 +1,168 @@
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use rplaid::HttpClient;
+
+use crate::plaid::sync::SyncClient;
+use crate::plaid::{http_client, LinkStatus};
+use crate::settings::Settings;
+use crate::store::SqliteStore;
+use crate::txn::{describe_failure, sync_error_key, sync_link, LinkSyncResult, PullOptions};
+
+/// Transactions pulled by the initial sync unless overridden, `txn sync`
+/// pulls the rest of the history.
+const INITIAL_SYNC_TRANSACTIONS: usize = 1000;
+
+#[derive(Debug)]
+struct OnboardReport {
+    accounts: usize,
+    accounts_added: usize,
+    sync: LinkSyncResult,
+}
+
+/// Runs the first-run steps for a link. Each step skips work that is already
+/// done, accounts are only added when missing and the sync continues from the
+/// link's cursor, so a failed onboard is resumed by running it again.
+async fn onboard<T: HttpClient>(
+    settings: &Settings,
+    client: &SyncClient<T>,
+    store: &mut SqliteStore,
+    item_id: &str,
+    max_transactions: usize,
+) -> Result<OnboardReport> {
+    let link = store
+        .links()
+        .by_id(item_id)
+        .await?
+        .ok_or_else(|| anyhow!("no link found for item {}", item_id))?;
+
+    if let LinkStatus::Degraded(reason) = &link.state {
+        return Err(anyhow!(
+            "{}, run `clerk link --update {}` to re-authenticate",
+            reason,
+            item_id
+        ));
+    }
+
+    if !link.supports_transactions() {
+        return Err(anyhow!(
+            "transactions are not enabled for item {}, enabled products are {}",
+            item_id,
+            link.products.join(", ")
+        ));
+    }
+
+    let accounts_before = store.accounts().by_item(item_id).await?.len();
+
+    let opts = PullOptions {
+        accounts_first: true,
+        max_transactions: Some(max_transactions),
+        ..Default::default()
+    };
+    let mut sync = LinkSyncResult::new(&link);
+    let start = Instant::now();
+    let error_key = sync_error_key(item_id);
+    match sync_link(settings, client, store, link, &opts, &mut sync).await {
+        Ok(()) => store.meta().delete(&error_key).await?,
+        Err(err) => {
+            store
+                .meta()
+                .set(&error_key, &describe_failure(&err, item_id))
+                .await?;
+
+            return Err(err.context("onboarding stopped, run it again to continue"));
+        }
+    }
+    sync.elapsed = start.elapsed();
+
+    let accounts = store.accounts().by_item(item_id).await?.len();
+
+    Ok(OnboardReport {
+        accounts,
+        accounts_added: accounts - accounts_before,
+        sync,
+    })
+}
+
+pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()> {
+    let item_id = matches.value_of("item_id").unwrap();
+    let max_transactions = match matches.value_of("max_transactions") {
+        Some(count) => count.parse()?,
+        None => INITIAL_SYNC_TRANSACTIONS,
+    };
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let client = SyncClient::new(&settings.plaid, http_client(&settings.plaid)?);
+    let report = onboard(&settings, &client, &mut store, item_id, max_transactions).await?;
+
+    println!(
+        "Onboarded {} in {:.1}s.",
+        item_id,
+        report.sync.elapsed.as_secs_f64()
+    );
+    println!(
+        "Accounts: {} ({} added)",
+        report.accounts, report.accounts_added
+    );
+    println!(
+        "Transactions: added={} modified={} removed={} refetched={}",
+        report.sync.added, report.sync.modified, report.sync.removed, report.sync.refetched
+    );
+
+    let pulled =
+        report.sync.added + report.sync.modified + report.sync.removed + report.sync.refetched;
+    if pulled >= max_transactions {
+        println!("More history may be available, run `clerk txn sync` to pull the rest.");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plaid::mock::{mock_account, MockPlaid};
+    use crate::settings::test_settings;
+    use crate::store::test_support::{TestStore, TransactionBuilder};
+    use crate::store::TransactionFilter;
+
+    #[tokio::test]
+    async fn onboard_resumes_without_duplicating() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let txn = TransactionBuilder::new()
+            .account_id("mock-account")
+            .transaction_id("mock-txn")
+            .build();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_accounts(vec![mock_account("mock-account")])
+                .with_transactions(vec![txn.source], "cursor-1"),
+        );
+
+        let report = onboard(&settings, &client, store.db(), &link.item_id, 10)
+            .await
+            .unwrap();
+        assert_eq!(report.accounts_added, 1);
+        assert_eq!(report.sync.added, 1);
+
+        let report = onboard(&settings, &client, store.db(), &link.item_id, 10)
+            .await
+            .unwrap();
+        assert_eq!(report.accounts, 1);
+        assert_eq!(report.accounts_added, 0);
+        assert_eq!(report.sync.added, 0);
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+    }
+}
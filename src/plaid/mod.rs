@@ -1,14 +1,33 @@
+pub(crate) mod sync;
+
 use std::collections::HashMap;
 use std::io::Write;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use http_client::isahc::IsahcClient;
+use isahc::config::{CaCertificate, Configurable};
+use isahc::http::Uri;
 use rplaid::client::{Builder, Credentials, Plaid};
+use rplaid::HttpClient;
+use serde_json::json;
 use tabwriter::TabWriter;
 use tracing::{info, warn};
 
+#[cfg(test)]
+pub(crate) use plaid_link::mock;
+
+use crate::core::AccountNameSource;
+use crate::retry::with_retry;
 use crate::settings::Plaid as PlaidSettings;
 use crate::store::{institution::Institution, SqliteStore};
 
+const TRANSACTIONS_PRODUCT: &str = "transactions";
+const INVESTMENTS_PRODUCT: &str = "investments";
+const LIABILITIES_PRODUCT: &str = "liabilities";
+/// Shown for links whose institution was never resolved, e.g. sandbox items.
+const UNKNOWN_INSTITUTION: &str = "Unknown Institution";
+
 pub struct LinkController {
     connections: Vec<Connection>,
 }
@@ -29,55 +48,36 @@ impl LinkController {
         for link in links {
             let accounts = store.accounts().by_item(&link.item_id).await?;
 
+            let ins_name = institution_name(&link.item_id, link.institution_id, &ins_cache);
             connections.push(Connection {
                 accounts,
                 state: link.state.clone(),
+                products: link.products,
                 alias: link.alias,
                 item_id: link.item_id,
-                ins_name: ins_cache
-                    .get(&link.institution_id.unwrap())
-                    .unwrap()
-                    .to_string(),
+                ins_name,
             });
         }
 
         Ok(LinkController { connections })
     }
 
-    pub async fn initialize(
-        client: Plaid,
+    pub async fn initialize<T: HttpClient>(
+        client: Plaid<T>,
         settings: &PlaidSettings,
         mut store: crate::store::SqliteStore,
     ) -> Result<LinkController> {
         let mut connections = vec![];
         let links = store.links().list().await?;
 
-        let country_codes: Vec<&str> = settings.country_codes.iter().map(AsRef::as_ref).collect();
-        let ins_cache: HashMap<String, String> = client
-            .get_institutions(&rplaid::model::InstitutionsGetRequest {
-                count: 500,
-                offset: 0,
-                country_codes: country_codes.as_slice(),
-                options: None,
-            })
-            .await?
-            .into_iter()
-            .map(|i| (i.institution_id, i.name))
-            .collect();
-
-        for (k, v) in ins_cache.iter() {
-            store
-                .institutions()
-                .save(&Institution {
-                    id: k.clone(),
-                    name: v.clone(),
-                })
-                .await?;
-        }
+        let ins_cache = institution_names(&client, settings, &mut store).await?;
 
         for mut link in links {
             let canonical = client.item(&link.access_token).await?;
 
+            repair_institution(&mut store, &mut link, canonical.institution_id.as_ref()).await?;
+            update_products(&mut store, &mut link, &canonical.billed_products).await?;
+
             if let Some(e) = &canonical.error {
                 if let Some("ITEM_LOGIN_REQUIRED") = &e.error_code.as_deref() {
                     info!("Link: {} failed with status {:?}", link.item_id, e);
@@ -93,61 +93,55 @@ impl LinkController {
                 warn!("Unexpected link error. id={}", link.item_id);
             }
 
-            for acc in client.accounts(link.access_token).await.unwrap() {
+            let accounts = with_retry(&settings.retry, || {
+                client.accounts(link.access_token.clone())
+            })
+            .await?;
+            for acc in accounts {
                 store.accounts().save(&link.item_id, &acc.into()).await?;
             }
 
             let accounts = store.accounts().by_item(&link.item_id).await?;
 
+            let ins_name = institution_name(&link.item_id, link.institution_id, &ins_cache);
             connections.push(Connection {
                 accounts,
                 state: link.state.clone(),
+                products: link.products,
                 alias: link.alias,
                 item_id: link.item_id,
-                ins_name: ins_cache
-                    .get(&link.institution_id.unwrap())
-                    .unwrap()
-                    .to_string(),
+                ins_name,
             });
         }
 
         Ok(LinkController { connections })
     }
 
-    pub async fn from_upstream(
-        client: Plaid,
+    /// Refreshes every link's state from Plaid. When `fetch_institutions` is
+    /// false institution names are read from the store only, even when stale,
+    /// and links whose institution isn't cached show the institution id
+    /// instead.
+    pub async fn from_upstream<T: HttpClient>(
+        client: Plaid<T>,
         settings: &PlaidSettings,
         mut store: crate::store::SqliteStore,
+        fetch_institutions: bool,
     ) -> Result<LinkController> {
         let mut connections = vec![];
         let links = store.links().list().await?;
 
-        let country_codes: Vec<&str> = settings.country_codes.iter().map(AsRef::as_ref).collect();
-        let ins_cache: HashMap<String, String> = client
-            .get_institutions(&rplaid::model::InstitutionsGetRequest {
-                count: 500,
-                offset: 0,
-                country_codes: country_codes.as_slice(),
-                options: None,
-            })
-            .await?
-            .into_iter()
-            .map(|i| (i.institution_id, i.name))
-            .collect();
-
-        for (k, v) in ins_cache.iter() {
-            store
-                .institutions()
-                .save(&Institution {
-                    id: k.clone(),
-                    name: v.clone(),
-                })
-                .await?;
-        }
+        let ins_cache = if fetch_institutions {
+            institution_names(&client, settings, &mut store).await?
+        } else {
+            cached_institution_names(&mut store).await?
+        };
 
         for mut link in links {
             let canonical = client.item(&link.access_token).await?;
 
+            repair_institution(&mut store, &mut link, canonical.institution_id.as_ref()).await?;
+            update_products(&mut store, &mut link, &canonical.billed_products).await?;
+
             if let Some(e) = &canonical.error {
                 if let Some("ITEM_LOGIN_REQUIRED") = &e.error_code.as_deref() {
                     info!("Link: {} failed with status {:?}", link.item_id, e);
@@ -165,30 +159,58 @@ impl LinkController {
 
             let accounts = store.accounts().by_item(&link.item_id).await?;
 
+            let ins_name = institution_name(&link.item_id, link.institution_id, &ins_cache);
             connections.push(Connection {
                 accounts,
                 state: link.state.clone(),
+                products: link.products,
                 alias: link.alias,
                 item_id: link.item_id,
-                ins_name: ins_cache
-                    .get(&link.institution_id.unwrap())
-                    .unwrap()
-                    .to_string(),
+                ins_name,
             });
         }
 
         Ok(LinkController { connections })
     }
 
-    pub fn display_connections_table<T: std::io::Write>(&self, wr: T) -> Result<()> {
+    pub fn display_connections_table<T: std::io::Write>(
+        &self,
+        mut wr: T,
+        format: OutputFormat,
+    ) -> Result<()> {
+        if let OutputFormat::Json = format {
+            let connections: Vec<_> = self
+                .connections
+                .iter()
+                .map(|conn| {
+                    json!({
+                        "alias": conn.alias,
+                        "item_id": conn.item_id,
+                        "institution": conn.ins_name,
+                        "products": conn.products,
+                        "state": conn.state.name(),
+                        "error": conn.state.error(),
+                    })
+                })
+                .collect();
+            serde_json::to_writer(&mut wr, &connections)?;
+            writeln!(wr)?;
+
+            return Ok(());
+        }
+
         let mut tw = TabWriter::new(wr);
-        writeln!(tw, "Name\tItem ID\tInstitution\tState")?;
+        writeln!(tw, "Name\tItem ID\tInstitution\tProducts\tState")?;
 
         for conn in &self.connections {
             writeln!(
                 tw,
-                "{}\t{}\t{}\t{:?}",
-                conn.alias, conn.item_id, conn.ins_name, conn.state
+                "{}\t{}\t{}\t{}\t{:?}",
+                conn.alias,
+                conn.item_id,
+                conn.ins_name,
+                conn.products.join(","),
+                conn.state
             )?;
         }
 
@@ -197,7 +219,34 @@ impl LinkController {
         Ok(())
     }
 
-    pub fn display_accounts_table<T: std::io::Write>(&self, wr: T) -> Result<()> {
+    pub fn display_accounts_table<T: std::io::Write>(
+        &self,
+        mut wr: T,
+        name_source: AccountNameSource,
+        format: OutputFormat,
+    ) -> Result<()> {
+        if let OutputFormat::Json = format {
+            let accounts: Vec<_> = self
+                .connections
+                .iter()
+                .flat_map(|conn| {
+                    conn.accounts.iter().map(|account| {
+                        json!({
+                            "institution": conn.ins_name,
+                            "item_id": conn.item_id,
+                            "name": account.display_name(name_source),
+                            "id": account.id,
+                            "type": account.ty,
+                        })
+                    })
+                })
+                .collect();
+            serde_json::to_writer(&mut wr, &accounts)?;
+            writeln!(wr)?;
+
+            return Ok(());
+        }
+
         let mut tw = TabWriter::new(wr);
         writeln!(tw, "Institution\tAccount\tAccount ID\tType")?;
 
@@ -206,7 +255,10 @@ impl LinkController {
                 writeln!(
                     tw,
                     "{}\t{}\t{}\t{:?}",
-                    conn.ins_name, account.name, account.id, account.ty,
+                    conn.ins_name,
+                    account.display_name(name_source),
+                    account.id,
+                    account.ty,
                 )?;
             }
         }
@@ -217,14 +269,167 @@ impl LinkController {
     }
 }
 
-pub(crate) fn default_plaid_client(settings: &PlaidSettings) -> rplaid::client::Plaid {
+/// How commands listing links or accounts write them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A human-readable table.
+    #[default]
+    Table,
+    /// A JSON array, for scripting.
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow::anyhow!("unknown output format {}", s)),
+        }
+    }
+}
+
+/// Returns institution names by id from the store, fetching them from Plaid
+/// first when they were never fetched or are older than
+/// `institutions_max_age_days`.
+async fn institution_names<T: HttpClient>(
+    client: &Plaid<T>,
+    settings: &PlaidSettings,
+    store: &mut SqliteStore,
+) -> Result<HashMap<String, String>> {
+    let max_age = chrono::Duration::days(settings.institutions_max_age_days);
+    let fresh = matches!(
+        store.institutions().last_synced().await?,
+        Some(at) if Utc::now() - at < max_age
+    );
+
+    if !fresh {
+        info!("Refreshing cached institutions.");
+
+        let country_codes: Vec<&str> = settings.country_codes.iter().map(AsRef::as_ref).collect();
+        let institutions = client
+            .get_institutions(&rplaid::model::InstitutionsGetRequest {
+                count: 500,
+                offset: 0,
+                country_codes: country_codes.as_slice(),
+                options: None,
+            })
+            .await?;
+
+        for ins in institutions {
+            store
+                .institutions()
+                .save(&Institution {
+                    id: ins.institution_id,
+                    name: ins.name,
+                })
+                .await?;
+        }
+    }
+
+    cached_institution_names(store).await
+}
+
+/// Looks up a link's institution name, falling back to the institution id
+/// when it isn't cached.
+fn institution_name(
+    item_id: &str,
+    institution_id: Option<String>,
+    names: &HashMap<String, String>,
+) -> String {
+    match institution_id {
+        Some(id) => match names.get(&id) {
+            Some(name) => name.clone(),
+            None => {
+                warn!("Institution {} for link {} is not cached", id, item_id);
+
+                id
+            }
+        },
+        None => {
+            warn!("Link {} has no institution", item_id);
+
+            UNKNOWN_INSTITUTION.to_string()
+        }
+    }
+}
+
+/// Fills in the institution of links saved before it was recorded.
+async fn repair_institution(
+    store: &mut SqliteStore,
+    link: &mut Link,
+    institution_id: Option<&String>,
+) -> Result<()> {
+    if link.institution_id.is_none() && institution_id.is_some() {
+        info!("Repairing missing institution for link {}", link.item_id);
+
+        link.institution_id = institution_id.cloned();
+        store.links().update(link).await?;
+    }
+
+    Ok(())
+}
+
+/// Records the products Plaid bills the item for when they changed.
+async fn update_products(
+    store: &mut SqliteStore,
+    link: &mut Link,
+    products: &[String],
+) -> Result<()> {
+    if link.products != products {
+        link.products = products.to_vec();
+        store.links().update(link).await?;
+    }
+
+    Ok(())
+}
+
+async fn cached_institution_names(store: &mut SqliteStore) -> Result<HashMap<String, String>> {
+    Ok(store
+        .institutions()
+        .list()
+        .await?
+        .into_iter()
+        .map(|i| (i.id, i.name))
+        .collect())
+}
+
+pub(crate) fn default_plaid_client(settings: &PlaidSettings) -> Result<Plaid<IsahcClient>> {
+    Ok(plaid_client_with(settings, http_client(settings)?))
+}
+
+/// Builds a client that sends requests through `http` instead of the default
+/// client, e.g. to serve canned responses with `mock::MockPlaid`.
+pub(crate) fn plaid_client_with<T: HttpClient>(settings: &PlaidSettings, http: T) -> Plaid<T> {
+    builder(settings).with_http_client(http).build()
+}
+
+/// Builds the HTTP client requests to Plaid are sent through, going through
+/// `plaid.proxy` and trusting `plaid.ca_certificate` when set.
+pub(crate) fn http_client(settings: &PlaidSettings) -> Result<IsahcClient> {
+    let mut builder = isahc::HttpClient::builder();
+    if let Some(proxy) = &settings.proxy {
+        let proxy: Uri = proxy
+            .parse()
+            .map_err(|e| anyhow!("plaid.proxy {} is not a valid URL: {}", proxy, e))?;
+        builder = builder.proxy(Some(proxy));
+    }
+    if let Some(path) = &settings.ca_certificate {
+        builder = builder.ssl_ca_certificate(CaCertificate::file(path));
+    }
+
+    Ok(IsahcClient::from_client(builder.build()?))
+}
+
+fn builder(settings: &PlaidSettings) -> Builder {
     Builder::new()
         .with_credentials(Credentials {
             client_id: settings.client_id.clone(),
             secret: settings.secret.clone(),
         })
         .with_env(settings.env.clone())
-        .build()
 }
 
 #[derive(Debug, Clone)]
@@ -235,19 +440,218 @@ pub struct Link {
     pub state: LinkStatus,
     pub sync_cursor: Option<String>,
     pub institution_id: Option<String>,
+    /// Plaid products enabled for the item, e.g. "transactions".
+    pub products: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+impl Link {
+    /// Whether the item has the transactions product enabled. Links saved
+    /// before products were recorded have none and are assumed to.
+    pub fn supports_transactions(&self) -> bool {
+        self.products.is_empty() || self.products.iter().any(|p| p == TRANSACTIONS_PRODUCT)
+    }
+
+    /// Whether the item has the investments product enabled.
+    pub fn supports_investments(&self) -> bool {
+        self.products.iter().any(|p| p == INVESTMENTS_PRODUCT)
+    }
+
+    /// Whether the item has the liabilities product enabled.
+    pub fn supports_liabilities(&self) -> bool {
+        self.products.iter().any(|p| p == LIABILITIES_PRODUCT)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LinkStatus {
     Active,
     Degraded(String),
 }
 
+impl LinkStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            LinkStatus::Active => "active",
+            LinkStatus::Degraded(_) => "degraded",
+        }
+    }
+
+    fn error(&self) -> Option<&str> {
+        match self {
+            LinkStatus::Active => None,
+            LinkStatus::Degraded(reason) => Some(reason),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Connection {
     alias: String,
     item_id: String,
     state: LinkStatus,
     ins_name: String,
+    products: Vec<String>,
     accounts: Vec<crate::core::Account>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plaid::mock::{mock_account, MockPlaid};
+    use crate::settings::test_settings;
+    use crate::store::test_support::{memory_store, LinkBuilder};
+
+    #[tokio::test]
+    async fn from_upstream_repairs_missing_institution() {
+        let settings = test_settings();
+        let mut store = memory_store().await;
+        let link = LinkBuilder::new().build();
+        store.links().save(&link).await.unwrap();
+
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().with_item(&link.item_id, "ins_mock"),
+        );
+        let controller = LinkController::from_upstream(plaid, &settings.plaid, store, true)
+            .await
+            .unwrap();
+
+        let mut out = vec![];
+        controller
+            .display_connections_table(&mut out, OutputFormat::Table)
+            .unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("Mock Bank"));
+    }
+
+    #[tokio::test]
+    async fn from_upstream_uses_cached_institutions() {
+        let settings = test_settings();
+        let mut store = memory_store().await;
+        let cached = LinkBuilder::new().institution_id("ins_cached").build();
+        let uncached = LinkBuilder::new().institution_id("ins_uncached").build();
+        store.links().save(&cached).await.unwrap();
+        store.links().save(&uncached).await.unwrap();
+        store
+            .institutions()
+            .save(&Institution {
+                id: "ins_cached".into(),
+                name: "Cached Bank".into(),
+            })
+            .await
+            .unwrap();
+
+        // Institutions aren't served, fetching them would fail the request.
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().replace("/institutions/get", serde_json::json!({})),
+        );
+        let controller = LinkController::from_upstream(plaid, &settings.plaid, store, false)
+            .await
+            .unwrap();
+
+        let mut out = vec![];
+        controller
+            .display_connections_table(&mut out, OutputFormat::Table)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Cached Bank"));
+        assert!(out.contains("ins_uncached"));
+    }
+
+    #[tokio::test]
+    async fn from_upstream_skips_fresh_institutions() {
+        let settings = test_settings();
+        let mut store = memory_store().await;
+        let link = LinkBuilder::new().institution_id("ins_cached").build();
+        store.links().save(&link).await.unwrap();
+        store
+            .institutions()
+            .save(&Institution {
+                id: "ins_cached".into(),
+                name: "Cached Bank".into(),
+            })
+            .await
+            .unwrap();
+
+        // Institutions aren't served, fetching them would fail the request.
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_item(&link.item_id, "ins_cached")
+                .replace("/institutions/get", serde_json::json!({})),
+        );
+        let controller = LinkController::from_upstream(plaid, &settings.plaid, store, true)
+            .await
+            .unwrap();
+
+        let mut out = vec![];
+        controller
+            .display_connections_table(&mut out, OutputFormat::Table)
+            .unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("Cached Bank"));
+    }
+
+    #[test]
+    fn supports_transactions() {
+        let mut link = LinkBuilder::new().build();
+        assert!(link.supports_transactions());
+
+        link.products = vec!["auth".into()];
+        assert!(!link.supports_transactions());
+
+        link.products = vec![];
+        assert!(link.supports_transactions());
+    }
+
+    #[tokio::test]
+    async fn new_renders_links_without_institution() {
+        let mut store = memory_store().await;
+        let link = LinkBuilder::new().build();
+        assert!(link.institution_id.is_none());
+        store.links().save(&link).await.unwrap();
+
+        let controller = LinkController::new(store).await.unwrap();
+
+        let mut out = vec![];
+        controller
+            .display_connections_table(&mut out, OutputFormat::Table)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&link.item_id));
+        assert!(out.contains(UNKNOWN_INSTITUTION));
+    }
+
+    #[tokio::test]
+    async fn displays_connections_as_json() {
+        let mut store = memory_store().await;
+        let link = LinkBuilder::new().build();
+        store.links().save(&link).await.unwrap();
+        store
+            .accounts()
+            .save(
+                &link.item_id,
+                &crate::core::Account::from(mock_account("acc-1")),
+            )
+            .await
+            .unwrap();
+
+        let controller = LinkController::new(store).await.unwrap();
+
+        let mut out = vec![];
+        controller
+            .display_connections_table(&mut out, OutputFormat::Json)
+            .unwrap();
+        let connections: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(connections[0]["item_id"], link.item_id.as_str());
+        assert_eq!(connections[0]["state"], "active");
+        assert_eq!(connections[0]["error"], serde_json::Value::Null);
+
+        let mut out = vec![];
+        controller
+            .display_accounts_table(&mut out, AccountNameSource::Display, OutputFormat::Json)
+            .unwrap();
+        let accounts: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(accounts[0]["id"], "acc-1");
+        assert_eq!(accounts[0]["name"], "Mock Checking");
+    }
+}
@@ -0,0 +1,270 @@
+use std::ops::{Deref, DerefMut};
+
+use http_types::{Body, Method, Request, Url};
+use rplaid::client::{ClientError, Environment};
+use rplaid::model::{Account, Transaction};
+use rplaid::HttpClient;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::settings::Plaid as PlaidSettings;
+
+/// Plaid's personal finance category for a transaction, e.g. "FOOD_AND_DRINK"
+/// and "FOOD_AND_DRINK_COFFEE".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersonalFinanceCategory {
+    pub primary: String,
+    pub detailed: String,
+}
+
+/// A transaction as Plaid reports it: rplaid's model, which leaves out the
+/// personal finance category, along with the category. It's serialized flat,
+/// the same as Plaid's response, and stored as the transaction's source.
+#[derive(Serialize, Deserialize)]
+pub struct PlaidTransaction {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    #[serde(default)]
+    pub personal_finance_category: Option<PersonalFinanceCategory>,
+}
+
+impl From<Transaction> for PlaidTransaction {
+    fn from(transaction: Transaction) -> Self {
+        Self {
+            transaction,
+            personal_finance_category: None,
+        }
+    }
+}
+
+impl Deref for PlaidTransaction {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Self::Target {
+        &self.transaction
+    }
+}
+
+impl DerefMut for PlaidTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.transaction
+    }
+}
+
+/// One page of changes from `/transactions/sync`.
+#[derive(Deserialize)]
+pub struct SyncPage {
+    pub added: Vec<PlaidTransaction>,
+    pub modified: Vec<PlaidTransaction>,
+    pub removed: Vec<RemovedTransaction>,
+    pub next_cursor: String,
+    pub has_more: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RemovedTransaction {
+    pub transaction_id: String,
+}
+
+/// One page of transactions from `/transactions/get`.
+#[derive(Deserialize)]
+pub struct TransactionsPage {
+    pub transactions: Vec<PlaidTransaction>,
+    pub total_transactions: usize,
+}
+
+#[derive(Deserialize)]
+struct AccountsResponse {
+    accounts: Vec<Account>,
+}
+
+/// What is requested alongside each transaction.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransactionOptions {
+    pub include_original_description: bool,
+    pub include_personal_finance_category: bool,
+}
+
+/// Calls the Plaid endpoints a sync reads from. Requests go through `http`
+/// directly rather than through rplaid, whose transaction model leaves out
+/// the personal finance category. Failures are reported as rplaid's
+/// [`ClientError`] so they're handled like any other Plaid failure.
+pub struct SyncClient<T> {
+    http: T,
+    url: &'static str,
+    client_id: String,
+    secret: String,
+}
+
+impl<T: HttpClient> SyncClient<T> {
+    pub fn new(settings: &PlaidSettings, http: T) -> Self {
+        Self {
+            http,
+            url: base_url(&settings.env),
+            client_id: settings.client_id.clone(),
+            secret: settings.secret.clone(),
+        }
+    }
+
+    pub async fn accounts(&self, access_token: &str) -> Result<Vec<Account>, ClientError> {
+        let res: AccountsResponse = self
+            .post("/accounts/get", json!({ "access_token": access_token }))
+            .await?;
+
+        Ok(res.accounts)
+    }
+
+    /// Pulls up to `count` changes after `cursor`, from the start of the
+    /// item's history without one.
+    pub async fn sync(
+        &self,
+        access_token: &str,
+        cursor: Option<&str>,
+        count: u32,
+        options: TransactionOptions,
+    ) -> Result<SyncPage, ClientError> {
+        let mut body = json!({
+            "access_token": access_token,
+            "count": count,
+            "options": {
+                "include_original_description": options.include_original_description,
+                "include_personal_finance_category": options.include_personal_finance_category,
+            },
+        });
+        if let Some(cursor) = cursor {
+            body["cursor"] = cursor.into();
+        }
+
+        self.post("/transactions/sync", body).await
+    }
+
+    /// Pulls up to `count` transactions dated within `start_date` and
+    /// `end_date`, skipping the first `offset`.
+    pub async fn transactions(
+        &self,
+        access_token: &str,
+        start_date: &str,
+        end_date: &str,
+        count: u32,
+        offset: usize,
+        options: TransactionOptions,
+    ) -> Result<TransactionsPage, ClientError> {
+        self.post(
+            "/transactions/get",
+            json!({
+                "access_token": access_token,
+                "start_date": start_date,
+                "end_date": end_date,
+                "options": {
+                    "count": count,
+                    "offset": offset,
+                    "include_original_description": options.include_original_description,
+                    "include_personal_finance_category":
+                        options.include_personal_finance_category,
+                },
+            }),
+        )
+        .await
+    }
+
+    async fn post<R: DeserializeOwned>(
+        &self,
+        path: &str,
+        mut body: Value,
+    ) -> Result<R, ClientError> {
+        body["client_id"] = self.client_id.as_str().into();
+        body["secret"] = self.secret.as_str().into();
+
+        // SAFETY: Built from one of Plaid's base URLs and a static path.
+        let url = Url::parse(&format!("{}{}", self.url, path)).unwrap();
+        let mut req = Request::new(Method::Post, url);
+        req.set_body(Body::from_json(&body).map_err(ClientError::Http)?);
+
+        let mut res = self.http.send(req).await.map_err(ClientError::Http)?;
+        if res.status().is_success() {
+            return res.body_json().await.map_err(ClientError::Http);
+        }
+
+        // Plaid reports failures as an error object, anything else is kept as
+        // an HTTP error with the response's status.
+        let status = res.status();
+        match res.body_json().await {
+            Ok(err) => Err(ClientError::App(err)),
+            Err(_) => Err(ClientError::Http(http_types::Error::from_str(
+                status,
+                format!("{} responded with status {}", path, status),
+            ))),
+        }
+    }
+}
+
+fn base_url(env: &Environment) -> &'static str {
+    match env {
+        Environment::Sandbox => "https://sandbox.plaid.com",
+        Environment::Development => "https://development.plaid.com",
+        Environment::Production => "https://production.plaid.com",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plaid::mock::{transactions_page, MockPlaid};
+    use crate::settings::test_settings;
+    use crate::store::test_support::TransactionBuilder;
+
+    #[tokio::test]
+    async fn sync_keeps_personal_finance_category() {
+        let settings = test_settings();
+        let mut txn = TransactionBuilder::new().build().source;
+        txn.personal_finance_category = Some(PersonalFinanceCategory {
+            primary: "FOOD_AND_DRINK".into(),
+            detailed: "FOOD_AND_DRINK_COFFEE".into(),
+        });
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new().replace(
+                "/transactions/sync",
+                transactions_page(vec![txn], "cursor-1", false),
+            ),
+        );
+
+        let page = client
+            .sync("token", None, 10, TransactionOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(page.next_cursor, "cursor-1");
+        assert_eq!(
+            page.added[0].personal_finance_category,
+            Some(PersonalFinanceCategory {
+                primary: "FOOD_AND_DRINK".into(),
+                detailed: "FOOD_AND_DRINK_COFFEE".into(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_keep_plaid_details() {
+        let settings = test_settings();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new().fail(
+                "/transactions/sync",
+                400,
+                "ITEM_ERROR",
+                "ITEM_LOGIN_REQUIRED",
+            ),
+        );
+
+        let err = client
+            .sync("token", None, 10, TransactionOptions::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            ClientError::App(res) if res.error_code.as_deref() == Some("ITEM_LOGIN_REQUIRED")
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rusty_money::{iso::Currency, Money};
+
+/// A static table of conversion rates read from the `[rates]` section of the
+/// configuration. Rates are keyed as `<FROM>_<TO>`, e.g. `EUR_USD = 1.08`.
+#[derive(Debug, Default)]
+pub struct Rates {
+    rates: HashMap<(String, String), Decimal>,
+}
+
+/// The result of rolling a set of amounts up to a single currency.
+#[derive(Debug, PartialEq)]
+pub enum Total {
+    /// Every amount could be converted to the target currency.
+    Converted(Money<'static, Currency>),
+    /// At least one rate was missing, amounts are summed per currency instead.
+    Subtotals(Vec<Money<'static, Currency>>),
+}
+
+impl Rates {
+    pub fn new(table: &HashMap<String, Decimal>) -> Result<Self> {
+        let mut rates = HashMap::new();
+        for (pair, rate) in table {
+            let (from, to) = pair
+                .split_once('_')
+                .ok_or_else(|| anyhow!("invalid rate pair {}, expected FROM_TO", pair))?;
+            if rate.is_zero() {
+                return Err(anyhow!("rate for {} must not be zero", pair));
+            }
+
+            rates.insert((from.to_uppercase(), to.to_uppercase()), *rate);
+        }
+
+        Ok(Self { rates })
+    }
+
+    /// Converts `money` to the `target` currency, using the inverse rate when
+    /// only the opposite pair is configured. Returns `None` if no rate exists.
+    pub fn convert(
+        &self,
+        money: &Money<'static, Currency>,
+        target: &'static Currency,
+    ) -> Option<Money<'static, Currency>> {
+        let from = money.currency().iso_alpha_code.to_string();
+        let to = target.iso_alpha_code.to_string();
+        if from == to {
+            return Some(money.clone());
+        }
+
+        if let Some(rate) = self.rates.get(&(from.clone(), to.clone())) {
+            return Some(Money::from_decimal(*money.amount() * rate, target));
+        }
+
+        self.rates
+            .get(&(to, from))
+            .map(|rate| Money::from_decimal(*money.amount() / rate, target))
+    }
+
+    /// Sums `amounts` in the `target` currency. When any amount can't be
+    /// converted the per-currency subtotals are reported rather than guessing.
+    pub fn total(&self, amounts: &[Money<'static, Currency>], target: &'static Currency) -> Total {
+        let mut converted = Decimal::ZERO;
+        for amount in amounts {
+            match self.convert(amount, target) {
+                Some(money) => converted += *money.amount(),
+                None => return Total::Subtotals(subtotals(amounts)),
+            }
+        }
+
+        Total::Converted(Money::from_decimal(converted, target))
+    }
+}
+
+fn subtotals(amounts: &[Money<'static, Currency>]) -> Vec<Money<'static, Currency>> {
+    let mut totals: Vec<(&'static Currency, Decimal)> = vec![];
+    for amount in amounts {
+        match totals
+            .iter_mut()
+            .find(|(currency, _)| *currency == amount.currency())
+        {
+            Some((_, total)) => *total += *amount.amount(),
+            None => totals.push((amount.currency(), *amount.amount())),
+        }
+    }
+
+    totals
+        .into_iter()
+        .map(|(currency, total)| Money::from_decimal(total, currency))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_money::iso;
+
+    use super::*;
+
+    fn rates() -> Rates {
+        let mut table = HashMap::new();
+        table.insert("EUR_USD".to_string(), Decimal::new(150, 2));
+
+        Rates::new(&table).unwrap()
+    }
+
+    #[test]
+    fn converts_with_direct_and_inverse_rates() {
+        let rates = rates();
+
+        assert_eq!(
+            rates.convert(&Money::from_major(10, iso::EUR), iso::USD),
+            Some(Money::from_major(15, iso::USD))
+        );
+        assert_eq!(
+            rates.convert(&Money::from_major(15, iso::USD), iso::EUR),
+            Some(Money::from_major(10, iso::EUR))
+        );
+        assert_eq!(rates.convert(&Money::from_major(1, iso::GBP), iso::USD), None);
+    }
+
+    #[test]
+    fn total_falls_back_to_subtotals_when_rate_missing() {
+        let rates = rates();
+
+        assert_eq!(
+            rates.total(
+                &[Money::from_major(10, iso::EUR), Money::from_major(5, iso::USD)],
+                iso::USD
+            ),
+            Total::Converted(Money::from_major(20, iso::USD))
+        );
+        assert_eq!(
+            rates.total(
+                &[
+                    Money::from_major(1, iso::GBP),
+                    Money::from_major(5, iso::USD),
+                    Money::from_major(2, iso::GBP),
+                ],
+                iso::USD
+            ),
+            Total::Subtotals(vec![
+                Money::from_major(3, iso::GBP),
+                Money::from_major(5, iso::USD),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_pairs() {
+        let mut table = HashMap::new();
+        table.insert("EURUSD".to_string(), Decimal::ONE);
+
+        assert!(Rates::new(&table).is_err());
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate, Utc};
+use clap::ArgMatches;
+use rust_decimal::Decimal;
+use rusty_money::{iso, iso::Currency, Money};
+use tabwriter::TabWriter;
+use ulid::Ulid;
+
+use crate::core::{Posting, Transaction};
+use crate::currency::find_currency;
+use crate::rates::Rates;
+use crate::settings::Settings;
+use crate::store::{SqliteStore, TransactionFilter};
+use crate::txn::parse_date;
+
+/// The category spending without a Plaid personal finance category is
+/// reported under.
+const UNCATEGORIZED: &str = "UNCATEGORIZED";
+
+/// Money spent per category in the reporting currency.
+#[derive(Debug, PartialEq)]
+struct Spending {
+    /// Categories with the most spending first.
+    categories: Vec<(String, Money<'static, Currency>)>,
+    total: Money<'static, Currency>,
+    /// Postings left out because they couldn't be converted to the reporting
+    /// currency.
+    skipped: usize,
+}
+
+/// Sums money leaving tracked accounts by the transaction's category.
+/// Transactions moving money between two tracked accounts aren't spending and
+/// are left out, as are postings in currencies without a rate to `base`.
+fn spending_by_category(
+    txns: &[Transaction],
+    categories: &HashMap<Ulid, String>,
+    accounts: &HashSet<&str>,
+    rates: &Rates,
+    base: &'static Currency,
+) -> Spending {
+    let mut totals: HashMap<&str, Decimal> = HashMap::new();
+    let mut skipped = 0;
+    for txn in txns {
+        let posting = match tracked_posting(txn, accounts) {
+            Some(posting) if posting.amount.is_sign_negative() => posting,
+            _ => continue,
+        };
+
+        match convert(posting, rates, base) {
+            Some(amount) => {
+                let category = categories
+                    .get(&txn.id)
+                    .map(String::as_str)
+                    .unwrap_or(UNCATEGORIZED);
+                *totals.entry(category).or_default() -= amount;
+            }
+            None => skipped += 1,
+        }
+    }
+
+    let mut categories: Vec<_> = totals
+        .into_iter()
+        .map(|(category, amount)| (category.to_string(), amount))
+        .collect();
+    categories.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+
+    Spending {
+        total: Money::from_decimal(categories.iter().map(|(_, amount)| *amount).sum(), base),
+        categories: categories
+            .into_iter()
+            .map(|(category, amount)| (category, Money::from_decimal(amount, base)))
+            .collect(),
+        skipped,
+    }
+}
+
+/// Money moved in and out of tracked accounts during a month, in the
+/// reporting currency.
+#[derive(Debug, PartialEq)]
+struct MonthlyCashflow {
+    /// The month formatted as YYYY-MM.
+    month: String,
+    inflow: Money<'static, Currency>,
+    outflow: Money<'static, Currency>,
+}
+
+impl MonthlyCashflow {
+    fn net(&self) -> Money<'static, Currency> {
+        Money::from_decimal(
+            *self.inflow.amount() - *self.outflow.amount(),
+            self.inflow.currency(),
+        )
+    }
+}
+
+/// Buckets money entering and leaving tracked accounts by month, oldest
+/// first. Whether money came in or went out is read from the posting to the
+/// tracked account, transfers between two tracked accounts are neither.
+/// Returns the number of postings that couldn't be converted to `base`
+/// alongside the months.
+fn cashflow(
+    txns: &[Transaction],
+    accounts: &HashSet<&str>,
+    rates: &Rates,
+    base: &'static Currency,
+) -> (Vec<MonthlyCashflow>, usize) {
+    let mut months: BTreeMap<String, (Decimal, Decimal)> = BTreeMap::new();
+    let mut skipped = 0;
+    for txn in txns {
+        let posting = match tracked_posting(txn, accounts) {
+            Some(posting) => posting,
+            None => continue,
+        };
+        let amount = match convert(posting, rates, base) {
+            Some(amount) => amount,
+            None => {
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let (inflow, outflow) = months
+            .entry(txn.date.format("%Y-%m").to_string())
+            .or_default();
+        if amount.is_sign_negative() {
+            *outflow -= amount;
+        } else {
+            *inflow += amount;
+        }
+    }
+
+    let months = months
+        .into_iter()
+        .map(|(month, (inflow, outflow))| MonthlyCashflow {
+            month,
+            inflow: Money::from_decimal(inflow, base),
+            outflow: Money::from_decimal(outflow, base),
+        })
+        .collect();
+
+    (months, skipped)
+}
+
+/// The posting moving money in or out of a tracked account, `None` for
+/// transactions touching no tracked account or moving money between two.
+fn tracked_posting<'a>(txn: &'a Transaction, accounts: &HashSet<&str>) -> Option<&'a Posting> {
+    let mut tracked = txn
+        .postings
+        .iter()
+        .filter(|p| accounts.contains(p.account.as_str()));
+    match (tracked.next(), tracked.next()) {
+        (Some(posting), None) => Some(posting),
+        _ => None,
+    }
+}
+
+/// The posting's amount in `base`, `None` when there's no rate for it.
+fn convert(posting: &Posting, rates: &Rates, base: &'static Currency) -> Option<Decimal> {
+    let currency = iso::find(&posting.currency)?;
+    rates
+        .convert(&Money::from_decimal(posting.amount, currency), base)
+        .map(|money| *money.amount())
+}
+
+/// The first day of the month `months - 1` months before `today`'s, so the
+/// window covers `months` calendar months including the current one.
+fn window_start(today: NaiveDate, months: u32) -> NaiveDate {
+    let months_back = months.saturating_sub(1) as i32;
+    let index = today.year() * 12 + today.month0() as i32 - months_back;
+
+    NaiveDate::from_ymd_opt(index.div_euclid(12), index.rem_euclid(12) as u32 + 1, 1)
+        .expect("first of the month is a valid date")
+}
+
+async fn print_cashflow(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    let months: Option<u32> = matches.value_of("months").map(str::parse).transpose()?;
+    let filter = TransactionFilter {
+        since: months
+            .filter(|months| *months > 0)
+            .map(|months| window_start(Utc::now().date_naive(), months)),
+        ..TransactionFilter::default()
+    };
+    let rates = Rates::new(&settings.rates)?;
+    let base = find_currency(&settings.base_currency)?;
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let accounts = store.accounts().list().await?;
+    let accounts: HashSet<&str> = accounts.iter().map(|a| a.id.as_str()).collect();
+    let txns = store.txns().list(&filter).await?;
+
+    let (months, skipped) = cashflow(&txns, &accounts, &rates, base);
+
+    let mut tw = TabWriter::new(std::io::stdout().lock());
+    writeln!(tw, "Month\tInflow\tOutflow\tNet")?;
+    for month in &months {
+        writeln!(
+            tw,
+            "{}\t{}\t{}\t{}",
+            month.month,
+            month.inflow,
+            month.outflow,
+            month.net()
+        )?;
+    }
+    tw.flush()?;
+
+    if skipped > 0 {
+        println!(
+            "Skipped {} transactions in currencies without a rate to {}, add one under [rates].",
+            skipped, base.iso_alpha_code
+        );
+    }
+
+    Ok(())
+}
+
+/// Spending of stored transactions matching `filter`, by the personal finance
+/// category Plaid reported for each.
+async fn stored_spending(
+    store: &mut SqliteStore,
+    filter: &TransactionFilter,
+    rates: &Rates,
+    base: &'static Currency,
+) -> Result<Spending> {
+    let accounts = store.accounts().list().await?;
+    let accounts: HashSet<&str> = accounts.iter().map(|a| a.id.as_str()).collect();
+    let txns = store.txns().list(filter).await?;
+    let categories = store.txns().primary_categories(filter).await?;
+
+    Ok(spending_by_category(
+        &txns,
+        &categories,
+        &accounts,
+        rates,
+        base,
+    ))
+}
+
+async fn spending(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    let filter = TransactionFilter {
+        since: parse_date(matches.value_of("since"))?,
+        until: parse_date(matches.value_of("until"))?,
+        ..TransactionFilter::default()
+    };
+    if let (Some(since), Some(until)) = (filter.since, filter.until) {
+        if since > until {
+            return Err(anyhow!("--since {} is after --until {}", since, until));
+        }
+    }
+    let rates = Rates::new(&settings.rates)?;
+    let base = find_currency(&settings.base_currency)?;
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let spending = stored_spending(&mut store, &filter, &rates, base).await?;
+
+    let mut tw = TabWriter::new(std::io::stdout().lock());
+    writeln!(tw, "Category\tAmount")?;
+    for (category, amount) in &spending.categories {
+        writeln!(tw, "{}\t{}", category, amount)?;
+    }
+    writeln!(tw, "Total\t{}", spending.total)?;
+    tw.flush()?;
+
+    if spending.skipped > 0 {
+        println!(
+            "Skipped {} transactions in currencies without a rate to {}, add one under [rates].",
+            spending.skipped, base.iso_alpha_code
+        );
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()> {
+    match matches.subcommand() {
+        Some(("spending", spending_matches)) => spending(settings, spending_matches).await,
+        Some(("cashflow", cashflow_matches)) => print_cashflow(settings, cashflow_matches).await,
+        None => unreachable!("subcommand is required"),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Status;
+    use crate::plaid::mock::{mock_account, MockPlaid};
+    use crate::plaid::sync::SyncClient;
+    use crate::settings::test_settings;
+    use crate::store::test_support::{TestStore, TransactionBuilder};
+    use crate::txn::{sync_link, LinkSyncResult, PullOptions};
+
+    fn txn(postings: &[(&str, i64, &str)]) -> Transaction {
+        dated(1, 5, postings)
+    }
+
+    fn dated(month: u32, day: u32, postings: &[(&str, i64, &str)]) -> Transaction {
+        Transaction {
+            id: Ulid::new(),
+            status: Status::Resolved,
+            date: NaiveDate::from_ymd_opt(2023, month, day).unwrap(),
+            payee: None,
+            narration: "Purchase".into(),
+            postings: postings
+                .iter()
+                .map(|(account, amount, currency)| Posting {
+                    account: account.to_string(),
+                    amount: Decimal::new(*amount, 0),
+                    currency: currency.to_string(),
+                })
+                .collect(),
+            tags: vec![],
+            meta: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn sums_outflows_by_category() {
+        let accounts = HashSet::from(["checking", "savings"]);
+        let rates = Rates::new(&HashMap::from([(
+            "EUR_USD".to_string(),
+            Decimal::new(2, 0),
+        )]))
+        .unwrap();
+        let coffee = txn(&[
+            ("checking", -5, "USD"),
+            ("Expenses:Uncategorized", 5, "USD"),
+        ]);
+        let flight = txn(&[
+            ("checking", -100, "EUR"),
+            ("Expenses:Uncategorized", 100, "EUR"),
+        ]);
+        let lunch = txn(&[
+            ("checking", -12, "USD"),
+            ("Expenses:Uncategorized", 12, "USD"),
+        ]);
+        let txns = vec![
+            coffee.clone(),
+            flight.clone(),
+            lunch,
+            // Income, transfers, and currencies without a rate aren't counted.
+            txn(&[
+                ("checking", 1000, "USD"),
+                ("Income:Uncategorized", -1000, "USD"),
+            ]),
+            txn(&[("checking", -50, "USD"), ("savings", 50, "USD")]),
+            txn(&[
+                ("checking", -7, "GBP"),
+                ("Expenses:Uncategorized", 7, "GBP"),
+            ]),
+        ];
+        let categories = HashMap::from([
+            (coffee.id, "FOOD_AND_DRINK".to_string()),
+            (flight.id, "TRAVEL".to_string()),
+        ]);
+
+        let spending = spending_by_category(&txns, &categories, &accounts, &rates, iso::USD);
+
+        assert_eq!(
+            spending,
+            Spending {
+                categories: vec![
+                    ("TRAVEL".into(), Money::from_major(200, iso::USD)),
+                    (UNCATEGORIZED.into(), Money::from_major(12, iso::USD)),
+                    ("FOOD_AND_DRINK".into(), Money::from_major(5, iso::USD)),
+                ],
+                total: Money::from_major(217, iso::USD),
+                skipped: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn spending_uses_categories_of_synced_transactions() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let txn = TransactionBuilder::new()
+            .account_id("mock-account")
+            .category("FOOD_AND_DRINK", "FOOD_AND_DRINK_COFFEE")
+            .build();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_accounts(vec![mock_account("mock-account")])
+                .with_transactions(vec![txn.source], "cursor-1"),
+        );
+        let opts = PullOptions {
+            accounts_first: true,
+            ..Default::default()
+        };
+        let mut result = LinkSyncResult::new(&link);
+        sync_link(&settings, &client, store.db(), link, &opts, &mut result)
+            .await
+            .unwrap();
+
+        let spending = stored_spending(
+            store.db(),
+            &TransactionFilter::default(),
+            &Rates::default(),
+            iso::USD,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            spending.categories,
+            vec![("FOOD_AND_DRINK".into(), Money::from_major(33, iso::USD))]
+        );
+    }
+
+    #[test]
+    fn buckets_cashflow_by_month() {
+        let accounts = HashSet::from(["checking", "card"]);
+        let salary = [
+            ("checking", 1000, "USD"),
+            ("Income:Uncategorized", -1000, "USD"),
+        ];
+        let groceries = [("card", -40, "USD"), ("Expenses:Uncategorized", 40, "USD")];
+        // Paying the card off moves money between tracked accounts.
+        let payment = [("checking", -40, "USD"), ("card", 40, "USD")];
+        let rent = [
+            ("checking", -25, "USD"),
+            ("Expenses:Uncategorized", 25, "USD"),
+        ];
+        let souvenir = [
+            ("checking", -9, "JPY"),
+            ("Expenses:Uncategorized", 9, "JPY"),
+        ];
+        let txns = vec![
+            dated(1, 2, &salary),
+            dated(1, 3, &groceries),
+            dated(1, 20, &payment),
+            dated(2, 2, &rent),
+            dated(2, 3, &souvenir),
+        ];
+
+        let (months, skipped) = cashflow(&txns, &accounts, &Rates::default(), iso::USD);
+
+        assert_eq!(skipped, 1);
+        assert_eq!(
+            months,
+            vec![
+                MonthlyCashflow {
+                    month: "2023-01".into(),
+                    inflow: Money::from_major(1000, iso::USD),
+                    outflow: Money::from_major(40, iso::USD),
+                },
+                MonthlyCashflow {
+                    month: "2023-02".into(),
+                    inflow: Money::from_major(0, iso::USD),
+                    outflow: Money::from_major(25, iso::USD),
+                },
+            ]
+        );
+        assert_eq!(months[0].net(), Money::from_major(960, iso::USD));
+    }
+
+    #[test]
+    fn window_covers_whole_months() {
+        let today = NaiveDate::from_ymd_opt(2023, 3, 15).unwrap();
+
+        assert_eq!(
+            window_start(today, 1),
+            NaiveDate::from_ymd_opt(2023, 3, 1).unwrap()
+        );
+        assert_eq!(
+            window_start(today, 3),
+            NaiveDate::from_ymd_opt(2023, 1, 1).unwrap()
+        );
+        assert_eq!(
+            window_start(today, 4),
+            NaiveDate::from_ymd_opt(2022, 12, 1).unwrap()
+        );
+    }
+}
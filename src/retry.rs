@@ -0,0 +1,135 @@
+use std::fmt::Display;
+use std::future::Future;
+
+use serde::Deserialize;
+use tokio::time::{sleep, Duration};
+use tracing::warn;
+
+/// Controls how many times a failing upstream call is attempted and how long to
+/// wait between attempts. The delay doubles after every failed attempt.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff_ms: 500,
+        }
+    }
+}
+
+pub async fn with_retry<F, Fut, T, E>(policy: &RetryPolicy, call: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    with_retry_when(policy, |_| true, call).await
+}
+
+/// Like [`with_retry`] but only retries errors `retryable` accepts, any other
+/// error is returned immediately.
+pub async fn with_retry_when<F, Fut, T, E, P>(
+    policy: &RetryPolicy,
+    retryable: P,
+    mut call: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+    P: Fn(&E) -> bool,
+{
+    let mut attempt = 1;
+    loop {
+        match call().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < policy.attempts && retryable(&err) => {
+                let delay = policy.backoff_ms.saturating_mul(1 << (attempt - 1).min(16));
+                warn!(
+                    "Upstream call failed, retrying in {}ms. attempt={} error={}",
+                    delay, attempt, err
+                );
+
+                sleep(Duration::from_millis(delay)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let calls = Cell::new(0);
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff_ms: 0,
+        };
+
+        let result: Result<u32, String> = with_retry(&policy, || {
+            calls.set(calls.get() + 1);
+            let n = calls.get();
+            async move {
+                if n < 3 {
+                    Err("transient".to_string())
+                } else {
+                    Ok(n)
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_attempts() {
+        let calls = Cell::new(0);
+        let policy = RetryPolicy {
+            attempts: 2,
+            backoff_ms: 0,
+        };
+
+        let result: Result<(), String> = with_retry(&policy, || {
+            calls.set(calls.get() + 1);
+            async { Err("permanent".to_string()) }
+        })
+        .await;
+
+        assert_eq!(result, Err("permanent".to_string()));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_are_not_retried() {
+        let calls = Cell::new(0);
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff_ms: 0,
+        };
+
+        let result: Result<(), String> = with_retry_when(
+            &policy,
+            |err: &String| err == "transient",
+            || {
+                calls.set(calls.get() + 1);
+                async { Err("permanent".to_string()) }
+            },
+        )
+        .await;
+
+        assert_eq!(result, Err("permanent".to_string()));
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -1,24 +1,113 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use config::{Config, Environment, File};
 use rplaid::client;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 
+use crate::core::AccountNameSource;
+use crate::currency::find_currency;
+use crate::retry::RetryPolicy;
+use crate::upstream::plaid::TransactionField;
 use crate::CLIENT_NAME;
 
 const COUNTRY_CODES: [&str; 1] = ["US"];
 const CONFIG_NAME: &str = "config.toml";
+const BASE_CURRENCY: &str = "USD";
+const UNCATEGORIZED_EXPENSES: &str = "Expenses:Uncategorized";
+const UNCATEGORIZED_INCOME: &str = "Income:Uncategorized";
+const LINK_ADDR: &str = "127.0.0.1:0";
+/// The most transactions Plaid returns in one `/transactions/sync` page.
+pub(crate) const MAX_SYNC_PAGE_SIZE: u32 = 500;
+const CLIENT_ID_ENV: &str = "CLERK_PLAID_CLIENT_ID";
+const SECRET_ENV: &str = "CLERK_PLAID_SECRET";
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub db_file: String,
     pub plaid: Plaid,
+    pub base_currency: String,
+    pub default_currency: String,
+    pub skip_degraded_links: bool,
+    pub narration_max_length: Option<usize>,
+    pub trim_narration: bool,
+    pub payee_fields: Vec<TransactionField>,
+    pub narration_fields: Vec<TransactionField>,
+    pub soft_delete: bool,
+    pub store_pending: bool,
+    #[serde(default)]
+    pub account_name_source: AccountNameSource,
+    pub ledger: Ledger,
+    #[serde(default)]
+    pub rates: HashMap<String, Decimal>,
+    /// Address the `clerk link` server listens on, port 0 picks any free
+    /// port.
+    pub link_addr: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Ledger {
+    /// Writes a generated-by comment at the top of exported journals.
+    pub header: bool,
+    pub preamble: Option<String>,
+    /// Counter-account for outflows no other account could be found for.
+    pub uncategorized_expenses: String,
+    /// Counter-account for inflows no other account could be found for.
+    pub uncategorized_income: String,
+}
+
+/// Where the Plaid client id and secret are read from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialsSource {
+    /// The `client_id` and `secret` keys of the configuration file.
+    #[default]
+    File,
+    /// The `CLERK_PLAID_CLIENT_ID` and `CLERK_PLAID_SECRET` environment
+    /// variables.
+    Env,
+    /// The `client_id` key of the configuration file and a secret stored in
+    /// the OS keychain under the "clerk" service and the client id.
+    Keyring,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Plaid {
     pub country_codes: Vec<String>,
+    #[serde(default)]
+    pub credentials_source: CredentialsSource,
+    #[serde(default)]
     pub client_id: String,
+    #[serde(default)]
     pub secret: String,
     pub env: client::Environment,
+    pub include_original_description: bool,
+    pub include_personal_finance_category: bool,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// The application name shown in the Plaid Link UI.
+    pub client_name: String,
+    /// Products new links request access to, e.g. "transactions".
+    pub products: Vec<String>,
+    /// Days cached institution names are used before fetching them again.
+    pub institutions_max_age_days: i64,
+    /// Key used to sign the link flow's state, a key is generated for each
+    /// `clerk link` run when unset.
+    pub state_secret: Option<String>,
+    /// Accept unsigned link flow state, for local development only.
+    pub allow_unsigned_state: bool,
+    /// Links whose balances are fetched at the same time.
+    pub max_concurrent_requests: usize,
+    /// Transactions requested per sync page, between 1 and
+    /// [`MAX_SYNC_PAGE_SIZE`].
+    pub sync_page_size: u32,
+    /// Proxy requests to Plaid are sent through, e.g.
+    /// "http://proxy.internal:3128".
+    pub proxy: Option<String>,
+    /// PEM file of CA certificates trusted for requests to Plaid, for proxies
+    /// intercepting TLS.
+    pub ca_certificate: Option<PathBuf>,
 }
 
 impl Settings {
@@ -26,6 +115,26 @@ impl Settings {
         let mut s = Config::builder()
             .set_default("db_file", default_data_path())?
             .set_default("plaid.country_codes", COUNTRY_CODES.to_vec())?
+            .set_default("plaid.include_original_description", false)?
+            .set_default("plaid.include_personal_finance_category", true)?
+            .set_default("plaid.client_name", CLIENT_NAME)?
+            .set_default("plaid.products", vec!["transactions"])?
+            .set_default("plaid.institutions_max_age_days", 30)?
+            .set_default("plaid.allow_unsigned_state", false)?
+            .set_default("plaid.max_concurrent_requests", 4)?
+            .set_default("plaid.sync_page_size", MAX_SYNC_PAGE_SIZE)?
+            .set_default("base_currency", BASE_CURRENCY)?
+            .set_default("default_currency", BASE_CURRENCY)?
+            .set_default("skip_degraded_links", true)?
+            .set_default("trim_narration", false)?
+            .set_default("payee_fields", vec!["merchant_name"])?
+            .set_default("narration_fields", vec!["name"])?
+            .set_default("soft_delete", false)?
+            .set_default("store_pending", false)?
+            .set_default("ledger.header", true)?
+            .set_default("ledger.uncategorized_expenses", UNCATEGORIZED_EXPENSES)?
+            .set_default("ledger.uncategorized_income", UNCATEGORIZED_INCOME)?
+            .set_default("link_addr", LINK_ADDR)?
             .add_source(Environment::with_prefix("CLERK"));
 
         if let Some(path) = config_path {
@@ -34,10 +143,95 @@ impl Settings {
             s = s.add_source(File::with_name(&default_config_path()));
         }
 
-        s.build()?.try_deserialize()
+        let mut settings: Self = s.build()?.try_deserialize()?;
+        settings.plaid.load_credentials()?;
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), config::ConfigError> {
+        if !(1..=MAX_SYNC_PAGE_SIZE).contains(&self.plaid.sync_page_size) {
+            return Err(config::ConfigError::Message(format!(
+                "plaid.sync_page_size must be between 1 and {}, got {}",
+                MAX_SYNC_PAGE_SIZE, self.plaid.sync_page_size
+            )));
+        }
+
+        for (key, code) in [
+            ("base_currency", &self.base_currency),
+            ("default_currency", &self.default_currency),
+        ] {
+            if find_currency(code).is_err() {
+                return Err(config::ConfigError::Message(format!(
+                    "{} must be an ISO 4217 currency code, got {}",
+                    key, code
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Plaid {
+    /// Replaces the client id and secret with the ones from
+    /// `credentials_source`.
+    fn load_credentials(&mut self) -> Result<(), config::ConfigError> {
+        self.load_credentials_with(|name| std::env::var(name).ok())
+    }
+
+    /// Same as [`Plaid::load_credentials`], reading environment variables
+    /// through `env`.
+    fn load_credentials_with<F>(&mut self, env: F) -> Result<(), config::ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        match self.credentials_source {
+            CredentialsSource::File => {}
+            CredentialsSource::Env => {
+                self.client_id = env_credential(&env, CLIENT_ID_ENV)?;
+                self.secret = env_credential(&env, SECRET_ENV)?;
+            }
+            CredentialsSource::Keyring => {
+                if self.client_id.is_empty() {
+                    return Err(config::ConfigError::Message(
+                        "plaid.client_id must be set to read the secret from the keyring".into(),
+                    ));
+                }
+                self.secret = keyring::Entry::new(CLIENT_NAME, &self.client_id)
+                    .and_then(|entry| entry.get_password())
+                    .map_err(|e| {
+                        config::ConfigError::Message(format!(
+                            "failed to read the Plaid secret for {} from the keyring: {}",
+                            self.client_id, e
+                        ))
+                    })?;
+            }
+        }
+
+        if self.client_id.is_empty() || self.secret.is_empty() {
+            return Err(config::ConfigError::Message(
+                "plaid.client_id and plaid.secret must be set".into(),
+            ));
+        }
+
+        Ok(())
     }
 }
 
+fn env_credential<F>(env: F, name: &str) -> Result<String, config::ConfigError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    env(name).ok_or_else(|| {
+        config::ConfigError::Message(format!(
+            "{} must be set when plaid.credentials_source is \"env\"",
+            name
+        ))
+    })
+}
+
 fn default_data_path() -> String {
     dirs::data_dir()
         .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| std::env::temp_dir()))
@@ -55,3 +249,102 @@ pub(crate) fn default_config_path() -> String {
         .display()
         .to_string()
 }
+
+#[cfg(test)]
+pub(crate) fn test_settings() -> Settings {
+    Settings {
+        db_file: "sqlite::memory:".into(),
+        plaid: Plaid {
+            country_codes: COUNTRY_CODES.iter().map(|c| c.to_string()).collect(),
+            credentials_source: CredentialsSource::File,
+            client_id: "test-client-id".into(),
+            secret: "test-secret".into(),
+            env: client::Environment::Sandbox,
+            include_original_description: false,
+            include_personal_finance_category: true,
+            retry: RetryPolicy::default(),
+            client_name: CLIENT_NAME.into(),
+            products: vec!["transactions".into()],
+            institutions_max_age_days: 30,
+            state_secret: None,
+            allow_unsigned_state: false,
+            max_concurrent_requests: 4,
+            sync_page_size: MAX_SYNC_PAGE_SIZE,
+            proxy: None,
+            ca_certificate: None,
+        },
+        base_currency: BASE_CURRENCY.into(),
+        default_currency: BASE_CURRENCY.into(),
+        skip_degraded_links: true,
+        narration_max_length: None,
+        trim_narration: false,
+        payee_fields: vec![TransactionField::MerchantName],
+        narration_fields: vec![TransactionField::Name],
+        soft_delete: false,
+        store_pending: false,
+        account_name_source: AccountNameSource::default(),
+        ledger: Ledger {
+            header: true,
+            preamble: None,
+            uncategorized_expenses: UNCATEGORIZED_EXPENSES.into(),
+            uncategorized_income: UNCATEGORIZED_INCOME.into(),
+        },
+        rates: HashMap::new(),
+        link_addr: LINK_ADDR.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_page_size_within_plaid_limits() {
+        let mut settings = test_settings();
+        assert!(settings.validate().is_ok());
+
+        settings.plaid.sync_page_size = 0;
+        assert!(settings.validate().is_err());
+        settings.plaid.sync_page_size = MAX_SYNC_PAGE_SIZE + 1;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn currencies_must_be_known() {
+        let mut settings = test_settings();
+        settings.default_currency = "XYZ".into();
+        assert!(settings.validate().is_err());
+
+        let mut settings = test_settings();
+        settings.base_currency = "XYZ".into();
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn credentials_from_env() {
+        let mut settings = test_settings();
+        settings.plaid.credentials_source = CredentialsSource::Env;
+        let env = |name: &str| match name {
+            CLIENT_ID_ENV => Some("env-client-id".to_string()),
+            SECRET_ENV => Some("env-secret".to_string()),
+            _ => None,
+        };
+
+        settings.plaid.load_credentials_with(env).unwrap();
+
+        assert_eq!(settings.plaid.client_id, "env-client-id");
+        assert_eq!(settings.plaid.secret, "env-secret");
+
+        settings.plaid.secret = String::new();
+        assert!(settings.plaid.load_credentials_with(|_| None).is_err());
+    }
+
+    #[test]
+    fn file_credentials_are_required() {
+        let mut settings = test_settings();
+        assert!(settings.plaid.load_credentials().is_ok());
+
+        settings.plaid.secret = String::new();
+        assert!(settings.plaid.load_credentials().is_err());
+    }
+}
@@ -2,7 +2,7 @@ use sea_query::{Expr, Iden, Query, SqliteQueryBuilder};
 use sea_query_binder::SqlxBinder;
 use sqlx::Row;
 
-use super::{Result, SqliteStore};
+use super::{Error, Result, SqliteStore};
 use crate::core::Account;
 
 #[derive(Iden)]
@@ -11,7 +11,9 @@ enum Accounts {
     Id,
     ItemId,
     Name,
+    OfficialName,
     Type,
+    Alias,
 }
 
 pub struct Store<'a>(&'a mut SqliteStore);
@@ -21,11 +23,16 @@ impl<'a> Store<'a> {
         Self(store)
     }
 
-    #[allow(dead_code)]
     pub async fn by_id(&mut self, id: &str) -> Result<Option<Account>> {
         let (query, values) = Query::select()
             .from(Accounts::Table)
-            .columns([Accounts::Id, Accounts::Name, Accounts::Type])
+            .columns([
+                Accounts::Id,
+                Accounts::Name,
+                Accounts::OfficialName,
+                Accounts::Type,
+                Accounts::Alias,
+            ])
             .and_where(Expr::col(Accounts::Id).eq(id))
             .build_sqlx(SqliteQueryBuilder);
 
@@ -35,14 +42,50 @@ impl<'a> Store<'a> {
             .map(|row| Account {
                 id: row.try_get("id").unwrap(),
                 name: row.try_get("name").unwrap(),
+                official_name: row.try_get("official_name").unwrap(),
                 ty: row.try_get("type").unwrap(),
+                alias: row.try_get("alias").unwrap(),
             }))
     }
 
+    pub async fn list(&mut self) -> Result<Vec<Account>> {
+        let (query, values) = Query::select()
+            .from(Accounts::Table)
+            .columns([
+                Accounts::Id,
+                Accounts::Name,
+                Accounts::OfficialName,
+                Accounts::Type,
+                Accounts::Alias,
+            ])
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Account {
+                id: row.try_get("id").unwrap(),
+                name: row.try_get("name").unwrap(),
+                official_name: row.try_get("official_name").unwrap(),
+                ty: row.try_get("type").unwrap(),
+                alias: row.try_get("alias").unwrap(),
+            })
+            .collect())
+    }
+
     pub async fn by_item(&mut self, id: &str) -> Result<Vec<Account>> {
         let (query, values) = Query::select()
             .from(Accounts::Table)
-            .columns([Accounts::Id, Accounts::Name, Accounts::Type])
+            .columns([
+                Accounts::Id,
+                Accounts::Name,
+                Accounts::OfficialName,
+                Accounts::Type,
+                Accounts::Alias,
+            ])
             .and_where(Expr::col(Accounts::ItemId).eq(id))
             .build_sqlx(SqliteQueryBuilder);
 
@@ -55,7 +98,9 @@ impl<'a> Store<'a> {
             .map(|row| Account {
                 id: row.try_get("id").unwrap(),
                 name: row.try_get("name").unwrap(),
+                official_name: row.try_get("official_name").unwrap(),
                 ty: row.try_get("type").unwrap(),
+                alias: row.try_get("alias").unwrap(),
             })
             .collect())
     }
@@ -67,29 +112,92 @@ impl<'a> Store<'a> {
                 Accounts::Id,
                 Accounts::ItemId,
                 Accounts::Name,
+                Accounts::OfficialName,
                 Accounts::Type,
             ])
             .values_panic(vec![
                 account.id.as_str().into(),
                 item_id.into(),
                 account.name.as_str().into(),
+                account.official_name.as_deref().into(),
                 account.ty.as_str().into(),
             ])
+            // Accounts are saved again whenever they're fetched from
+            // upstream. The alias is only ever set locally, keep it.
+            .on_conflict(
+                sea_query::OnConflict::column(Accounts::Id)
+                    .update_columns([
+                        Accounts::ItemId,
+                        Accounts::Name,
+                        Accounts::OfficialName,
+                        Accounts::Type,
+                    ])
+                    .to_owned(),
+            )
             .build_sqlx(SqliteQueryBuilder);
 
-        sqlx::query_with(&query, values)
-            .execute(&mut self.0.conn.acquire().await?)
-            .await?;
+        self.0.execute(&query, values).await?;
+
+        Ok(())
+    }
+
+    /// Updates the stored copy of `account`, matched by id.
+    pub async fn update(&mut self, account: &Account) -> Result<()> {
+        self.ensure_alias_available(account).await?;
+
+        let (query, values) = Query::update()
+            .table(Accounts::Table)
+            .values(vec![
+                (Accounts::Name, account.name.as_str().into()),
+                (
+                    Accounts::OfficialName,
+                    account.official_name.as_deref().into(),
+                ),
+                (Accounts::Type, account.ty.as_str().into()),
+                (Accounts::Alias, account.alias.as_deref().into()),
+            ])
+            .and_where(Expr::col(Accounts::Id).eq(account.id.as_str()))
+            .build_sqlx(SqliteQueryBuilder);
+
+        self.0.execute(&query, values).await?;
 
         Ok(())
     }
+
+    // Aliases are exported as account names, each must identify exactly one
+    // account.
+    async fn ensure_alias_available(&mut self, account: &Account) -> Result<()> {
+        let alias = match &account.alias {
+            Some(alias) => alias,
+            None => return Ok(()),
+        };
+
+        let (query, values) = Query::select()
+            .from(Accounts::Table)
+            .column(Accounts::Id)
+            .and_where(Expr::col(Accounts::Alias).eq(alias.as_str()))
+            .and_where(Expr::col(Accounts::Id).ne(account.id.as_str()))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let existing = sqlx::query_with(&query, values)
+            .fetch_optional(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        match existing {
+            Some(row) => Err(Error::DuplicateAccountAlias {
+                alias: alias.clone(),
+                account_id: row.try_get("id")?,
+            }),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use rplaid::model::{Account, AccountType, Balance};
 
-    use crate::store::link::tests::TestStore;
+    use crate::store::test_support::TestStore;
 
     #[tokio::test]
     async fn get_account() {
@@ -105,7 +213,7 @@ mod tests {
                     account_id: "account-id".into(),
                     name: "Test Account".into(),
                     r#type: AccountType::Credit,
-                    official_name: None,
+                    official_name: Some("Official Test Account".into()),
                     verification_status: None,
                     subtype: None,
                     mask: None,
@@ -130,5 +238,74 @@ mod tests {
             .unwrap()
             .unwrap();
         assert_eq!(&account.name, "Test Account");
+        assert_eq!(
+            account.official_name.as_deref(),
+            Some("Official Test Account")
+        );
+        assert_eq!(account.alias, None);
+    }
+
+    #[tokio::test]
+    async fn save_updates_existing_accounts() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let mut account = store.new_account(&link.item_id).await;
+        account.alias = Some("Joint Checking".into());
+        store.db().accounts().update(&account).await.unwrap();
+
+        account.name = "Renamed Upstream".into();
+        account.alias = None;
+        store
+            .db()
+            .accounts()
+            .save(&link.item_id, &account)
+            .await
+            .unwrap();
+
+        let stored = store.db().accounts().list().await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].name, "Renamed Upstream");
+        assert_eq!(stored[0].alias.as_deref(), Some("Joint Checking"));
+    }
+
+    #[tokio::test]
+    async fn update_sets_alias() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let mut account = store.new_account(&link.item_id).await;
+
+        account.alias = Some("Joint Checking".into());
+        store.db().accounts().update(&account).await.unwrap();
+
+        let stored = store
+            .db()
+            .accounts()
+            .by_id(&account.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.alias.as_deref(), Some("Joint Checking"));
+        assert_eq!(stored.name, account.name);
+    }
+
+    #[tokio::test]
+    async fn update_rejects_duplicate_alias() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let mut first = store.new_account(&link.item_id).await;
+        let mut second = store.new_account(&link.item_id).await;
+
+        first.alias = Some("Joint Checking".into());
+        store.db().accounts().update(&first).await.unwrap();
+
+        second.alias = first.alias.clone();
+        let err = store.db().accounts().update(&second).await.unwrap_err();
+        assert_eq!(
+            err,
+            super::Error::DuplicateAccountAlias {
+                alias: "Joint Checking".into(),
+                account_id: first.id,
+            }
+        );
     }
 }
@@ -0,0 +1,206 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use sea_query::{Expr, Iden, Order, Query, SelectStatement, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use sqlx::sqlite::SqliteRow;
+use sqlx::Row;
+
+use super::{Result, SqliteStore};
+use crate::core::BalanceSnapshot;
+
+#[derive(Iden)]
+enum BalanceSnapshots {
+    Table,
+    AccountId,
+    TakenAt,
+    Available,
+    Current,
+    Currency,
+}
+
+pub struct Store<'a>(&'a mut SqliteStore);
+
+impl<'a> Store<'a> {
+    pub fn new(store: &'a mut SqliteStore) -> Self {
+        Self(store)
+    }
+
+    pub async fn save(&mut self, snapshot: &BalanceSnapshot) -> Result<()> {
+        let (query, values) = Query::insert()
+            .into_table(BalanceSnapshots::Table)
+            .columns([
+                BalanceSnapshots::AccountId,
+                BalanceSnapshots::TakenAt,
+                BalanceSnapshots::Available,
+                BalanceSnapshots::Current,
+                BalanceSnapshots::Currency,
+            ])
+            .values_panic(vec![
+                snapshot.account_id.as_str().into(),
+                snapshot.taken_at.to_rfc3339().into(),
+                snapshot.available.map(|a| a.to_string()).into(),
+                snapshot.current.map(|c| c.to_string()).into(),
+                snapshot.currency.as_str().into(),
+            ])
+            .build_sqlx(SqliteQueryBuilder);
+
+        self.0.execute(&query, values).await?;
+
+        Ok(())
+    }
+
+    /// Returns the latest snapshot taken on or before `date`.
+    pub async fn as_of(
+        &mut self,
+        account_id: &str,
+        date: NaiveDate,
+    ) -> Result<Option<BalanceSnapshot>> {
+        // Snapshot times are stored as RFC 3339 strings, anything taken on
+        // `date` sorts before the start of the following day.
+        let next_day = (date + Duration::days(1)).format("%Y-%m-%d").to_string();
+        let (query, values) = select()
+            .and_where(Expr::col(BalanceSnapshots::AccountId).eq(account_id))
+            .and_where(Expr::col(BalanceSnapshots::TakenAt).lt(next_day))
+            .order_by(BalanceSnapshots::TakenAt, Order::Desc)
+            .limit(1)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values)
+            .fetch_optional(&mut self.0.conn.acquire().await?)
+            .await?
+            .map(snapshot)
+            .transpose()
+    }
+
+    /// Returns every snapshot recorded for an account, oldest first.
+    pub async fn history(&mut self, account_id: &str) -> Result<Vec<BalanceSnapshot>> {
+        let (query, values) = select()
+            .and_where(Expr::col(BalanceSnapshots::AccountId).eq(account_id))
+            .order_by(BalanceSnapshots::TakenAt, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?
+            .into_iter()
+            .map(snapshot)
+            .collect()
+    }
+}
+
+fn select() -> SelectStatement {
+    Query::select()
+        .from(BalanceSnapshots::Table)
+        .columns([
+            BalanceSnapshots::AccountId,
+            BalanceSnapshots::TakenAt,
+            BalanceSnapshots::Available,
+            BalanceSnapshots::Current,
+            BalanceSnapshots::Currency,
+        ])
+        .take()
+}
+
+fn snapshot(row: SqliteRow) -> Result<BalanceSnapshot> {
+    let decimal = |value: Option<String>| value.as_deref().map(Decimal::from_str).transpose();
+
+    Ok(BalanceSnapshot {
+        account_id: row.try_get("account_id")?,
+        taken_at: DateTime::parse_from_rfc3339(row.try_get("taken_at")?)?.with_timezone(&Utc),
+        available: decimal(row.try_get("available")?)?,
+        current: decimal(row.try_get("current")?)?,
+        currency: row.try_get("currency")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use rust_decimal::Decimal;
+
+    use crate::core::BalanceSnapshot;
+    use crate::store::test_support::TestStore;
+
+    #[tokio::test]
+    async fn as_of() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let account = store.new_account(&link.item_id).await;
+
+        let snapshot = |day, current| BalanceSnapshot {
+            account_id: account.id.clone(),
+            taken_at: Utc.with_ymd_and_hms(2022, 12, day, 12, 0, 0).unwrap(),
+            available: None,
+            current: Some(Decimal::new(current, 0)),
+            currency: "USD".into(),
+        };
+        for s in [snapshot(1, 100), snapshot(15, 200), snapshot(31, 300)] {
+            store.db().balances().save(&s).await.unwrap();
+        }
+
+        let date = |day| NaiveDate::from_ymd_opt(2022, 12, day).unwrap();
+        let as_of = |s: Option<BalanceSnapshot>| s.and_then(|s| s.current);
+        assert_eq!(
+            as_of(
+                store
+                    .db()
+                    .balances()
+                    .as_of(&account.id, date(15))
+                    .await
+                    .unwrap()
+            ),
+            Some(Decimal::new(200, 0))
+        );
+        assert_eq!(
+            as_of(
+                store
+                    .db()
+                    .balances()
+                    .as_of(&account.id, date(20))
+                    .await
+                    .unwrap()
+            ),
+            Some(Decimal::new(200, 0))
+        );
+        assert_eq!(
+            store
+                .db()
+                .balances()
+                .as_of(&account.id, NaiveDate::from_ymd_opt(2022, 11, 30).unwrap())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let account = store.new_account(&link.item_id).await;
+        let other = store.new_account(&link.item_id).await;
+
+        let snapshot = |account_id: &str, day, current| BalanceSnapshot {
+            account_id: account_id.to_string(),
+            taken_at: Utc.with_ymd_and_hms(2022, 12, day, 12, 0, 0).unwrap(),
+            available: None,
+            current: Some(Decimal::new(current, 0)),
+            currency: "USD".into(),
+        };
+        for s in [
+            snapshot(&account.id, 15, 200),
+            snapshot(&account.id, 1, 100),
+            snapshot(&other.id, 1, 50),
+        ] {
+            store.db().balances().save(&s).await.unwrap();
+        }
+
+        let history = store.db().balances().history(&account.id).await.unwrap();
+        assert_eq!(
+            history.iter().map(|s| s.current).collect::<Vec<_>>(),
+            vec![Some(Decimal::new(100, 0)), Some(Decimal::new(200, 0))]
+        );
+    }
+}
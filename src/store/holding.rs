@@ -0,0 +1,209 @@
+use std::str::FromStr;
+
+use chrono::Utc;
+use rust_decimal::Decimal;
+use sea_query::{Expr, Iden, Order, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use sqlx::{Connection, Row};
+
+use super::{Error, Result, SqliteStore};
+use crate::core::Holding;
+
+#[derive(Iden)]
+enum Holdings {
+    Table,
+    AccountId,
+    SecurityId,
+    Name,
+    Ticker,
+    Quantity,
+    Price,
+    Value,
+    Currency,
+    FetchedAt,
+}
+
+pub struct Store<'a>(&'a mut SqliteStore);
+
+impl<'a> Store<'a> {
+    pub fn new(store: &'a mut SqliteStore) -> Self {
+        Self(store)
+    }
+
+    /// Replaces the holdings stored for `account_ids` with `holdings`, any
+    /// position no longer reported is dropped.
+    pub async fn replace(&mut self, account_ids: &[String], holdings: &[Holding]) -> Result<()> {
+        let (delete, delete_values) = Query::delete()
+            .from_table(Holdings::Table)
+            .and_where(Expr::col(Holdings::AccountId).is_in(account_ids.iter().map(String::as_str)))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let fetched_at = Utc::now().to_rfc3339();
+        let inserts: Vec<_> = holdings
+            .iter()
+            .map(|holding| {
+                Query::insert()
+                    .into_table(Holdings::Table)
+                    .columns([
+                        Holdings::AccountId,
+                        Holdings::SecurityId,
+                        Holdings::Name,
+                        Holdings::Ticker,
+                        Holdings::Quantity,
+                        Holdings::Price,
+                        Holdings::Value,
+                        Holdings::Currency,
+                        Holdings::FetchedAt,
+                    ])
+                    .values_panic(vec![
+                        holding.account_id.as_str().into(),
+                        holding.security_id.as_str().into(),
+                        holding.name.as_deref().into(),
+                        holding.ticker.as_deref().into(),
+                        holding.quantity.to_string().into(),
+                        holding.price.to_string().into(),
+                        holding.value.to_string().into(),
+                        holding.currency.as_str().into(),
+                        fetched_at.as_str().into(),
+                    ])
+                    .build_sqlx(SqliteQueryBuilder)
+            })
+            .collect();
+
+        self.0
+            .conn
+            .acquire()
+            .await?
+            .transaction(|conn| {
+                Box::pin(async move {
+                    sqlx::query_with(&delete, delete_values)
+                        .execute(&mut *conn)
+                        .await?;
+
+                    for (query, values) in inserts {
+                        sqlx::query_with(&query, values).execute(&mut *conn).await?;
+                    }
+
+                    Ok::<(), Error>(())
+                })
+            })
+            .await
+    }
+
+    /// Lists every stored holding grouped by account, largest positions first.
+    pub async fn list(&mut self) -> Result<Vec<Holding>> {
+        let (query, values) = Query::select()
+            .from(Holdings::Table)
+            .columns([
+                Holdings::AccountId,
+                Holdings::SecurityId,
+                Holdings::Name,
+                Holdings::Ticker,
+                Holdings::Quantity,
+                Holdings::Price,
+                Holdings::Value,
+                Holdings::Currency,
+            ])
+            .order_by(Holdings::AccountId, Order::Asc)
+            .order_by(Holdings::SecurityId, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut holdings = rows
+            .into_iter()
+            .map(|row| {
+                Ok(Holding {
+                    account_id: row.try_get("account_id")?,
+                    security_id: row.try_get("security_id")?,
+                    name: row.try_get("name")?,
+                    ticker: row.try_get("ticker")?,
+                    quantity: Decimal::from_str(row.try_get("quantity")?)?,
+                    price: Decimal::from_str(row.try_get("price")?)?,
+                    value: Decimal::from_str(row.try_get("value")?)?,
+                    currency: row.try_get("currency")?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Values are stored as text, which doesn't sort numerically.
+        holdings.sort_by(|a, b| a.account_id.cmp(&b.account_id).then(b.value.cmp(&a.value)));
+
+        Ok(holdings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::core::Holding;
+    use crate::store::test_support::TestStore;
+
+    fn holding(account_id: &str, security_id: &str, value: i64) -> Holding {
+        Holding {
+            account_id: account_id.to_string(),
+            security_id: security_id.to_string(),
+            name: Some(format!("Security {}", security_id)),
+            ticker: None,
+            quantity: Decimal::new(1, 0),
+            price: Decimal::new(value, 0),
+            value: Decimal::new(value, 0),
+            currency: "USD".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replace_drops_positions_no_longer_held() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let account = store.new_account(&link.item_id).await;
+        let other = store.new_account(&link.item_id).await;
+
+        store
+            .db()
+            .holdings()
+            .replace(
+                &[account.id.clone(), other.id.clone()],
+                &[
+                    holding(&account.id, "sold", 5),
+                    holding(&account.id, "kept", 10),
+                    holding(&other.id, "other", 100),
+                ],
+            )
+            .await
+            .unwrap();
+        store
+            .db()
+            .holdings()
+            .replace(
+                &[account.id.clone()],
+                &[
+                    holding(&account.id, "kept", 9),
+                    holding(&account.id, "new", 20),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let held: Vec<_> = store
+            .db()
+            .holdings()
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|h| h.account_id == account.id)
+            .map(|h| (h.security_id, h.value))
+            .collect();
+        assert_eq!(
+            held,
+            vec![
+                ("new".to_string(), Decimal::new(20, 0)),
+                ("kept".to_string(), Decimal::new(9, 0)),
+            ]
+        );
+        assert_eq!(store.db().holdings().list().await.unwrap().len(), 3);
+    }
+}
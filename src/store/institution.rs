@@ -1,4 +1,5 @@
-use sea_query::{Iden, Query, SqliteQueryBuilder};
+use chrono::{DateTime, Utc};
+use sea_query::{func::Func, types::Alias, Expr, Iden, Query, SqliteQueryBuilder};
 use sea_query_binder::SqlxBinder;
 use sqlx::{FromRow, Row};
 
@@ -9,6 +10,7 @@ enum Institutions {
     Table,
     Id,
     Name,
+    LastSynced,
 }
 
 pub struct Institution {
@@ -55,22 +57,89 @@ impl<'a> Store<'a> {
         Ok(institutions)
     }
 
+    /// Returns when institutions were last fetched from upstream, `None` if
+    /// they never have been.
+    pub async fn last_synced(&mut self) -> Result<Option<DateTime<Utc>>> {
+        let (query, values) = Query::select()
+            .expr_as(
+                Func::max(Expr::col(Institutions::LastSynced)),
+                Alias::new("last_synced"),
+            )
+            .from(Institutions::Table)
+            .build_sqlx(SqliteQueryBuilder);
+
+        let last_synced: Option<String> = sqlx::query_with(&query, values)
+            .fetch_one(&mut self.0.conn.acquire().await?)
+            .await?
+            .try_get("last_synced")?;
+
+        Ok(last_synced
+            .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+            .map(|at| at.with_timezone(&Utc)))
+    }
+
+    /// Saves an institution fetched from upstream, marking it as synced now.
     pub async fn save(&mut self, ins: &Institution) -> Result<()> {
         let (query, values) = Query::insert()
             .into_table(Institutions::Table)
-            .columns([Institutions::Id, Institutions::Name])
-            .values_panic(vec![ins.id.as_str().into(), ins.name.as_str().into()])
+            .columns([
+                Institutions::Id,
+                Institutions::Name,
+                Institutions::LastSynced,
+            ])
+            .values_panic(vec![
+                ins.id.as_str().into(),
+                ins.name.as_str().into(),
+                Utc::now().to_rfc3339().into(),
+            ])
             .on_conflict(
                 sea_query::OnConflict::column(Institutions::Id)
-                    .update_column(Institutions::Name)
+                    .update_columns([Institutions::Name, Institutions::LastSynced])
                     .to_owned(),
             )
             .build_sqlx(SqliteQueryBuilder);
 
-        sqlx::query_with(&query, values)
-            .execute(&mut self.0.conn.acquire().await?)
-            .await?;
+        self.0.execute(&query, values).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Institution;
+    use crate::store::test_support::TestStore;
+
+    #[tokio::test]
+    async fn save_updates_existing_institutions() {
+        let mut store = TestStore::new().await;
+        let institution = |name: &str| Institution {
+            id: "ins_1".into(),
+            name: name.into(),
+        };
+
+        store
+            .db()
+            .institutions()
+            .save(&institution("First Platypus Bank"))
+            .await
+            .unwrap();
+        store
+            .db()
+            .institutions()
+            .save(&institution("Platypus Bank"))
+            .await
+            .unwrap();
+
+        let stored = store.db().institutions().list().await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].name, "Platypus Bank");
+        assert!(store
+            .db()
+            .institutions()
+            .last_synced()
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
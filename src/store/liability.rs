@@ -0,0 +1,192 @@
+use std::str::FromStr;
+
+use chrono::{NaiveDate, Utc};
+use rust_decimal::Decimal;
+use sea_query::{Expr, Iden, Order, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use sqlx::{Connection, Row};
+
+use super::{Error, Result, SqliteStore};
+use crate::core::Liability;
+
+#[derive(Iden)]
+enum Liabilities {
+    Table,
+    AccountId,
+    Apr,
+    MinimumPayment,
+    NextDueDate,
+    LastStatementBalance,
+    Currency,
+    FetchedAt,
+}
+
+pub struct Store<'a>(&'a mut SqliteStore);
+
+impl<'a> Store<'a> {
+    pub fn new(store: &'a mut SqliteStore) -> Self {
+        Self(store)
+    }
+
+    /// Replaces the liability details stored for `account_ids`, accounts
+    /// without an entry in `liabilities` are left with none.
+    pub async fn replace(
+        &mut self,
+        account_ids: &[String],
+        liabilities: &[Liability],
+    ) -> Result<()> {
+        let (delete, delete_values) = Query::delete()
+            .from_table(Liabilities::Table)
+            .and_where(
+                Expr::col(Liabilities::AccountId).is_in(account_ids.iter().map(String::as_str)),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        let fetched_at = Utc::now().to_rfc3339();
+        let inserts: Vec<_> = liabilities
+            .iter()
+            .map(|liability| {
+                Query::insert()
+                    .into_table(Liabilities::Table)
+                    .columns([
+                        Liabilities::AccountId,
+                        Liabilities::Apr,
+                        Liabilities::MinimumPayment,
+                        Liabilities::NextDueDate,
+                        Liabilities::LastStatementBalance,
+                        Liabilities::Currency,
+                        Liabilities::FetchedAt,
+                    ])
+                    .values_panic(vec![
+                        liability.account_id.as_str().into(),
+                        liability.apr.map(|d| d.to_string()).into(),
+                        liability.minimum_payment.map(|d| d.to_string()).into(),
+                        liability
+                            .next_due_date
+                            .map(|d| d.format("%Y-%m-%d").to_string())
+                            .into(),
+                        liability
+                            .last_statement_balance
+                            .map(|d| d.to_string())
+                            .into(),
+                        liability.currency.as_str().into(),
+                        fetched_at.as_str().into(),
+                    ])
+                    .build_sqlx(SqliteQueryBuilder)
+            })
+            .collect();
+
+        self.0
+            .conn
+            .acquire()
+            .await?
+            .transaction(|conn| {
+                Box::pin(async move {
+                    sqlx::query_with(&delete, delete_values)
+                        .execute(&mut *conn)
+                        .await?;
+
+                    for (query, values) in inserts {
+                        sqlx::query_with(&query, values).execute(&mut *conn).await?;
+                    }
+
+                    Ok::<(), Error>(())
+                })
+            })
+            .await
+    }
+
+    pub async fn list(&mut self) -> Result<Vec<Liability>> {
+        let (query, values) = Query::select()
+            .from(Liabilities::Table)
+            .columns([
+                Liabilities::AccountId,
+                Liabilities::Apr,
+                Liabilities::MinimumPayment,
+                Liabilities::NextDueDate,
+                Liabilities::LastStatementBalance,
+                Liabilities::Currency,
+            ])
+            .order_by(Liabilities::AccountId, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let decimal = |value: Option<String>| value.as_deref().map(Decimal::from_str).transpose();
+        rows.into_iter()
+            .map(|row| {
+                let next_due_date: Option<String> = row.try_get("next_due_date")?;
+
+                Ok(Liability {
+                    account_id: row.try_get("account_id")?,
+                    apr: decimal(row.try_get("apr")?)?,
+                    minimum_payment: decimal(row.try_get("minimum_payment")?)?,
+                    next_due_date: next_due_date
+                        .as_deref()
+                        .map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d"))
+                        .transpose()?,
+                    last_statement_balance: decimal(row.try_get("last_statement_balance")?)?,
+                    currency: row.try_get("currency")?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use crate::core::Liability;
+    use crate::store::test_support::TestStore;
+
+    #[tokio::test]
+    async fn replace_keeps_missing_details_unset() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let card = store.new_account(&link.item_id).await;
+        let loan = store.new_account(&link.item_id).await;
+
+        let card_detail = Liability {
+            account_id: card.id.clone(),
+            apr: Some(Decimal::new(1999, 2)),
+            minimum_payment: Some(Decimal::new(35, 0)),
+            next_due_date: NaiveDate::from_ymd_opt(2023, 1, 15),
+            last_statement_balance: Some(Decimal::new(120050, 2)),
+            currency: "USD".into(),
+        };
+        let loan_detail = Liability {
+            account_id: loan.id.clone(),
+            apr: None,
+            minimum_payment: None,
+            next_due_date: None,
+            last_statement_balance: None,
+            currency: "USD".into(),
+        };
+        let ids = [card.id.clone(), loan.id.clone()];
+        store
+            .db()
+            .liabilities()
+            .replace(&ids, &[card_detail.clone(), loan_detail.clone()])
+            .await
+            .unwrap();
+
+        let mut stored = store.db().liabilities().list().await.unwrap();
+        stored.sort_by(|a, b| (a.account_id == loan.id).cmp(&(b.account_id == loan.id)));
+        assert_eq!(stored, vec![card_detail.clone(), loan_detail]);
+
+        store
+            .db()
+            .liabilities()
+            .replace(&ids, &[card_detail.clone()])
+            .await
+            .unwrap();
+        assert_eq!(
+            store.db().liabilities().list().await.unwrap(),
+            vec![card_detail]
+        );
+    }
+}
@@ -1,10 +1,14 @@
-use sea_query::{Expr, Iden, Query, SqliteQueryBuilder};
+use sea_query::{Cond, Expr, Iden, Query, SqliteQueryBuilder};
 use sea_query_binder::SqlxBinder;
-use sqlx::{FromRow, Row};
+use sqlx::{Connection, FromRow, Row};
 
-use super::{Result, SqliteStore};
+use super::{Error, Result, SqliteStore};
 use crate::plaid::{Link, LinkStatus};
 
+/// The placeholder link owning accounts tracked without Plaid, it's never
+/// listed or looked up as a link.
+pub const LOCAL_ITEM_ID: &str = "local";
+
 #[derive(Iden)]
 enum PlaidLinks {
     Table,
@@ -14,6 +18,63 @@ enum PlaidLinks {
     LinkState,
     SyncCursor,
     Institution,
+    Products,
+}
+
+// Tables holding rows owned by a link, removed along with it.
+#[derive(Iden)]
+enum Accounts {
+    Table,
+    Id,
+    ItemId,
+}
+
+#[derive(Iden)]
+enum Transactions {
+    Table,
+    Id,
+    AccountId,
+}
+
+#[derive(Iden)]
+enum Postings {
+    Table,
+    TxnId,
+}
+
+#[derive(Iden)]
+enum IntTransactionsLinks {
+    Table,
+    TxnId,
+    ItemId,
+}
+
+#[derive(Iden)]
+enum Transfers {
+    Table,
+    TxnId,
+    JoinedId,
+}
+
+#[derive(Iden)]
+enum BalanceSnapshots {
+    Table,
+}
+
+#[derive(Iden)]
+enum Holdings {
+    Table,
+}
+
+#[derive(Iden)]
+enum Liabilities {
+    Table,
+}
+
+/// The column tying balances, holdings, and liabilities to their account.
+#[derive(Iden)]
+enum AccountDetail {
+    AccountId,
 }
 
 pub struct Store<'a>(&'a mut SqliteStore);
@@ -24,6 +85,8 @@ impl<'a> Store<'a> {
     }
 
     pub async fn update(&mut self, link: &Link) -> Result<()> {
+        self.ensure_alias_available(link).await?;
+
         let (query, values) = Query::update()
             .table(PlaidLinks::Table)
             .values(vec![
@@ -35,13 +98,12 @@ impl<'a> Store<'a> {
                     PlaidLinks::Institution,
                     link.institution_id.as_deref().into(),
                 ),
+                (PlaidLinks::Products, link.products.join(",").into()),
             ])
             .and_where(Expr::col(PlaidLinks::Id).eq(link.item_id.as_str()))
             .build_sqlx(SqliteQueryBuilder);
 
-        sqlx::query_with(&query, values)
-            .execute(&mut self.0.conn.acquire().await?)
-            .await?;
+        self.0.execute(&query, values).await?;
 
         Ok(())
     }
@@ -55,9 +117,11 @@ impl<'a> Store<'a> {
                 PlaidLinks::LinkState,
                 PlaidLinks::SyncCursor,
                 PlaidLinks::Institution,
+                PlaidLinks::Products,
             ])
             .from(PlaidLinks::Table)
             .and_where(Expr::col(PlaidLinks::Id).eq(id))
+            .and_where(Expr::col(PlaidLinks::Id).ne(LOCAL_ITEM_ID))
             .build_sqlx(SqliteQueryBuilder);
 
         let row = sqlx::query_with(&query, values)
@@ -76,8 +140,10 @@ impl<'a> Store<'a> {
                 PlaidLinks::LinkState,
                 PlaidLinks::SyncCursor,
                 PlaidLinks::Institution,
+                PlaidLinks::Products,
             ])
             .from(PlaidLinks::Table)
+            .and_where(Expr::col(PlaidLinks::Id).ne(LOCAL_ITEM_ID))
             .build_sqlx(SqliteQueryBuilder);
 
         let rows = sqlx::query_with(&query, values)
@@ -92,7 +158,137 @@ impl<'a> Store<'a> {
         Ok(links)
     }
 
+    pub async fn by_id(&mut self, id: &str) -> Result<Option<Link>> {
+        let (query, values) = Query::select()
+            .columns([
+                PlaidLinks::Id,
+                PlaidLinks::Alias,
+                PlaidLinks::AccessToken,
+                PlaidLinks::LinkState,
+                PlaidLinks::SyncCursor,
+                PlaidLinks::Institution,
+                PlaidLinks::Products,
+            ])
+            .from(PlaidLinks::Table)
+            .and_where(Expr::col(PlaidLinks::Id).eq(id))
+            .and_where(Expr::col(PlaidLinks::Id).ne(LOCAL_ITEM_ID))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let row = sqlx::query_with(&query, values)
+            .fetch_optional(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(Link::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn by_institution(&mut self, institution_id: &str) -> Result<Vec<Link>> {
+        let (query, values) = Query::select()
+            .columns([
+                PlaidLinks::Id,
+                PlaidLinks::Alias,
+                PlaidLinks::AccessToken,
+                PlaidLinks::LinkState,
+                PlaidLinks::SyncCursor,
+                PlaidLinks::Institution,
+                PlaidLinks::Products,
+            ])
+            .from(PlaidLinks::Table)
+            .and_where(Expr::col(PlaidLinks::Institution).eq(institution_id))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut links = vec![];
+        for row in rows {
+            links.push(Link::from_row(&row)?);
+        }
+
+        Ok(links)
+    }
+
+    /// Returns links without an institution or whose institution is missing
+    /// from the institutions table.
+    pub async fn unresolved_institutions(&mut self) -> Result<Vec<Link>> {
+        #[derive(Iden)]
+        enum Institutions {
+            Table,
+            Id,
+        }
+
+        let (query, values) = Query::select()
+            .columns([
+                PlaidLinks::Id,
+                PlaidLinks::Alias,
+                PlaidLinks::AccessToken,
+                PlaidLinks::LinkState,
+                PlaidLinks::SyncCursor,
+                PlaidLinks::Institution,
+                PlaidLinks::Products,
+            ])
+            .from(PlaidLinks::Table)
+            .cond_where(
+                Cond::all()
+                    .add(Expr::col(PlaidLinks::Id).ne(LOCAL_ITEM_ID))
+                    .add(
+                        Cond::any()
+                            .add(Expr::col(PlaidLinks::Institution).is_null())
+                            .add(
+                                Expr::col(PlaidLinks::Institution).not_in_subquery(
+                                    Query::select()
+                                        .column(Institutions::Id)
+                                        .from(Institutions::Table)
+                                        .take(),
+                                ),
+                            ),
+                    ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut links = vec![];
+        for row in rows {
+            links.push(Link::from_row(&row)?);
+        }
+
+        Ok(links)
+    }
+
+    pub async fn by_alias(&mut self, alias: &str) -> Result<Option<Link>> {
+        let (query, values) = Query::select()
+            .columns([
+                PlaidLinks::Id,
+                PlaidLinks::Alias,
+                PlaidLinks::AccessToken,
+                PlaidLinks::LinkState,
+                PlaidLinks::SyncCursor,
+                PlaidLinks::Institution,
+                PlaidLinks::Products,
+            ])
+            .from(PlaidLinks::Table)
+            .and_where(Expr::col(PlaidLinks::Alias).eq(alias))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let row = sqlx::query_with(&query, values)
+            .fetch_optional(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(Link::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
     pub async fn save(&mut self, link: &Link) -> Result<()> {
+        self.ensure_alias_available(link).await?;
+
         let (query, values) = Query::insert()
             .into_table(PlaidLinks::Table)
             .columns([
@@ -101,6 +297,7 @@ impl<'a> Store<'a> {
                 PlaidLinks::AccessToken,
                 PlaidLinks::LinkState,
                 PlaidLinks::Institution,
+                PlaidLinks::Products,
             ])
             .values_panic(vec![
                 link.item_id.as_str().into(),
@@ -108,34 +305,157 @@ impl<'a> Store<'a> {
                 link.access_token.as_str().into(),
                 to_status_enum(&link.state).as_str().into(),
                 link.institution_id.as_deref().into(),
+                link.products.join(",").into(),
             ])
             .build_sqlx(SqliteQueryBuilder);
 
-        sqlx::query_with(&query, values)
-            .execute(&mut self.0.conn.acquire().await?)
-            .await?;
+        self.0.execute(&query, values).await?;
 
         Ok(())
     }
 
+    /// Deletes a link along with its accounts and every transaction saved
+    /// from it or against its accounts. Nothing is deleted if any part fails.
     pub async fn delete(&mut self, id: &str) -> Result<Link> {
-        let (query, values) = Query::delete()
-            .from_table(PlaidLinks::Table)
-            .and_where(Expr::col(PlaidLinks::Id).eq(id))
-            .returning(Query::returning().columns([
-                PlaidLinks::Id,
-                PlaidLinks::Alias,
-                PlaidLinks::AccessToken,
-                PlaidLinks::LinkState,
-                PlaidLinks::Institution,
-            ]))
-            .build_sqlx(SqliteQueryBuilder);
+        // The placeholder owns local accounts, it isn't a link to delete.
+        if id == LOCAL_ITEM_ID {
+            return Err(Error::Database(sqlx::Error::RowNotFound));
+        }
+        let id = id.to_string();
+
+        self.0
+            .conn
+            .acquire()
+            .await?
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let accounts = Query::select()
+                        .column(Accounts::Id)
+                        .from(Accounts::Table)
+                        .and_where(Expr::col(Accounts::ItemId).eq(id.as_str()))
+                        .take();
+
+                    let (query, values) = Query::select()
+                        .column(Transactions::Id)
+                        .from(Transactions::Table)
+                        .cond_where(
+                            Cond::any()
+                                .add(
+                                    Expr::col(Transactions::AccountId)
+                                        .in_subquery(accounts.clone()),
+                                )
+                                .add(
+                                    Expr::col(Transactions::Id).in_subquery(
+                                        Query::select()
+                                            .column(IntTransactionsLinks::TxnId)
+                                            .from(IntTransactionsLinks::Table)
+                                            .and_where(
+                                                Expr::col(IntTransactionsLinks::ItemId)
+                                                    .eq(id.as_str()),
+                                            )
+                                            .take(),
+                                    ),
+                                ),
+                        )
+                        .build_sqlx(SqliteQueryBuilder);
+                    let txn_ids = sqlx::query_with(&query, values)
+                        .fetch_all(&mut *conn)
+                        .await?
+                        .iter()
+                        .map(|row| row.try_get("id"))
+                        .collect::<std::result::Result<Vec<String>, _>>()?;
+
+                    let statements = [
+                        Query::delete()
+                            .from_table(Transfers::Table)
+                            .cond_where(
+                                Cond::any()
+                                    .add(Expr::col(Transfers::TxnId).is_in(txn_ids.clone()))
+                                    .add(Expr::col(Transfers::JoinedId).is_in(txn_ids.clone())),
+                            )
+                            .to_owned(),
+                        Query::delete()
+                            .from_table(Postings::Table)
+                            .and_where(Expr::col(Postings::TxnId).is_in(txn_ids.clone()))
+                            .to_owned(),
+                        Query::delete()
+                            .from_table(IntTransactionsLinks::Table)
+                            .cond_where(
+                                Cond::any()
+                                    .add(
+                                        Expr::col(IntTransactionsLinks::TxnId)
+                                            .is_in(txn_ids.clone()),
+                                    )
+                                    .add(Expr::col(IntTransactionsLinks::ItemId).eq(id.as_str())),
+                            )
+                            .to_owned(),
+                        Query::delete()
+                            .from_table(Transactions::Table)
+                            .and_where(Expr::col(Transactions::Id).is_in(txn_ids))
+                            .to_owned(),
+                        Query::delete()
+                            .from_table(BalanceSnapshots::Table)
+                            .and_where(
+                                Expr::col(AccountDetail::AccountId).in_subquery(accounts.clone()),
+                            )
+                            .to_owned(),
+                        Query::delete()
+                            .from_table(Holdings::Table)
+                            .and_where(
+                                Expr::col(AccountDetail::AccountId).in_subquery(accounts.clone()),
+                            )
+                            .to_owned(),
+                        Query::delete()
+                            .from_table(Liabilities::Table)
+                            .and_where(Expr::col(AccountDetail::AccountId).in_subquery(accounts))
+                            .to_owned(),
+                        Query::delete()
+                            .from_table(Accounts::Table)
+                            .and_where(Expr::col(Accounts::ItemId).eq(id.as_str()))
+                            .to_owned(),
+                    ];
+                    for statement in statements {
+                        let (query, values) = statement.build_sqlx(SqliteQueryBuilder);
+                        sqlx::query_with(&query, values).execute(&mut *conn).await?;
+                    }
+
+                    let (query, values) = Query::delete()
+                        .from_table(PlaidLinks::Table)
+                        .and_where(Expr::col(PlaidLinks::Id).eq(id.as_str()))
+                        .returning(Query::returning().columns([
+                            PlaidLinks::Id,
+                            PlaidLinks::Alias,
+                            PlaidLinks::AccessToken,
+                            PlaidLinks::LinkState,
+                            PlaidLinks::SyncCursor,
+                            PlaidLinks::Institution,
+                            PlaidLinks::Products,
+                        ]))
+                        .build_sqlx(SqliteQueryBuilder);
+                    let row = sqlx::query_with(&query, values)
+                        .fetch_one(&mut *conn)
+                        .await?;
+
+                    Ok::<Link, Error>(Link::from_row(&row)?)
+                })
+            })
+            .await
+    }
 
-        let row = sqlx::query_with(&query, values)
-            .fetch_one(&mut self.0.conn.acquire().await?)
-            .await?;
+    // Links without an alias are allowed to share the empty name, any other
+    // alias must identify exactly one link.
+    async fn ensure_alias_available(&mut self, link: &Link) -> Result<()> {
+        if link.alias.is_empty() {
+            return Ok(());
+        }
 
-        Ok(Link::from_row(&row)?)
+        match self.by_alias(&link.alias).await? {
+            Some(existing) if existing.item_id != link.item_id => Err(Error::DuplicateAlias {
+                alias: link.alias.clone(),
+                item_id: existing.item_id,
+            }),
+            _ => Ok(()),
+        }
     }
 }
 
@@ -153,6 +473,16 @@ where
             state: from_status_enum(row.try_get("link_state")?).unwrap(),
             sync_cursor: row.try_get("sync_cursor")?,
             institution_id: row.try_get("institution")?,
+            products: row
+                .try_get::<Option<String>, _>("products")?
+                .map(|products| {
+                    products
+                        .split(',')
+                        .filter(|p| !p.is_empty())
+                        .map(ToString::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
         })
     }
 }
@@ -173,53 +503,17 @@ fn from_status_enum(status: &str) -> anyhow::Result<LinkStatus> {
 }
 
 #[cfg(test)]
-pub(crate) mod tests {
+mod tests {
     use ulid::Ulid;
 
+    use super::LOCAL_ITEM_ID;
     use crate::plaid::Link;
-
-    use super::SqliteStore;
-
-    pub(crate) struct TestStore {
-        store: SqliteStore,
-    }
-
-    impl TestStore {
-        pub(crate) async fn new() -> Self {
-            TestStore {
-                store: SqliteStore::new("sqlite::memory:").await.unwrap(),
-            }
-        }
-
-        pub(crate) async fn new_link(&mut self) -> Link {
-            let link = Link {
-                alias: "test_link".to_string(),
-                access_token: "access-token-1234".to_string(),
-                item_id: Ulid::new().to_string(),
-                state: crate::plaid::LinkStatus::Active,
-                sync_cursor: None,
-                institution_id: None,
-            };
-
-            self.store.links().save(&link).await.unwrap();
-
-            link
-        }
-
-        pub(crate) fn db(&mut self) -> &mut SqliteStore {
-            &mut self.store
-        }
-    }
-
-    async fn test_store() -> TestStore {
-        TestStore {
-            store: SqliteStore::new("sqlite::memory:").await.unwrap(),
-        }
-    }
+    use crate::store::test_support::{LinkBuilder, TestStore, TransactionBuilder};
+    use crate::store::TransactionFilter;
 
     #[tokio::test]
     async fn retrieve_link() {
-        let mut store = test_store().await;
+        let mut store = TestStore::new().await;
         let link = store.new_link().await;
 
         let fetch_link = store.db().links().link(&link.item_id).await.unwrap();
@@ -227,12 +521,13 @@ pub(crate) mod tests {
         assert_eq!(&link.alias, &fetch_link.alias);
         assert_eq!(&link.access_token, &fetch_link.access_token);
         assert_eq!(&link.item_id, &fetch_link.item_id);
+        assert_eq!(&link.products, &fetch_link.products);
         assert!(matches!(link.state, crate::plaid::LinkStatus::Active));
     }
 
     #[tokio::test]
     async fn list_links() {
-        let mut store = test_store().await;
+        let mut store = TestStore::new().await;
         for _ in 0..5 {
             store.new_link().await;
         }
@@ -244,7 +539,7 @@ pub(crate) mod tests {
 
     #[tokio::test]
     async fn update_plaid_link() {
-        let mut store = test_store().await;
+        let mut store = TestStore::new().await;
         let link = store.new_link().await;
 
         let updated_link = Link {
@@ -253,4 +548,135 @@ pub(crate) mod tests {
         };
         store.db().links().update(&updated_link).await.unwrap();
     }
+
+    #[tokio::test]
+    async fn save_rejects_duplicate_alias() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let duplicate = Link {
+            item_id: Ulid::new().to_string(),
+            ..link.clone()
+        };
+        let err = store.db().links().save(&duplicate).await.unwrap_err();
+
+        assert_eq!(
+            err,
+            super::Error::DuplicateAlias {
+                alias: link.alias,
+                item_id: link.item_id,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn by_id_returns_none_for_unknown_link() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let found = store.db().links().by_id(&link.item_id).await.unwrap();
+        assert!(found.is_some());
+
+        let missing = store.db().links().by_id("unknown-item").await.unwrap();
+        assert!(missing.is_none());
+    }
+
+    #[tokio::test]
+    async fn links_by_institution() {
+        let mut store = TestStore::new().await;
+        for institution in ["ins_1", "ins_1", "ins_2"] {
+            let link = LinkBuilder::new().institution_id(institution).build();
+            store.db().links().save(&link).await.unwrap();
+        }
+        store.new_link().await;
+
+        let links = store.db().links().by_institution("ins_1").await.unwrap();
+
+        assert_eq!(links.len(), 2);
+        assert!(links
+            .iter()
+            .all(|l| l.institution_id.as_deref() == Some("ins_1")));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_accounts_and_transactions() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let account = store.new_account(&link.item_id).await;
+        let entry = TransactionBuilder::new().account_id(&account.id).build();
+        store
+            .db()
+            .txns()
+            .save(
+                &link.item_id,
+                &entry.source.transaction_id,
+                &account.id,
+                &entry,
+            )
+            .await
+            .unwrap();
+
+        store.db().links().delete(&link.item_id).await.unwrap();
+
+        assert!(store.db().accounts().list().await.unwrap().is_empty());
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter {
+                include_removed: true,
+                ..TransactionFilter::default()
+            })
+            .await
+            .unwrap();
+        assert!(txns.is_empty());
+        assert!(store
+            .db()
+            .txns()
+            .orphaned_accounts()
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut conn = store.db().conn.acquire().await.unwrap();
+        for table in ["postings", "int_transactions_links"] {
+            let (count,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+            assert_eq!(count, 0, "{} has orphaned rows", table);
+        }
+    }
+
+    #[tokio::test]
+    async fn placeholder_link_is_hidden() {
+        let mut store = TestStore::new().await;
+        let account = store.new_account(LOCAL_ITEM_ID).await;
+
+        assert!(store.db().links().link(LOCAL_ITEM_ID).await.is_err());
+        assert!(store
+            .db()
+            .links()
+            .by_id(LOCAL_ITEM_ID)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .db()
+            .links()
+            .by_alias("local")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.db().links().delete(LOCAL_ITEM_ID).await.is_err());
+        assert_eq!(
+            store.db().accounts().list().await.unwrap()[0].id,
+            account.id
+        );
+
+        let link = Link {
+            alias: "local".into(),
+            ..LinkBuilder::new().build()
+        };
+        store.db().links().save(&link).await.unwrap();
+    }
 }
@@ -0,0 +1,90 @@
+use sea_query::{Expr, Iden, Query, SqliteQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use sqlx::Row;
+
+use super::{Result, SqliteStore};
+
+#[derive(Iden)]
+enum Meta {
+    Table,
+    Key,
+    Value,
+}
+
+/// A small key value table for bookkeeping that doesn't belong to any one
+/// record, e.g. markers tracking the last export.
+pub struct Store<'a>(&'a mut SqliteStore);
+
+impl<'a> Store<'a> {
+    pub fn new(store: &'a mut SqliteStore) -> Self {
+        Self(store)
+    }
+
+    pub async fn get(&mut self, key: &str) -> Result<Option<String>> {
+        let (query, values) = Query::select()
+            .column(Meta::Value)
+            .from(Meta::Table)
+            .and_where(Expr::col(Meta::Key).eq(key))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let row = sqlx::query_with(&query, values)
+            .fetch_optional(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(row.try_get("value")?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let (query, values) = Query::insert()
+            .into_table(Meta::Table)
+            .columns([Meta::Key, Meta::Value])
+            .values_panic(vec![key.into(), value.into()])
+            .on_conflict(
+                sea_query::OnConflict::column(Meta::Key)
+                    .update_column(Meta::Value)
+                    .to_owned(),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        self.0.execute(&query, values).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&mut self, key: &str) -> Result<()> {
+        let (query, values) = Query::delete()
+            .from_table(Meta::Table)
+            .and_where(Expr::col(Meta::Key).eq(key))
+            .build_sqlx(SqliteQueryBuilder);
+
+        self.0.execute(&query, values).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::test_support::TestStore;
+
+    #[tokio::test]
+    async fn set_overwrites_value() {
+        let mut store = TestStore::new().await;
+
+        assert_eq!(store.db().meta().get("marker").await.unwrap(), None);
+
+        store.db().meta().set("marker", "first").await.unwrap();
+        store.db().meta().set("marker", "second").await.unwrap();
+
+        assert_eq!(
+            store.db().meta().get("marker").await.unwrap(),
+            Some("second".to_string())
+        );
+
+        store.db().meta().delete("marker").await.unwrap();
+        assert_eq!(store.db().meta().get("marker").await.unwrap(), None);
+    }
+}
@@ -1,14 +1,32 @@
 mod account;
+mod balance;
+mod holding;
+mod liability;
 pub(crate) mod institution;
 pub(crate) mod link;
+mod meta;
 mod txn;
 
+#[cfg(test)]
+pub(crate) mod test_support;
+
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use sea_query_binder::SqlxValues;
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqliteQueryResult},
+    Row,
+};
 use thiserror::Error;
+use tracing::warn;
 
 use crate::upstream::TransactionEntry;
 
+pub use txn::{RegionCount, TransactionFilter};
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
@@ -22,9 +40,33 @@ pub enum Error {
     #[error(transparent)]
     Decode(#[from] ulid::DecodeError),
     #[error(transparent)]
+    Amount(#[from] rust_decimal::Error),
+    #[error(transparent)]
+    Date(#[from] chrono::ParseError),
+    #[error(transparent)]
+    Status(#[from] crate::core::UnknownStatus),
+    #[error("alias \"{alias}\" is already used by link {item_id}")]
+    DuplicateAlias { alias: String, item_id: String },
+    #[error("alias \"{alias}\" is already used by account {account_id}")]
+    DuplicateAccountAlias { alias: String, account_id: String },
+    #[error(transparent)]
     Unknown(#[from] anyhow::Error),
 }
 
+impl Error {
+    /// Whether the error was caused by another connection holding a lock on
+    /// the database.
+    fn is_locked(&self) -> bool {
+        match self {
+            Error::Database(sqlx::Error::Database(err)) => {
+                matches!(err.code().as_deref(), Some("5") | Some("6"))
+                    || err.message().contains("database is locked")
+            }
+            _ => false,
+        }
+    }
+}
+
 impl PartialEq for Error {
     fn eq(&self, other: &Error) -> bool {
         self.to_string() == other.to_string()
@@ -33,22 +75,101 @@ impl PartialEq for Error {
 
 type Result<T> = ::std::result::Result<T, Error>;
 
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// How long sqlite waits on a lock held by another connection, e.g. a cron
+/// sync running alongside an export, before reporting the database as busy.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts made at a write that still finds the database locked after the
+/// busy timeout.
+const LOCKED_ATTEMPTS: u32 = 3;
+
 pub struct SqliteStore {
     conn: Arc<sqlx::pool::Pool<sqlx::sqlite::Sqlite>>,
 }
 
 impl SqliteStore {
     pub async fn new(uri: &str) -> Result<Self> {
-        let pool = sqlx::sqlite::SqlitePoolOptions::new().connect(uri).await?;
+        let store = Self::open(uri).await?;
+
+        let mut conn = store.conn.acquire().await?;
+        MIGRATOR.run(&mut conn).await?;
 
-        let mut conn = pool.acquire().await?;
-        sqlx::migrate!("./migrations").run(&mut conn).await?;
+        Ok(store)
+    }
+
+    /// Opens the store without applying pending migrations, for inspecting a
+    /// database as it is on disk.
+    pub async fn open(uri: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(uri)?.busy_timeout(BUSY_TIMEOUT);
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect_with(options)
+            .await?;
 
         Ok(Self {
             conn: Arc::new(pool),
         })
     }
 
+    /// The latest migration version embedded in this binary.
+    pub fn expected_schema_version() -> Option<i64> {
+        MIGRATOR.iter().map(|m| m.version).max()
+    }
+
+    /// The latest migration version successfully applied to the database.
+    pub async fn schema_version(&mut self) -> Result<Option<i64>> {
+        let row = sqlx::query("SELECT MAX(version) AS version FROM _sqlx_migrations WHERE success")
+            .fetch_one(&mut self.conn.acquire().await?)
+            .await?;
+
+        Ok(row.try_get("version")?)
+    }
+
+    /// Executes a single write statement, retrying when the database stays
+    /// locked by another connection past the busy timeout.
+    async fn execute(&self, query: &str, values: SqlxValues) -> Result<SqliteQueryResult> {
+        let mut attempt = 1;
+        loop {
+            let result = sqlx::query_with(query, values.clone())
+                .execute(&mut self.conn.acquire().await?)
+                .await
+                .map_err(Error::from);
+
+            match result {
+                Err(err) if err.is_locked() && attempt < LOCKED_ATTEMPTS => {
+                    warn!("Database is locked, retrying write. attempt={}", attempt);
+
+                    tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs sqlite's integrity check, a healthy database reports a single "ok".
+    pub async fn integrity_check(&mut self) -> Result<Vec<String>> {
+        let rows = sqlx::query("PRAGMA integrity_check")
+            .fetch_all(&mut self.conn.acquire().await?)
+            .await?;
+
+        let mut results = Vec::with_capacity(rows.len());
+        for row in rows {
+            results.push(row.try_get(0)?);
+        }
+
+        Ok(results)
+    }
+
+    /// Rebuilds the database file, reclaiming space left behind by deletes.
+    pub async fn vacuum(&mut self) -> Result<()> {
+        sqlx::query("VACUUM")
+            .execute(&mut self.conn.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
     pub fn institutions(&mut self) -> institution::Store {
         institution::Store::new(self)
     }
@@ -57,6 +178,10 @@ impl SqliteStore {
         link::Store::new(self)
     }
 
+    pub fn meta(&mut self) -> meta::Store {
+        meta::Store::new(self)
+    }
+
     pub fn txns(&mut self) -> txn::Store {
         txn::Store::new(self)
     }
@@ -64,4 +189,51 @@ impl SqliteStore {
     pub fn accounts(&mut self) -> account::Store {
         account::Store::new(self)
     }
+
+    pub fn balances(&mut self) -> balance::Store {
+        balance::Store::new(self)
+    }
+
+    pub fn holdings(&mut self) -> holding::Store {
+        holding::Store::new(self)
+    }
+
+    pub fn liabilities(&mut self) -> liability::Store {
+        liability::Store::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_concurrent_writer() {
+        let path = std::env::temp_dir().join(format!("clerk-{}.db", ulid::Ulid::new()));
+        let uri = format!("sqlite://{}?mode=rwc", path.display());
+
+        let first = SqliteStore::new(&uri).await.unwrap();
+        let mut second = SqliteStore::new(&uri).await.unwrap();
+
+        // Hold the write lock from another connection for a moment.
+        let mut conn = first.conn.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            sqlx::query("COMMIT").execute(&mut conn).await.unwrap();
+        });
+
+        second.meta().set("key", "value").await.unwrap();
+        release.await.unwrap();
+
+        assert_eq!(
+            second.meta().get("key").await.unwrap(),
+            Some("value".to_string())
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
 }
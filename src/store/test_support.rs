@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use rplaid::model;
+use ulid::Ulid;
+
+use super::SqliteStore;
+use crate::core::{Account, Posting, Status, Transaction};
+use crate::plaid::sync::{PersonalFinanceCategory, PlaidTransaction};
+use crate::plaid::{Link, LinkStatus};
+use crate::upstream::TransactionEntry;
+
+pub(crate) async fn memory_store() -> SqliteStore {
+    SqliteStore::new("sqlite::memory:").await.unwrap()
+}
+
+pub(crate) struct TestStore {
+    store: SqliteStore,
+}
+
+impl TestStore {
+    pub(crate) async fn new() -> Self {
+        TestStore {
+            store: memory_store().await,
+        }
+    }
+
+    pub(crate) async fn new_link(&mut self) -> Link {
+        let link = LinkBuilder::new().build();
+
+        self.store.links().save(&link).await.unwrap();
+
+        link
+    }
+
+    pub(crate) async fn new_account(&mut self, item_id: &str) -> Account {
+        let account = Account {
+            id: Ulid::new().to_string(),
+            name: "Test Account".into(),
+            official_name: None,
+            ty: "CREDIT_NORMAL".into(),
+            alias: None,
+        };
+
+        self.store
+            .accounts()
+            .save(item_id, &account)
+            .await
+            .unwrap();
+
+        account
+    }
+
+    pub(crate) fn db(&mut self) -> &mut SqliteStore {
+        &mut self.store
+    }
+}
+
+pub(crate) struct LinkBuilder {
+    link: Link,
+}
+
+impl LinkBuilder {
+    pub(crate) fn new() -> Self {
+        let item_id = Ulid::new().to_string();
+
+        Self {
+            link: Link {
+                alias: format!("test_link_{}", item_id),
+                access_token: "access-token-1234".to_string(),
+                item_id,
+                state: LinkStatus::Active,
+                sync_cursor: None,
+                institution_id: None,
+                products: vec!["transactions".to_string()],
+            },
+        }
+    }
+
+    pub(crate) fn item_id(mut self, item_id: &str) -> Self {
+        self.link.item_id = item_id.to_string();
+        self
+    }
+
+    pub(crate) fn institution_id(mut self, institution_id: &str) -> Self {
+        self.link.institution_id = Some(institution_id.to_string());
+        self
+    }
+
+    pub(crate) fn build(self) -> Link {
+        self.link
+    }
+}
+
+pub(crate) struct TransactionBuilder {
+    canonical: Transaction,
+    source: PlaidTransaction,
+}
+
+impl TransactionBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            canonical: Transaction {
+                id: Ulid::new(),
+                date: NaiveDate::parse_from_str("2022-05-01", "%Y-%m-%d").unwrap(),
+                narration: "Test Transaction".to_string(),
+                payee: None,
+                status: Status::Resolved,
+                postings: vec![],
+                tags: vec![],
+                meta: BTreeMap::new(),
+            },
+            source: PlaidTransaction {
+                transaction: model::Transaction {
+                    transaction_type: "".to_string(),
+                    pending_transaction_id: None,
+                    category_id: None,
+                    category: None,
+                    location: None,
+                    payment_meta: None,
+                    account_owner: None,
+                    name: "".to_string(),
+                    original_description: None,
+                    account_id: "test-account-id".to_string(),
+                    amount: 33.into(),
+                    iso_currency_code: None,
+                    unofficial_currency_code: None,
+                    date: "2022-05-01".to_string(),
+                    pending: false,
+                    transaction_id: "1234-test".to_string(),
+                    payment_channel: "".to_string(),
+                    merchant_name: None,
+                    authorized_date: None,
+                    authorized_datetime: None,
+                    datetime: None,
+                    check_number: None,
+                    transaction_code: None,
+                },
+                personal_finance_category: None,
+            },
+        }
+    }
+
+    pub(crate) fn id(mut self, id: Ulid) -> Self {
+        self.canonical.id = id;
+        self
+    }
+
+    pub(crate) fn account_id(mut self, account_id: &str) -> Self {
+        self.source.account_id = account_id.to_string();
+        self
+    }
+
+    pub(crate) fn transaction_id(mut self, transaction_id: &str) -> Self {
+        self.source.transaction_id = transaction_id.to_string();
+        self
+    }
+
+    pub(crate) fn postings(mut self, postings: Vec<Posting>) -> Self {
+        self.canonical.postings = postings;
+        self
+    }
+
+    pub(crate) fn category(mut self, primary: &str, detailed: &str) -> Self {
+        self.source.personal_finance_category = Some(PersonalFinanceCategory {
+            primary: primary.to_string(),
+            detailed: detailed.to_string(),
+        });
+        self
+    }
+
+    pub(crate) fn build(self) -> TransactionEntry<PlaidTransaction> {
+        TransactionEntry {
+            canonical: self.canonical,
+            source: self.source,
+        }
+    }
+}
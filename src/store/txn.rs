@@ -1,9 +1,19 @@
-use sea_query::{func::Func, types::Alias, Expr, Iden, Query, SqliteQueryBuilder};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use chrono::{NaiveDate, Utc};
+use rust_decimal::Decimal;
+use sea_query::{
+    func::Func, types::Alias, Cond, Expr, Iden, LikeExpr, Order, Query, SelectStatement,
+    SqliteQueryBuilder,
+};
 use sea_query_binder::SqlxBinder;
 use serde::Serialize;
-use sqlx::{Connection, Row};
+use sqlx::{Connection, Row, SqliteConnection};
+use ulid::Ulid;
 
-use super::{Result, SqliteStore, TransactionEntry};
+use super::{Error, Result, SqliteStore, TransactionEntry};
+use crate::core::{Posting, Status, Transaction, Transfer};
 
 #[derive(Iden)]
 enum Transactions {
@@ -11,6 +21,43 @@ enum Transactions {
     Id,
     AccountId,
     Source,
+    Date,
+    Narration,
+    Payee,
+    Status,
+    RemovedAt,
+    LocationRegion,
+    LocationCountry,
+    CategoryPrimary,
+    CategoryDetailed,
+    Tags,
+    Meta,
+}
+
+#[derive(Iden)]
+enum Postings {
+    Table,
+    Id,
+    TxnId,
+    Account,
+    Amount,
+    Currency,
+}
+
+#[derive(Iden)]
+enum Transfers {
+    Table,
+    TxnId,
+    JoinedId,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum IntTransactionsLinks {
+    Table,
+    TxnId,
+    ItemId,
+    UpstreamId,
 }
 
 struct JsonExtract;
@@ -21,6 +68,107 @@ impl Iden for JsonExtract {
     }
 }
 
+/// Narrows the set of transactions returned by [`Store::list`].
+#[derive(Debug, Default)]
+pub struct TransactionFilter {
+    /// Only include transactions saved at or after the given time.
+    pub created_after: Option<Ulid>,
+    /// Include transactions that were removed upstream but kept as tombstones.
+    pub include_removed: bool,
+    /// Only include transactions with the given status.
+    pub status: Option<Status>,
+    /// Only include transactions belonging to the given account.
+    pub account_id: Option<String>,
+    /// Only include transactions dated on or after the given day.
+    pub since: Option<NaiveDate>,
+    /// Only include transactions dated on or before the given day.
+    pub until: Option<NaiveDate>,
+    /// Only include transactions whose narration, or upstream name or
+    /// merchant name, contains the given text.
+    pub text: Option<String>,
+    /// Only include transactions whose primary or detailed personal finance
+    /// category is the given category, e.g. "FOOD_AND_DRINK" or
+    /// "FOOD_AND_DRINK_COFFEE", ignoring case.
+    pub category: Option<String>,
+}
+
+impl TransactionFilter {
+    fn condition(&self) -> Cond {
+        let mut cond = Cond::all();
+        if let Some(created_after) = &self.created_after {
+            cond = cond.add(Expr::col(Transactions::Id).gte(created_after.to_string()));
+        }
+        if !self.include_removed {
+            cond = cond.add(Expr::col(Transactions::RemovedAt).is_null());
+        }
+        if let Some(status) = &self.status {
+            cond = cond.add(Expr::col(Transactions::Status).eq(status.to_string()));
+        }
+        if let Some(account_id) = &self.account_id {
+            cond = cond.add(Expr::col(Transactions::AccountId).eq(account_id.as_str()));
+        }
+        // Dates are stored as YYYY-MM-DD so they compare in date order.
+        if let Some(since) = &self.since {
+            cond =
+                cond.add(Expr::col(Transactions::Date).gte(since.format("%Y-%m-%d").to_string()));
+        }
+        if let Some(until) = &self.until {
+            cond =
+                cond.add(Expr::col(Transactions::Date).lte(until.format("%Y-%m-%d").to_string()));
+        }
+        // Plaid reports categories in upper case.
+        if let Some(category) = &self.category {
+            let category = category.to_uppercase();
+            cond = cond.add(
+                Cond::any()
+                    .add(Expr::col(Transactions::CategoryPrimary).eq(category.as_str()))
+                    .add(Expr::col(Transactions::CategoryDetailed).eq(category.as_str())),
+            );
+        }
+        // SQLite's LIKE ignores case for ASCII characters. Wildcards in the
+        // text are escaped so they match literally.
+        if let Some(text) = &self.text {
+            let pattern = format!(
+                "%{}%",
+                text.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            let like = || LikeExpr::str(&pattern).escape('\\');
+            let source_field = |path: &str| {
+                Expr::expr(
+                    Func::cust(JsonExtract)
+                        .args(vec![Expr::col(Transactions::Source), Expr::val(path)]),
+                )
+            };
+            cond = cond.add(
+                Cond::any()
+                    .add(Expr::col(Transactions::Narration).like(like()))
+                    .add(source_field("$.name").like(like()))
+                    .add(source_field("$.merchant_name").like(like())),
+            );
+        }
+
+        cond
+    }
+}
+
+/// Row counts removed by [`Store::prune_before`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PruneStats {
+    pub transactions: u64,
+    pub postings: u64,
+    pub connections: u64,
+}
+
+/// The number of transactions made in a region, see [`Store::count_by_region`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct RegionCount {
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub transactions: i64,
+}
+
 pub struct Store<'a>(&'a mut SqliteStore);
 
 impl<'a> Store<'a> {
@@ -53,44 +201,506 @@ impl<'a> Store<'a> {
             .map(|row| row.try_get("id").unwrap()))
     }
 
-    pub async fn update_source<S: Serialize>(&mut self, id: &str, source: S) -> Result<()> {
+    /// Updates a transaction from a newer copy of it, its postings are
+    /// replaced by the newer copy's. A soft deleted transaction that comes
+    /// back upstream is restored. Transactions joined into a transfer stay
+    /// joined: the outflow keeps the joined postings and the inflow stays
+    /// removed.
+    pub async fn update<S: Serialize>(&mut self, id: &str, tx: &TransactionEntry<S>) -> Result<()> {
+        let mut columns = vec![
+            (Transactions::Source, tx.serialize_string()?.into()),
+            (
+                Transactions::Date,
+                tx.canonical.date.format("%Y-%m-%d").to_string().into(),
+            ),
+            (
+                Transactions::Narration,
+                tx.canonical.narration.as_str().into(),
+            ),
+            (Transactions::Payee, tx.canonical.payee.as_deref().into()),
+            (Transactions::Status, tx.canonical.status.to_string().into()),
+            (
+                Transactions::Tags,
+                serde_json::to_string(&tx.canonical.tags)?.into(),
+            ),
+            (
+                Transactions::Meta,
+                serde_json::to_string(&tx.canonical.meta)?.into(),
+            ),
+        ];
+        let id = id.to_string();
+        let postings = tx.canonical.postings.clone();
+
+        self.0
+            .conn
+            .acquire()
+            .await?
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let (query, values) = Query::select()
+                        .columns([Transfers::TxnId, Transfers::JoinedId])
+                        .from(Transfers::Table)
+                        .cond_where(
+                            Cond::any()
+                                .add(Expr::col(Transfers::TxnId).eq(id.as_str()))
+                                .add(Expr::col(Transfers::JoinedId).eq(id.as_str())),
+                        )
+                        .build_sqlx(SqliteQueryBuilder);
+                    let transfer = sqlx::query_with(&query, values)
+                        .fetch_optional(&mut *conn)
+                        .await?;
+                    let (outflow, inflow) = match transfer {
+                        Some(row) => (
+                            row.try_get::<String, _>("txn_id")? == id,
+                            row.try_get::<String, _>("joined_id")? == id,
+                        ),
+                        None => (false, false),
+                    };
+
+                    if !inflow {
+                        columns.push((Transactions::RemovedAt, None::<String>.into()));
+                    }
+                    let (query, values) = Query::update()
+                        .table(Transactions::Table)
+                        .values(columns)
+                        .and_where(Expr::col(Transactions::Id).eq(id.as_str()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    if !outflow {
+                        let (query, values) = Query::delete()
+                            .from_table(Postings::Table)
+                            .and_where(Expr::col(Postings::TxnId).eq(id.as_str()))
+                            .build_sqlx(SqliteQueryBuilder);
+                        sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                        for posting in &postings {
+                            insert_posting(&mut **conn, &id, posting).await?;
+                        }
+                    }
+
+                    Ok::<(), Error>(())
+                })
+            })
+            .await
+    }
+
+    /// Lists canonical transactions matching `filter` along with their
+    /// postings, ordered by date.
+    pub async fn list(&mut self, filter: &TransactionFilter) -> Result<Vec<Transaction>> {
+        self.select(filter, Order::Asc, None).await
+    }
+
+    /// Finds transactions whose narration, or upstream name or merchant
+    /// name, contains `text`, ignoring case. The most recent matches come
+    /// first.
+    pub async fn search(&mut self, text: &str, limit: Option<u64>) -> Result<Vec<Transaction>> {
+        let filter = TransactionFilter {
+            text: Some(text.to_string()),
+            ..TransactionFilter::default()
+        };
+
+        self.select(&filter, Order::Desc, limit).await
+    }
+
+    async fn select(
+        &mut self,
+        filter: &TransactionFilter,
+        order: Order,
+        limit: Option<u64>,
+    ) -> Result<Vec<Transaction>> {
+        let mut select = Query::select();
+        select
+            .columns([
+                Transactions::Id,
+                Transactions::Date,
+                Transactions::Narration,
+                Transactions::Payee,
+                Transactions::Status,
+                Transactions::Tags,
+                Transactions::Meta,
+            ])
+            .from(Transactions::Table)
+            .cond_where(filter.condition())
+            .order_by(Transactions::Date, order.clone())
+            .order_by(Transactions::Id, order);
+        if let Some(limit) = limit {
+            select.limit(limit);
+        }
+        let (query, values) = select.build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut postings = self.postings_by_txn(filter).await?;
+        let mut txns = Vec::with_capacity(rows.len());
+        for row in rows {
+            let id: String = row.try_get("id")?;
+            let status: String = row.try_get("status")?;
+
+            txns.push(Transaction {
+                postings: postings.remove(&id).unwrap_or_default(),
+                id: Ulid::from_string(&id)?,
+                status: Status::try_from(status)?,
+                date: NaiveDate::parse_from_str(row.try_get("date")?, "%Y-%m-%d")?,
+                payee: row.try_get("payee")?,
+                narration: row.try_get("narration")?,
+                tags: serde_json::from_str(row.try_get("tags")?)?,
+                meta: serde_json::from_str(row.try_get("meta")?)?,
+            });
+        }
+
+        Ok(txns)
+    }
+
+    async fn postings_by_txn(
+        &mut self,
+        filter: &TransactionFilter,
+    ) -> Result<HashMap<String, Vec<Posting>>> {
+        let txn_ids: SelectStatement = Query::select()
+            .column(Transactions::Id)
+            .from(Transactions::Table)
+            .cond_where(filter.condition())
+            .take();
+        let (query, values) = Query::select()
+            .columns([
+                Postings::TxnId,
+                Postings::Account,
+                Postings::Amount,
+                Postings::Currency,
+            ])
+            .from(Postings::Table)
+            .and_where(Expr::col(Postings::TxnId).in_subquery(txn_ids))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut postings: HashMap<String, Vec<Posting>> = HashMap::new();
+        for row in rows {
+            postings
+                .entry(row.try_get("txn_id")?)
+                .or_default()
+                .push(Posting {
+                    account: row.try_get("account")?,
+                    amount: Decimal::from_str(row.try_get("amount")?)?,
+                    currency: row.try_get("currency")?,
+                });
+        }
+
+        Ok(postings)
+    }
+
+    /// Counts transactions matching `filter`.
+    pub async fn count(&mut self, filter: &TransactionFilter) -> Result<i64> {
+        let (query, values) = Query::select()
+            .expr_as(
+                Func::count(Expr::col(Transactions::Id)),
+                Alias::new("count"),
+            )
+            .from(Transactions::Table)
+            .cond_where(filter.condition())
+            .build_sqlx(SqliteQueryBuilder);
+
+        Ok(sqlx::query_with(&query, values)
+            .fetch_one(&mut self.0.conn.acquire().await?)
+            .await?
+            .try_get("count")?)
+    }
+
+    /// Counts transactions matching `filter` with a posting to any of
+    /// `accounts`.
+    pub async fn count_posted_to(
+        &mut self,
+        filter: &TransactionFilter,
+        accounts: &[&str],
+    ) -> Result<i64> {
+        let (query, values) = Query::select()
+            .expr_as(
+                Func::count(Expr::col(Transactions::Id)),
+                Alias::new("count"),
+            )
+            .from(Transactions::Table)
+            .cond_where(filter.condition())
+            .and_where(
+                Expr::col(Transactions::Id).in_subquery(
+                    Query::select()
+                        .column(Postings::TxnId)
+                        .from(Postings::Table)
+                        .and_where(Expr::col(Postings::Account).is_in(accounts.iter().copied()))
+                        .take(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        Ok(sqlx::query_with(&query, values)
+            .fetch_one(&mut self.0.conn.acquire().await?)
+            .await?
+            .try_get("count")?)
+    }
+
+    /// The primary personal finance category of matching transactions keyed
+    /// by transaction id, transactions without one are left out.
+    pub async fn primary_categories(
+        &mut self,
+        filter: &TransactionFilter,
+    ) -> Result<HashMap<Ulid, String>> {
+        let (query, values) = Query::select()
+            .columns([Transactions::Id, Transactions::CategoryPrimary])
+            .from(Transactions::Table)
+            .cond_where(filter.condition())
+            .and_where(Expr::col(Transactions::CategoryPrimary).is_not_null())
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut categories = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let id: String = row.try_get("id")?;
+            categories.insert(Ulid::from_string(&id)?, row.try_get("category_primary")?);
+        }
+
+        Ok(categories)
+    }
+
+    /// Counts matching transactions by the country and region of their
+    /// upstream location, most frequent first. Transactions without a location
+    /// are counted together with no country or region.
+    pub async fn count_by_region(
+        &mut self,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<RegionCount>> {
+        let (query, values) = Query::select()
+            .column(Transactions::LocationCountry)
+            .column(Transactions::LocationRegion)
+            .expr_as(
+                Func::count(Expr::col(Transactions::Id)),
+                Alias::new("transactions"),
+            )
+            .from(Transactions::Table)
+            .cond_where(filter.condition())
+            .group_by_col(Transactions::LocationCountry)
+            .group_by_col(Transactions::LocationRegion)
+            .order_by(Alias::new("transactions"), Order::Desc)
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut counts = Vec::with_capacity(rows.len());
+        for row in rows {
+            counts.push(RegionCount {
+                country: row.try_get("location_country")?,
+                region: row.try_get("location_region")?,
+                transactions: row.try_get("transactions")?,
+            });
+        }
+
+        Ok(counts)
+    }
+
+    /// Returns the distinct account ids referenced by transactions that have no
+    /// matching row in the accounts table.
+    pub async fn orphaned_accounts(&mut self) -> Result<Vec<String>> {
+        #[derive(Iden)]
+        enum Accounts {
+            Table,
+            Id,
+        }
+
+        let (query, values) = Query::select()
+            .distinct()
+            .column(Transactions::AccountId)
+            .from(Transactions::Table)
+            .and_where(
+                Expr::col(Transactions::AccountId).not_in_subquery(
+                    Query::select()
+                        .column(Accounts::Id)
+                        .from(Accounts::Table)
+                        .take(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut accounts = Vec::with_capacity(rows.len());
+        for row in rows {
+            accounts.push(row.try_get("account_id")?);
+        }
+
+        Ok(accounts)
+    }
+
+    /// Counts postings whose transaction no longer exists.
+    pub async fn orphaned_postings(&mut self) -> Result<i64> {
+        let (query, values) = Query::select()
+            .expr_as(Func::count(Expr::col(Postings::Id)), Alias::new("count"))
+            .from(Postings::Table)
+            .and_where(
+                Expr::col(Postings::TxnId).not_in_subquery(
+                    Query::select()
+                        .column(Transactions::Id)
+                        .from(Transactions::Table)
+                        .take(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        Ok(sqlx::query_with(&query, values)
+            .fetch_one(&mut self.0.conn.acquire().await?)
+            .await?
+            .try_get("count")?)
+    }
+
+    /// Counts upstream connections whose transaction no longer exists.
+    pub async fn orphaned_connections(&mut self) -> Result<i64> {
+        let (query, values) = Query::select()
+            .expr_as(
+                Func::count(Expr::col(IntTransactionsLinks::TxnId)),
+                Alias::new("count"),
+            )
+            .from(IntTransactionsLinks::Table)
+            .and_where(
+                Expr::col(IntTransactionsLinks::TxnId).not_in_subquery(
+                    Query::select()
+                        .column(Transactions::Id)
+                        .from(Transactions::Table)
+                        .take(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        Ok(sqlx::query_with(&query, values)
+            .fetch_one(&mut self.0.conn.acquire().await?)
+            .await?
+            .try_get("count")?)
+    }
+
+    /// Deletes postings whose transaction no longer exists.
+    pub async fn delete_orphaned_postings(&mut self) -> Result<u64> {
+        let (query, values) = Query::delete()
+            .from_table(Postings::Table)
+            .and_where(
+                Expr::col(Postings::TxnId).not_in_subquery(
+                    Query::select()
+                        .column(Transactions::Id)
+                        .from(Transactions::Table)
+                        .take(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        Ok(self.0.execute(&query, values).await?.rows_affected())
+    }
+
+    /// Deletes upstream connections whose transaction no longer exists.
+    pub async fn delete_orphaned_connections(&mut self) -> Result<u64> {
+        let (query, values) = Query::delete()
+            .from_table(IntTransactionsLinks::Table)
+            .and_where(
+                Expr::col(IntTransactionsLinks::TxnId).not_in_subquery(
+                    Query::select()
+                        .column(Transactions::Id)
+                        .from(Transactions::Table)
+                        .take(),
+                ),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+
+        Ok(self.0.execute(&query, values).await?.rows_affected())
+    }
+
+    pub async fn reassign_account(&mut self, from: &str, to: &str) -> Result<u64> {
         let (query, values) = Query::update()
             .table(Transactions::Table)
-            .values(vec![(
-                Transactions::Source,
-                serde_json::to_string(&source)?.into(),
-            )])
-            .and_where(Expr::col(Transactions::Id).eq(id))
+            .values(vec![(Transactions::AccountId, to.into())])
+            .and_where(Expr::col(Transactions::AccountId).eq(from))
+            .build_sqlx(SqliteQueryBuilder);
+
+        Ok(self.0.execute(&query, values).await?.rows_affected())
+    }
+
+    pub async fn delete_by_account(&mut self, account_id: &str) -> Result<u64> {
+        let (query, values) = Query::select()
+            .column(Transactions::Id)
+            .from(Transactions::Table)
+            .and_where(Expr::col(Transactions::AccountId).eq(account_id))
             .build_sqlx(SqliteQueryBuilder);
 
-        sqlx::query_with(&query, values)
-            .execute(&mut self.0.conn.acquire().await?)
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
             .await?;
 
-        Ok(())
+        for row in &rows {
+            let id: String = row.try_get("id")?;
+            self.delete(&id).await?;
+        }
+
+        Ok(rows.len() as u64)
     }
 
-    pub async fn delete(&mut self, id: &str) -> Result<()> {
-        let (query, values) = Query::delete()
-            .from_table(Transactions::Table)
+    /// The upstream representation the transaction was saved from.
+    pub async fn source(&mut self, id: &str) -> Result<Option<String>> {
+        let (query, values) = Query::select()
+            .column(Transactions::Source)
+            .from(Transactions::Table)
             .and_where(Expr::col(Transactions::Id).eq(id))
             .build_sqlx(SqliteQueryBuilder);
 
-        sqlx::query_with(&query, values)
-            .execute(&mut self.0.conn.acquire().await?)
+        Ok(sqlx::query_with(&query, values)
+            .fetch_optional(&mut self.0.conn.acquire().await?)
+            .await?
+            .map(|row| row.try_get("source"))
+            .transpose()?)
+    }
+
+    pub async fn postings(&mut self, txn_id: &str) -> Result<Vec<Posting>> {
+        let (query, values) = Query::select()
+            .columns([Postings::Account, Postings::Amount, Postings::Currency])
+            .from(Postings::Table)
+            .and_where(Expr::col(Postings::TxnId).eq(txn_id))
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
             .await?;
 
+        let mut postings = Vec::with_capacity(rows.len());
+        for row in rows {
+            postings.push(Posting {
+                account: row.try_get("account")?,
+                amount: Decimal::from_str(row.try_get("amount")?)?,
+                currency: row.try_get("currency")?,
+            });
+        }
+
+        Ok(postings)
+    }
+
+    /// Marks a transaction as removed without deleting it, keeping previously
+    /// exported history stable.
+    pub async fn soft_delete(&mut self, id: &str) -> Result<()> {
+        let (query, values) = Query::update()
+            .table(Transactions::Table)
+            .values(vec![(Transactions::RemovedAt, Utc::now().to_rfc3339().into())])
+            .and_where(Expr::col(Transactions::Id).eq(id))
+            .build_sqlx(SqliteQueryBuilder);
+
+        self.0.execute(&query, values).await?;
+
         Ok(())
     }
 
-    pub async fn save<S: Serialize>(
-        &mut self,
-        account_id: &str,
-        tx: &TransactionEntry<S>,
-    ) -> Result<()> {
-        let source = tx.serialize_string()?;
-        let canonical = tx.canonical.clone();
-        let account_id = account_id.to_string();
+    pub async fn delete(&mut self, id: &str) -> Result<()> {
+        let id = id.to_string();
 
         self.0
             .conn
@@ -98,154 +708,1027 @@ impl<'a> Store<'a> {
             .await?
             .transaction(|conn| {
                 Box::pin(async move {
-                    let (query, values) = Query::insert()
-                        .into_table(Transactions::Table)
-                        .columns([
-                            Transactions::Id,
-                            Transactions::AccountId,
-                            Transactions::Source,
-                        ])
-                        .values_panic(vec![
-                            canonical.id.to_string().into(),
-                            account_id.into(),
-                            source.into(),
-                        ])
+                    let (query, values) = Query::delete()
+                        .from_table(Transfers::Table)
+                        .cond_where(
+                            Cond::any()
+                                .add(Expr::col(Transfers::TxnId).eq(id.as_str()))
+                                .add(Expr::col(Transfers::JoinedId).eq(id.as_str())),
+                        )
                         .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
 
-                    sqlx::query_with(&query, values).execute(conn).await?;
+                    let (query, values) = Query::delete()
+                        .from_table(Postings::Table)
+                        .and_where(Expr::col(Postings::TxnId).eq(id.as_str()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
 
-                    Ok(())
+                    let (query, values) = Query::delete()
+                        .from_table(IntTransactionsLinks::Table)
+                        .and_where(Expr::col(IntTransactionsLinks::TxnId).eq(id.as_str()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    let (query, values) = Query::delete()
+                        .from_table(Transactions::Table)
+                        .and_where(Expr::col(Transactions::Id).eq(id.as_str()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    Ok::<(), Error>(())
                 })
             })
             .await
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use chrono::NaiveDate;
-    use rplaid::model::Transaction as PlaidTransaction;
-    use ulid::Ulid;
+    /// Deletes every transaction dated before `before` along with its postings
+    /// and connections. Link cursors are left alone so syncing continues from
+    /// where it left off.
+    pub async fn prune_before(&mut self, before: NaiveDate) -> Result<PruneStats> {
+        let before = before.format("%Y-%m-%d").to_string();
 
-    use crate::core::{Account, Status, Transaction};
-    use crate::plaid::Link;
+        self.0
+            .conn
+            .acquire()
+            .await?
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let txn_ids = || -> SelectStatement {
+                        Query::select()
+                            .column(Transactions::Id)
+                            .from(Transactions::Table)
+                            .and_where(Expr::col(Transactions::Date).lt(before.as_str()))
+                            .take()
+                    };
+                    let mut stats = PruneStats::default();
 
-    use super::*;
+                    let (query, values) = Query::delete()
+                        .from_table(Transfers::Table)
+                        .cond_where(
+                            Cond::any()
+                                .add(Expr::col(Transfers::TxnId).in_subquery(txn_ids()))
+                                .add(Expr::col(Transfers::JoinedId).in_subquery(txn_ids())),
+                        )
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
 
-    fn plaid_transaction() -> PlaidTransaction {
-        PlaidTransaction {
-            transaction_type: "".to_string(),
-            pending_transaction_id: None,
-            category_id: None,
-            category: None,
-            location: None,
-            payment_meta: None,
-            account_owner: None,
-            name: "".to_string(),
-            original_description: None,
-            account_id: "test-account-id".to_string(),
-            amount: 33.into(),
-            iso_currency_code: None,
-            unofficial_currency_code: None,
-            date: "2022-05-01".to_string(),
-            pending: false,
-            transaction_id: "1234-test".to_string(),
-            payment_channel: "".to_string(),
-            merchant_name: None,
-            authorized_date: None,
-            authorized_datetime: None,
-            datetime: None,
-            check_number: None,
-            transaction_code: None,
-        }
-    }
+                    let (query, values) = Query::delete()
+                        .from_table(Postings::Table)
+                        .and_where(Expr::col(Postings::TxnId).in_subquery(txn_ids()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    stats.postings = sqlx::query_with(&query, values)
+                        .execute(&mut *conn)
+                        .await?
+                        .rows_affected();
 
-    async fn test_store() -> SqliteStore {
-        SqliteStore::new("sqlite::memory:").await.unwrap()
-    }
+                    let (query, values) = Query::delete()
+                        .from_table(IntTransactionsLinks::Table)
+                        .and_where(Expr::col(IntTransactionsLinks::TxnId).in_subquery(txn_ids()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    stats.connections = sqlx::query_with(&query, values)
+                        .execute(&mut *conn)
+                        .await?
+                        .rows_affected();
 
-    #[tokio::test]
-    async fn save_transaction() {
-        let mut store = test_store().await;
-        let link = Link {
-            institution_id: Some("10".to_string()),
-            alias: "test_link".to_string(),
-            access_token: "1234".to_string(),
-            item_id: "plaid-id-123".to_string(),
-            state: crate::plaid::LinkStatus::Active,
-            sync_cursor: None,
-        };
-        store.links().save(&link).await.unwrap();
-        store
-            .accounts()
-            .save(
-                &link.item_id,
-                &Account {
-                    id: "test-account-id".into(),
-                    ty: "CREDIT_NORMAL".into(),
-                    name: "Test Account".into(),
-                },
-            )
+                    let (query, values) = Query::delete()
+                        .from_table(Transactions::Table)
+                        .and_where(Expr::col(Transactions::Date).lt(before.as_str()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    stats.transactions = sqlx::query_with(&query, values)
+                        .execute(&mut *conn)
+                        .await?
+                        .rows_affected();
+
+                    Ok::<PruneStats, Error>(stats)
+                })
+            })
             .await
-            .unwrap();
+    }
 
-        let entry = TransactionEntry {
-            canonical: Transaction {
-                id: Ulid::new(),
-                date: NaiveDate::parse_from_str("2022-05-01", "%Y-%m-%d").unwrap(),
-                narration: "Test Transaction".to_string(),
-                payee: None,
-                status: Status::Resolved,
-            },
-            source: plaid_transaction(),
-        };
+    /// Ids of every transaction already part of a transfer, on either side.
+    pub async fn transfer_ids(&mut self) -> Result<HashSet<String>> {
+        let (query, values) = Query::select()
+            .columns([Transfers::TxnId, Transfers::JoinedId])
+            .from(Transfers::Table)
+            .build_sqlx(SqliteQueryBuilder);
+
+        let rows = sqlx::query_with(&query, values)
+            .fetch_all(&mut self.0.conn.acquire().await?)
+            .await?;
+
+        let mut ids = HashSet::with_capacity(rows.len() * 2);
+        for row in rows {
+            ids.insert(row.try_get("txn_id")?);
+            ids.insert(row.try_get("joined_id")?);
+        }
+
+        Ok(ids)
+    }
+
+    /// Joins a transfer's two transactions: the outflow's postings are
+    /// replaced by the joined postings and the inflow is marked removed, the
+    /// same way upstream removals are kept, so it's no longer exported while
+    /// syncs still recognize it.
+    pub async fn join_transfer(&mut self, transfer: &Transfer) -> Result<()> {
+        let outflow = transfer.outflow.to_string();
+        let inflow = transfer.inflow.to_string();
+        let postings = transfer.joined.postings.clone();
+        let now = Utc::now().to_rfc3339();
+
+        self.0
+            .conn
+            .acquire()
+            .await?
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let (query, values) = Query::delete()
+                        .from_table(Postings::Table)
+                        .and_where(Expr::col(Postings::TxnId).eq(outflow.as_str()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    for posting in &postings {
+                        insert_posting(&mut **conn, &outflow, posting).await?;
+                    }
+
+                    let (query, values) = Query::update()
+                        .table(Transactions::Table)
+                        .values(vec![(Transactions::RemovedAt, now.as_str().into())])
+                        .and_where(Expr::col(Transactions::Id).eq(inflow.as_str()))
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    let (query, values) = Query::insert()
+                        .into_table(Transfers::Table)
+                        .columns([Transfers::TxnId, Transfers::JoinedId, Transfers::CreatedAt])
+                        .values_panic(vec![
+                            outflow.as_str().into(),
+                            inflow.as_str().into(),
+                            now.as_str().into(),
+                        ])
+                        .build_sqlx(SqliteQueryBuilder);
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    Ok::<(), Error>(())
+                })
+            })
+            .await
+    }
+
+    /// Saves a transaction along with its postings and a connection row tying
+    /// it back to the upstream item and transaction it was synced from.
+    pub async fn save<S: Serialize>(
+        &mut self,
+        item_id: &str,
+        upstream_id: &str,
+        account_id: &str,
+        tx: &TransactionEntry<S>,
+    ) -> Result<()> {
+        let source = tx.serialize_string()?;
+        let tags = serde_json::to_string(&tx.canonical.tags)?;
+        let meta = serde_json::to_string(&tx.canonical.meta)?;
+        let canonical = tx.canonical.clone();
+        let item_id = item_id.to_string();
+        let upstream_id = upstream_id.to_string();
+        let account_id = account_id.to_string();
+
+        self.0
+            .conn
+            .acquire()
+            .await?
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let txn_id = canonical.id.to_string();
+                    let (query, values) = Query::insert()
+                        .into_table(Transactions::Table)
+                        .columns([
+                            Transactions::Id,
+                            Transactions::AccountId,
+                            Transactions::Source,
+                            Transactions::Date,
+                            Transactions::Narration,
+                            Transactions::Payee,
+                            Transactions::Status,
+                            Transactions::Tags,
+                            Transactions::Meta,
+                        ])
+                        .values_panic(vec![
+                            txn_id.as_str().into(),
+                            account_id.into(),
+                            source.into(),
+                            canonical.date.format("%Y-%m-%d").to_string().into(),
+                            canonical.narration.as_str().into(),
+                            canonical.payee.as_deref().into(),
+                            canonical.status.to_string().into(),
+                            tags.into(),
+                            meta.into(),
+                        ])
+                        .build_sqlx(SqliteQueryBuilder);
+
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    let (query, values) = Query::insert()
+                        .into_table(IntTransactionsLinks::Table)
+                        .columns([
+                            IntTransactionsLinks::TxnId,
+                            IntTransactionsLinks::ItemId,
+                            IntTransactionsLinks::UpstreamId,
+                        ])
+                        .values_panic(vec![
+                            txn_id.as_str().into(),
+                            item_id.into(),
+                            upstream_id.into(),
+                        ])
+                        .build_sqlx(SqliteQueryBuilder);
+
+                    sqlx::query_with(&query, values).execute(&mut *conn).await?;
+
+                    for posting in &canonical.postings {
+                        insert_posting(&mut **conn, &txn_id, posting).await?;
+                    }
+
+                    Ok(())
+                })
+            })
+            .await
+    }
+}
+
+async fn insert_posting(
+    conn: &mut SqliteConnection,
+    txn_id: &str,
+    posting: &Posting,
+) -> Result<()> {
+    let (query, values) = Query::insert()
+        .into_table(Postings::Table)
+        .columns([
+            Postings::Id,
+            Postings::TxnId,
+            Postings::Account,
+            Postings::Amount,
+            Postings::Currency,
+        ])
+        .values_panic(vec![
+            Ulid::new().to_string().into(),
+            txn_id.into(),
+            posting.account.as_str().into(),
+            posting.amount.to_string().into(),
+            posting.currency.as_str().into(),
+        ])
+        .build_sqlx(SqliteQueryBuilder);
+
+    sqlx::query_with(&query, values).execute(conn).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
 
-        store.txns().save("test-account-id", &entry).await.unwrap();
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+    use ulid::Ulid;
+
+    use super::{PruneStats, RegionCount, TransactionFilter};
+    use crate::core::{Posting, Status};
+    use crate::store::test_support::{LinkBuilder, TestStore, TransactionBuilder};
+    use crate::upstream::TransactionEntry;
+
+    async fn store_with_account() -> (TestStore, String) {
+        let mut store = TestStore::new().await;
+        let link = LinkBuilder::new()
+            .item_id("plaid-id-123")
+            .institution_id("10")
+            .build();
+        store.db().links().save(&link).await.unwrap();
+        let account = store.new_account(&link.item_id).await;
+
+        (store, account.id)
     }
 
     #[tokio::test]
-    async fn delete() {
-        let mut store = test_store().await;
-        let link = Link {
-            institution_id: Some("10".to_string()),
-            alias: "test_link".to_string(),
-            access_token: "1234".to_string(),
-            item_id: "plaid-id-123".to_string(),
-            state: crate::plaid::LinkStatus::Active,
-            sync_cursor: None,
-        };
-        store.links().save(&link).await.unwrap();
+    async fn save_transaction() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let entry = TransactionBuilder::new().account_id(&account_id).build();
+
         store
-            .accounts()
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn by_upstream_id() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let entry = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("upstream-txn-id")
+            .build();
+        store
+            .db()
+            .txns()
             .save(
-                &link.item_id,
-                &Account {
-                    id: "test-account-id".into(),
-                    ty: "CREDIT_NORMAL".into(),
-                    name: "Test Account".into(),
-                },
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
             )
             .await
             .unwrap();
 
+        let id = store
+            .db()
+            .txns()
+            .by_upstream_id("upstream-txn-id")
+            .await
+            .unwrap();
+        assert_eq!(id, Some(entry.canonical.id.to_string()));
+
+        let source = store
+            .db()
+            .txns()
+            .source(&entry.canonical.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(source, Some(entry.serialize_string().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let (mut store, account_id) = store_with_account().await;
+
         let txn_id = Ulid::new();
-        let entry = TransactionEntry {
-            canonical: Transaction {
-                id: txn_id.clone(),
-                date: NaiveDate::parse_from_str("2022-05-01", "%Y-%m-%d").unwrap(),
-                narration: "Test Transaction".to_string(),
-                payee: None,
-                status: Status::Resolved,
-            },
-            source: plaid_transaction(),
-        };
+        let entry = TransactionBuilder::new()
+            .id(txn_id)
+            .account_id(&account_id)
+            .build();
 
-        store.txns().save("test-account-id", &entry).await.unwrap();
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
 
         store
+            .db()
             .txns()
             .delete(txn_id.to_string().as_str())
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn save_transaction_postings() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let mut entry = TransactionBuilder::new().account_id(&account_id).build();
+        entry.canonical.postings = vec![
+            Posting {
+                account: account_id.clone(),
+                amount: Decimal::new(-3300, 2),
+                currency: "USD".into(),
+            },
+            Posting {
+                account: "Expenses:Uncategorized".into(),
+                amount: Decimal::new(3300, 2),
+                currency: "USD".into(),
+            },
+        ];
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+
+        let mut postings = store
+            .db()
+            .txns()
+            .postings(&entry.canonical.id.to_string())
+            .await
+            .unwrap();
+        postings.sort_by(|a, b| a.amount.cmp(&b.amount));
+        assert_eq!(postings, entry.canonical.postings);
+    }
+
+    #[tokio::test]
+    async fn update_replaces_postings() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let mut entry = TransactionBuilder::new().account_id(&account_id).build();
+        entry.canonical.postings = vec![Posting {
+            account: "Expenses:Uncategorized".into(),
+            amount: Decimal::new(3300, 2),
+            currency: "USD".into(),
+        }];
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+
+        let id = entry.canonical.id.to_string();
+        entry.canonical.postings[0].amount = Decimal::new(4000, 2);
+        store.db().txns().update(&id, &entry).await.unwrap();
+
+        let postings = store.db().txns().postings(&id).await.unwrap();
+        assert_eq!(postings, entry.canonical.postings);
+
+        let uncategorized = store
+            .db()
+            .txns()
+            .count_posted_to(&TransactionFilter::default(), &["Expenses:Uncategorized"])
+            .await
+            .unwrap();
+        assert_eq!(uncategorized, 1);
+    }
+
+    #[tokio::test]
+    async fn update_restores_soft_deleted() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let entry = TransactionBuilder::new().account_id(&account_id).build();
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+
+        let id = entry.canonical.id.to_string();
+        store.db().txns().soft_delete(&id).await.unwrap();
+        store.db().txns().update(&id, &entry).await.unwrap();
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, entry.canonical.id);
+    }
+
+    #[tokio::test]
+    async fn list_rejects_unknown_status() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let entry = TransactionBuilder::new().account_id(&account_id).build();
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+        let mut conn = store.db().conn.acquire().await.unwrap();
+        sqlx::query("UPDATE transactions SET status = 'SETTLED'")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let err = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("SETTLED"));
+    }
+
+    #[tokio::test]
+    async fn orphaned_accounts() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let entry = TransactionBuilder::new().account_id(&account_id).build();
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+
+        // Foreign keys prevent orphans from being created through the store,
+        // older databases may have been written without enforcement.
+        let mut conn = store.db().conn.acquire().await.unwrap();
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO transactions (id, account_id, source) VALUES ('orphan', 'missing', '{}')",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        sqlx::query("PRAGMA foreign_keys = ON")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let orphans = store.db().txns().orphaned_accounts().await.unwrap();
+        assert_eq!(orphans, vec!["missing".to_string()]);
+
+        let moved = store
+            .db()
+            .txns()
+            .reassign_account("missing", &account_id)
+            .await
+            .unwrap();
+        assert_eq!(moved, 1);
+        assert!(store
+            .db()
+            .txns()
+            .orphaned_accounts()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_transactions() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let mut first = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("first")
+            .build();
+        first.canonical.postings = vec![Posting {
+            account: account_id.clone(),
+            amount: Decimal::new(-100, 2),
+            currency: "USD".into(),
+        }];
+        let second = TransactionBuilder::new()
+            .id(Ulid::from_parts(first.canonical.id.timestamp_ms() + 1000, 0))
+            .account_id(&account_id)
+            .transaction_id("second")
+            .build();
+        for entry in [&first, &second] {
+            store
+                .db()
+                .txns()
+                .save(
+                    "plaid-id-123",
+                    &entry.source.transaction_id,
+                    &account_id,
+                    entry,
+                )
+                .await
+                .unwrap();
+        }
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 2);
+        assert_eq!(txns[0].id, first.canonical.id);
+        assert_eq!(txns[0].postings, first.canonical.postings);
+        assert!(txns[1].postings.is_empty());
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter {
+                created_after: Some(second.canonical.id),
+                ..TransactionFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, second.canonical.id);
+    }
+
+    #[tokio::test]
+    async fn soft_delete_keeps_tombstone() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let entry = TransactionBuilder::new().account_id(&account_id).build();
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+
+        store
+            .db()
+            .txns()
+            .soft_delete(&entry.canonical.id.to_string())
+            .await
+            .unwrap();
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert!(txns.is_empty());
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter {
+                include_removed: true,
+                ..TransactionFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, entry.canonical.id);
+    }
+
+    #[tokio::test]
+    async fn prune_before() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let mut old = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("old")
+            .build();
+        old.canonical.date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        old.canonical.postings = vec![Posting {
+            account: account_id.clone(),
+            amount: Decimal::new(-100, 2),
+            currency: "USD".into(),
+        }];
+        let mut recent = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("recent")
+            .build();
+        recent.canonical.date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        for entry in [&old, &recent] {
+            store
+                .db()
+                .txns()
+                .save(
+                    "plaid-id-123",
+                    &entry.source.transaction_id,
+                    &account_id,
+                    entry,
+                )
+                .await
+                .unwrap();
+        }
+
+        let stats = store
+            .db()
+            .txns()
+            .prune_before(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            PruneStats {
+                transactions: 1,
+                postings: 1,
+                connections: 1,
+            }
+        );
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, recent.canonical.id);
+    }
+
+    #[tokio::test]
+    async fn list_by_status() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let resolved = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("resolved")
+            .build();
+        let mut pending = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("pending")
+            .build();
+        pending.canonical.status = Status::Pending;
+        for entry in [&resolved, &pending] {
+            store
+                .db()
+                .txns()
+                .save(
+                    "plaid-id-123",
+                    &entry.source.transaction_id,
+                    &account_id,
+                    entry,
+                )
+                .await
+                .unwrap();
+        }
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter {
+                status: Some(Status::Pending),
+                ..TransactionFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, pending.canonical.id);
+    }
+
+    #[tokio::test]
+    async fn filter_by_category() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let categories = [
+            Some(("FOOD_AND_DRINK", "FOOD_AND_DRINK_COFFEE")),
+            Some(("TRAVEL", "TRAVEL_FLIGHTS")),
+            None,
+        ];
+        let mut ids = vec![];
+        for (i, category) in categories.into_iter().enumerate() {
+            let mut builder = TransactionBuilder::new().account_id(&account_id);
+            if let Some((primary, detailed)) = category {
+                builder = builder.category(primary, detailed);
+            }
+            let entry = builder.build();
+            ids.push(entry.canonical.id);
+
+            store
+                .db()
+                .txns()
+                .save("plaid-id-123", &format!("txn-{}", i), &account_id, &entry)
+                .await
+                .unwrap();
+        }
+
+        for category in ["food_and_drink", "FOOD_AND_DRINK_COFFEE"] {
+            let txns = store
+                .db()
+                .txns()
+                .list(&TransactionFilter {
+                    category: Some(category.into()),
+                    ..TransactionFilter::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(txns.len(), 1);
+            assert_eq!(txns[0].id, ids[0]);
+        }
+
+        let categories = store
+            .db()
+            .txns()
+            .primary_categories(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(categories.len(), 2);
+        assert_eq!(categories[&ids[1]], "TRAVEL");
+    }
+
+    #[tokio::test]
+    async fn count_by_region() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let regions = [Some("CA"), Some("CA"), Some("NY"), None];
+        for (i, region) in regions.into_iter().enumerate() {
+            let entry = TransactionBuilder::new().account_id(&account_id).build();
+            let mut source = serde_json::to_value(&entry.source).unwrap();
+            source["location"] = serde_json::json!({
+                "city": null,
+                "region": region,
+                "country": region.map(|_| "US"),
+            });
+            let entry = TransactionEntry {
+                canonical: entry.canonical,
+                source,
+            };
+
+            store
+                .db()
+                .txns()
+                .save("plaid-id-123", &format!("txn-{}", i), &account_id, &entry)
+                .await
+                .unwrap();
+        }
+
+        let counts = store
+            .db()
+            .txns()
+            .count_by_region(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            counts[0],
+            RegionCount {
+                country: Some("US".into()),
+                region: Some("CA".into()),
+                transactions: 2,
+            }
+        );
+        assert_eq!(counts.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn list_by_account_and_date() {
+        let (mut store, account_id) = store_with_account().await;
+        let other_account = store.new_account("plaid-id-123").await.id;
+
+        for (upstream_id, account, date) in [
+            ("early", &account_id, "2022-04-30"),
+            ("in-range", &account_id, "2022-05-01"),
+            ("other-account", &other_account, "2022-05-01"),
+            ("late", &account_id, "2022-05-02"),
+        ] {
+            let mut entry = TransactionBuilder::new().account_id(account).build();
+            entry.canonical.date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+            store
+                .db()
+                .txns()
+                .save("plaid-id-123", upstream_id, account, &entry)
+                .await
+                .unwrap();
+        }
+
+        let day = NaiveDate::from_ymd_opt(2022, 5, 1).unwrap();
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter {
+                account_id: Some(account_id.clone()),
+                since: Some(day),
+                until: Some(day),
+                ..TransactionFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].date, day);
+    }
+
+    #[tokio::test]
+    async fn search() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let mut coffee = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("coffee")
+            .build();
+        coffee.source.merchant_name = Some("Blue Bottle Coffee".into());
+        let mut rent = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("rent")
+            .build();
+        rent.canonical.narration = "Monthly Rent".into();
+        let mut groceries = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("groceries")
+            .build();
+        groceries.source.name = "COFFEE BEANS MARKET".into();
+        groceries.canonical.date = NaiveDate::from_ymd_opt(2022, 6, 1).unwrap();
+        for entry in [&coffee, &rent, &groceries] {
+            store
+                .db()
+                .txns()
+                .save(
+                    "plaid-id-123",
+                    &entry.source.transaction_id,
+                    &account_id,
+                    entry,
+                )
+                .await
+                .unwrap();
+        }
+
+        let txns = store.db().txns().search("coffee", None).await.unwrap();
+        assert_eq!(
+            txns.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![groceries.canonical.id, coffee.canonical.id]
+        );
+
+        let txns = store.db().txns().search("RENT", None).await.unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, rent.canonical.id);
+
+        let txns = store.db().txns().search("coffee", Some(1)).await.unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, groceries.canonical.id);
+    }
+
+    #[tokio::test]
+    async fn search_matches_wildcards_literally() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let mut discount = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("discount")
+            .build();
+        discount.canonical.narration = "100% Cashback".into();
+        let mut other = TransactionBuilder::new()
+            .account_id(&account_id)
+            .transaction_id("other")
+            .build();
+        other.canonical.narration = "1000 Points".into();
+        for entry in [&discount, &other] {
+            store
+                .db()
+                .txns()
+                .save(
+                    "plaid-id-123",
+                    &entry.source.transaction_id,
+                    &account_id,
+                    entry,
+                )
+                .await
+                .unwrap();
+        }
+
+        let txns = store.db().txns().search("100%", None).await.unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, discount.canonical.id);
+
+        let txns = store.db().txns().search("10_0", None).await.unwrap();
+        assert!(txns.is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_keeps_tags_and_meta() {
+        let (mut store, account_id) = store_with_account().await;
+
+        let mut entry = TransactionBuilder::new().account_id(&account_id).build();
+        entry.canonical.tags = vec!["travel".into()];
+        entry.canonical.meta = BTreeMap::from([("Trip".to_string(), "Berlin".to_string())]);
+        store
+            .db()
+            .txns()
+            .save(
+                "plaid-id-123",
+                &entry.source.transaction_id,
+                &account_id,
+                &entry,
+            )
+            .await
+            .unwrap();
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns[0].tags, entry.canonical.tags);
+        assert_eq!(txns[0].meta, entry.canonical.meta);
+    }
 }
@@ -1,90 +1,1353 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
 use clap::ArgMatches;
-use tracing::info;
+use rplaid::model;
+use rplaid::HttpClient;
+use rust_decimal::Decimal;
+use rusty_money::iso::Currency;
+use tabwriter::TabWriter;
+use tracing::{info, warn};
+use ulid::Ulid;
 
-use crate::plaid::{default_plaid_client, Link};
+use crate::core::{detect_transfers, Account, Posting, Status, Transaction};
+use crate::currency::{find_currency, resolve_currency};
+use crate::ledger;
+use crate::plaid::sync::SyncClient;
+use crate::plaid::{http_client, Link, LinkStatus};
 use crate::settings::Settings;
-use crate::store::SqliteStore;
-use crate::upstream::{plaid::Source, TransactionEvent, TransactionSource};
+use crate::store::{link::LOCAL_ITEM_ID, SqliteStore, TransactionFilter};
+use crate::upstream::{
+    plaid::{PlaidError, Source, SyncOptions},
+    AccountSource, TransactionEntry, TransactionEvent, TransactionSource,
+};
+
+const LAST_EXPORTED_AT: &str = "last_exported_at";
+
+/// Meta key holding the error from a link's last failed sync, cleared once
+/// the link syncs successfully.
+pub(crate) fn sync_error_key(item_id: &str) -> String {
+    format!("sync_error.{}", item_id)
+}
+
+/// The outcome of syncing every link in the store.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub per_link: Vec<LinkSyncResult>,
+    /// Transfers joined after syncing, when detection was requested.
+    pub transfers: Option<usize>,
+}
+
+impl SyncReport {
+    pub fn failed(&self) -> impl Iterator<Item = &LinkSyncResult> {
+        self.per_link
+            .iter()
+            .filter(|r| matches!(r.status, LinkSyncStatus::Failed(_)))
+    }
+
+    pub fn login_required(&self) -> impl Iterator<Item = &LinkSyncResult> {
+        self.per_link
+            .iter()
+            .filter(|r| matches!(r.status, LinkSyncStatus::LoginRequired(_)))
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkSyncResult {
+    pub item_id: String,
+    pub alias: String,
+    pub status: LinkSyncStatus,
+    pub added: usize,
+    pub modified: usize,
+    pub removed: usize,
+    /// Added transactions that were already stored.
+    pub refetched: usize,
+    pub previous_cursor: Option<String>,
+    pub next_cursor: Option<String>,
+    pub elapsed: Duration,
+}
+
+impl LinkSyncResult {
+    pub(crate) fn new(link: &Link) -> Self {
+        Self {
+            item_id: link.item_id.clone(),
+            alias: link.alias.clone(),
+            status: LinkSyncStatus::Synced,
+            added: 0,
+            modified: 0,
+            removed: 0,
+            refetched: 0,
+            previous_cursor: link.sync_cursor.clone(),
+            next_cursor: link.sync_cursor.clone(),
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    fn name(&self) -> &str {
+        if self.alias.is_empty() {
+            &self.item_id
+        } else {
+            &self.alias
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LinkSyncStatus {
+    Synced,
+    Skipped(String),
+    Failed(String),
+    /// The item needs re-authentication and was marked degraded.
+    LoginRequired(String),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PullOptions {
+    /// Refresh the item's accounts before saving transactions against them.
+    pub(crate) accounts_first: bool,
+    /// Write every processed event to stdout as newline delimited JSON.
+    pub(crate) json: bool,
+    /// Stop pulling a link's transactions after this many, see
+    /// [`SyncOptions::max_transactions`].
+    pub(crate) max_transactions: Option<usize>,
+    /// Clear every link's cursor first, pulling all available history again.
+    pub(crate) reset_cursors: bool,
+    /// Pull transactions dated within this inclusive range instead of
+    /// syncing from the cursor, see [`Source::transactions_between`].
+    pub(crate) date_range: Option<(NaiveDate, NaiveDate)>,
+    /// Join transfers between tracked accounts once every link is synced.
+    pub(crate) detect_transfers: bool,
+}
+
+/// Resolves the `--since` and `--until` flags, an open ended range ends
+/// `today`.
+fn date_range(
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    today: NaiveDate,
+) -> Result<Option<(NaiveDate, NaiveDate)>> {
+    match (since, until) {
+        (None, None) => Ok(None),
+        (None, Some(_)) => Err(anyhow!("--until requires --since")),
+        (Some(since), until) => {
+            let until = until.unwrap_or(today);
+            if since > until {
+                return Err(anyhow!("--since {} is after --until {}", since, until));
+            }
+
+            Ok(Some((since, until)))
+        }
+    }
+}
 
 #[tracing::instrument]
-async fn pull(settings: Settings) -> Result<()> {
+async fn pull(settings: &Settings, opts: &PullOptions) -> Result<SyncReport> {
     let mut store = SqliteStore::new(&settings.db_file).await?;
-    let plaid = default_plaid_client(&settings.plaid);
+    let client = SyncClient::new(&settings.plaid, http_client(&settings.plaid)?);
+
+    let mut report = sync_links(settings, &client, &mut store, opts).await?;
+    if opts.detect_transfers {
+        report.transfers = Some(join_transfers(&mut store).await?);
+    }
+
+    Ok(report)
+}
+
+/// Joins transfers between tracked accounts found among stored transactions.
+/// Transactions already part of a transfer are skipped so running it again
+/// joins nothing new.
+pub(crate) async fn join_transfers(store: &mut SqliteStore) -> Result<usize> {
+    let joined = store.txns().transfer_ids().await?;
+    let txns: Vec<Transaction> = store
+        .txns()
+        .list(&TransactionFilter::default())
+        .await?
+        .into_iter()
+        .filter(|txn| !joined.contains(&txn.id.to_string()))
+        .collect();
+    let accounts = store.accounts().list().await?;
+    let account_ids: HashSet<&str> = accounts.iter().map(|a| a.id.as_str()).collect();
+
+    let transfers = detect_transfers(&txns, &account_ids);
+    for transfer in &transfers {
+        store.txns().join_transfer(transfer).await?;
+    }
+
+    Ok(transfers.len())
+}
+
+/// Syncs every link in the store. A failing link doesn't stop the others,
+/// links found to need re-authentication are marked degraded.
+async fn sync_links<T: HttpClient>(
+    settings: &Settings,
+    client: &SyncClient<T>,
+    store: &mut SqliteStore,
+    opts: &PullOptions,
+) -> Result<SyncReport> {
     let links: Vec<Link> = store.links().list().await?;
 
-    for link in links {
-        let mut upstream = Source::new(&plaid, link.access_token.clone(), link.sync_cursor.clone());
-
-        info!("Pulling transactions for item {}.", link.item_id);
-        let mut added_count = 0;
-        let mut modified_count = 0;
-        let mut removed_count = 0;
-        for tx in upstream.transactions().await? {
-            match tx {
-                TransactionEvent::Added(entry) => {
-                    if !entry.source.pending {
-                        if let Some(pending_txn_id) = &entry.source.pending_transaction_id {
-                            let canonical_id = store.txns().by_upstream_id(pending_txn_id).await?;
-
-                            info!("update of existing transaction. id={:?}", canonical_id);
-                        }
+    let mut report = SyncReport::default();
+    for mut link in links {
+        if opts.reset_cursors && link.sync_cursor.is_some() {
+            info!("Clearing cursor for item {}.", link.item_id);
+
+            link.sync_cursor = None;
+            store.links().update(&link).await?;
+        }
 
-                        store.txns().save(&entry.source.account_id, &entry).await?;
+        let mut result = LinkSyncResult::new(&link);
+        let start = Instant::now();
 
-                        added_count += 1;
+        match &link.state {
+            LinkStatus::Degraded(reason) if settings.skip_degraded_links => {
+                result.status = LinkSyncStatus::Skipped(format!(
+                    "{}, run `clerk link --update {}` to re-authenticate",
+                    reason, link.item_id
+                ));
+            }
+            _ if !link.supports_transactions() => {
+                result.status = LinkSyncStatus::Skipped(format!(
+                    "transactions are not enabled for this item, enabled products are {}",
+                    link.products.join(", ")
+                ));
+            }
+            _ => {
+                let error_key = sync_error_key(&link.item_id);
+                match sync_link(settings, client, store, link, opts, &mut result).await {
+                    Ok(()) => store.meta().delete(&error_key).await?,
+                    Err(err) => {
+                        let message = describe_failure(&err, &result.item_id);
+                        store.meta().set(&error_key, &message).await?;
+
+                        if let Some(reason) = login_required(&err) {
+                            info!("Marking item {} as degraded: {}", result.item_id, reason);
+
+                            if let Some(mut link) = store.links().by_id(&result.item_id).await? {
+                                link.state = LinkStatus::Degraded(reason);
+                                store.links().update(&link).await?;
+                            }
+                            result.status = LinkSyncStatus::LoginRequired(message);
+                        } else {
+                            result.status = LinkSyncStatus::Failed(message);
+                        }
                     }
                 }
-                TransactionEvent::Modified(entry) => {
+            }
+        }
+
+        result.elapsed = start.elapsed();
+        report.per_link.push(result);
+    }
+
+    Ok(report)
+}
+
+/// Describes a failed sync including any Plaid error code, suggesting a fix
+/// when the user needs to act.
+pub(crate) fn describe_failure(err: &anyhow::Error, item_id: &str) -> String {
+    match login_required(err) {
+        Some(_) => format!(
+            "{:#}, run `clerk link --update {}` to re-authenticate",
+            err, item_id
+        ),
+        None => format!("{:#}", err),
+    }
+}
+
+/// Returns Plaid's message when `err` means the item's credentials must be
+/// updated through Link before it can sync again.
+fn login_required(err: &anyhow::Error) -> Option<String> {
+    match err.downcast_ref::<PlaidError>()? {
+        PlaidError::Api {
+            error_code,
+            message,
+            ..
+        } if error_code == "ITEM_LOGIN_REQUIRED" => Some(message.clone()),
+        _ => None,
+    }
+}
+
+pub(crate) async fn sync_link<T: HttpClient>(
+    settings: &Settings,
+    client: &SyncClient<T>,
+    store: &mut SqliteStore,
+    link: Link,
+    opts: &PullOptions,
+    result: &mut LinkSyncResult,
+) -> Result<()> {
+    let mut upstream = Source::new(client, link.access_token.clone(), link.sync_cursor.clone())
+        .with_options(SyncOptions {
+            max_transactions: opts.max_transactions,
+            ..SyncOptions::from(settings)
+        });
+
+    // Transactions reference their account, make sure every account the
+    // item reports exists before saving anything against it.
+    if opts.accounts_first {
+        for account in upstream.accounts().await? {
+            let account = Account::from(account);
+            if store.accounts().by_id(&account.id).await?.is_none() {
+                info!("Adding missing account. id={}", account.id);
+                store.accounts().save(&link.item_id, &account).await?;
+            }
+        }
+    }
+
+    info!("Pulling transactions for item {}.", link.item_id);
+    let events = match opts.date_range {
+        Some((since, until)) => upstream.transactions_between(since, until).await?,
+        None => upstream.transactions().await?,
+    };
+    let mut stdout = std::io::stdout().lock();
+    for tx in events {
+        let event = match opts.json {
+            true => Some(serde_json::to_string(&tx)?),
+            false => None,
+        };
+
+        match tx {
+            TransactionEvent::Added(entry) => {
+                if !entry.source.pending || settings.store_pending {
+                    if let Some(pending_txn_id) = &entry.source.pending_transaction_id {
+                        let canonical_id = store.txns().by_upstream_id(pending_txn_id).await?;
+
+                        info!("update of existing transaction. id={:?}", canonical_id);
+                    }
+
+                    // Pulling from an earlier cursor replays transactions that
+                    // are already stored, refresh them instead of duplicating.
                     match store
                         .txns()
                         .by_upstream_id(&entry.source.transaction_id)
                         .await?
                     {
                         Some(id) => {
-                            store.txns().update_source(&id, entry.source).await?;
+                            store.txns().update(&id, &entry).await?;
 
-                            modified_count += 1;
+                            result.refetched += 1;
+                        }
+                        None => {
+                            store
+                                .txns()
+                                .save(
+                                    &link.item_id,
+                                    &entry.source.transaction_id,
+                                    &entry.source.account_id,
+                                    &entry,
+                                )
+                                .await?;
+
+                            result.added += 1;
                         }
-                        None => return Err(anyhow!("transaction modified with no base")),
                     }
                 }
-                TransactionEvent::Removed(id) => {
-                    store.txns().delete(&id).await?;
+            }
+            TransactionEvent::Modified(entry) => {
+                match store
+                    .txns()
+                    .by_upstream_id(&entry.source.transaction_id)
+                    .await?
+                {
+                    Some(id) => {
+                        store.txns().update(&id, &entry).await?;
+
+                        result.modified += 1;
+                    }
+                    None => return Err(anyhow!("transaction modified with no base")),
+                }
+            }
+            TransactionEvent::Removed(upstream_id) => {
+                match store.txns().by_upstream_id(&upstream_id).await? {
+                    Some(id) => {
+                        if settings.soft_delete {
+                            store.txns().soft_delete(&id).await?;
+                        } else {
+                            store.txns().delete(&id).await?;
+                        }
 
-                    removed_count += 1;
+                        result.removed += 1;
+                    }
+                    None => info!("Removed transaction was never saved. id={}", upstream_id),
                 }
             }
         }
 
+        if let Some(event) = event {
+            writeln!(stdout, "{}", event)?;
+        }
+    }
+
+    info!(
+        "{} total transactions. added={} modified={} removed={}",
+        result.added + result.modified + result.removed,
+        result.added,
+        result.modified,
+        result.removed
+    );
+
+    // A dated pull doesn't move the cursor.
+    if opts.date_range.is_some() {
+        return Ok(());
+    }
+
+    let updated_link = Link {
+        sync_cursor: Some(upstream.next_cursor()),
+        ..link
+    };
+    if updated_link.sync_cursor != result.previous_cursor {
         info!(
-            "{} total transactions. added={} modified={} removed={}",
-            added_count + modified_count + removed_count,
-            added_count,
-            modified_count,
-            removed_count
+            "Updating link with latest cursor. cursor={:?}",
+            &updated_link.sync_cursor
         );
+        store.links().update(&updated_link).await?;
+        result.next_cursor = updated_link.sync_cursor;
+    }
 
-        let updated_link = Link {
-            sync_cursor: Some(upstream.next_cursor()),
-            ..link
-        };
-        if updated_link.sync_cursor != link.sync_cursor {
-            info!(
-                "Updating link with latest cursor. cursor={:?}",
-                &updated_link.sync_cursor
+    Ok(())
+}
+
+fn print_report<W: Write>(mut wr: W, report: &SyncReport) -> Result<()> {
+    for result in &report.per_link {
+        match &result.status {
+            LinkSyncStatus::Synced => writeln!(
+                wr,
+                "Synced {} in {:.1}s: added={} modified={} removed={} refetched={} cursor_updated={}",
+                result.name(),
+                result.elapsed.as_secs_f64(),
+                result.added,
+                result.modified,
+                result.removed,
+                result.refetched,
+                result.next_cursor != result.previous_cursor
+            ),
+            LinkSyncStatus::Skipped(reason) => {
+                writeln!(wr, "Skipping {}: {}.", result.name(), reason)
+            }
+            LinkSyncStatus::Failed(err) => {
+                writeln!(wr, "Failed to sync {}: {}", result.name(), err)
+            }
+            LinkSyncStatus::LoginRequired(err) => {
+                writeln!(wr, "Skipping {}: {}", result.name(), err)
+            }
+        }?;
+    }
+
+    if let Some(transfers) = report.transfers {
+        writeln!(
+            wr,
+            "Joined {} transfers between tracked accounts.",
+            transfers
+        )?;
+    }
+
+    let login_required: Vec<_> = report.login_required().collect();
+    if !login_required.is_empty() {
+        writeln!(wr, "\nThese links need to be re-authenticated:")?;
+        for result in login_required {
+            writeln!(
+                wr,
+                "  {}: clerk link --update {}",
+                result.name(),
+                result.item_id
+            )?;
+        }
+    }
+
+    match report.failed().count() {
+        0 => Ok(()),
+        n => Err(anyhow!("{} links failed to sync", n)),
+    }
+}
+
+async fn doctor(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let orphans = store.txns().orphaned_accounts().await?;
+
+    if orphans.is_empty() {
+        println!("No orphaned transactions found.");
+
+        return Ok(());
+    }
+
+    for account_id in &orphans {
+        if matches.is_present("delete") {
+            let count = store.txns().delete_by_account(account_id).await?;
+            println!("Deleted {} transactions for unknown account {}.", count, account_id);
+        } else if let Some(target) = matches.value_of("reassign") {
+            if store.accounts().by_id(target).await?.is_none() {
+                return Err(anyhow!("account {} does not exist", target));
+            }
+
+            let count = store.txns().reassign_account(account_id, target).await?;
+            println!(
+                "Moved {} transactions from unknown account {} to {}.",
+                count, account_id, target
+            );
+        } else {
+            println!("Transactions reference unknown account {}.", account_id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn export(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let exported_at = Utc::now();
+
+    let pending_output = matches.value_of("pending_output");
+    let mut filter = TransactionFilter {
+        include_removed: matches.is_present("include_removed"),
+        // Pending transactions go to their own file when one is given.
+        status: pending_output.map(|_| Status::Resolved),
+        ..TransactionFilter::default()
+    };
+    if matches.is_present("since_last_export") {
+        // Transaction ids are ULIDs generated when the transaction is first
+        // saved, anything added after the last export sorts after the marker.
+        if let Some(marker) = store.meta().get(LAST_EXPORTED_AT).await? {
+            let marker = DateTime::parse_from_rfc3339(&marker)?;
+            filter.created_after = Some(Ulid::from_parts(marker.timestamp_millis() as u64, 0));
+        }
+    }
+
+    let mut txns = store.txns().list(&filter).await?;
+    // The pending file is rewritten on every export so transactions that have
+    // since resolved drop out of it.
+    let mut pending = match pending_output {
+        Some(_) => {
+            let filter = TransactionFilter {
+                status: Some(Status::Pending),
+                ..TransactionFilter::default()
+            };
+
+            store.txns().list(&filter).await?
+        }
+        None => vec![],
+    };
+    let accounts = store.accounts().list().await?;
+
+    if matches.is_present("synthesize_postings") {
+        let default_currency = find_currency(&settings.default_currency)?;
+        let mut synthesized = 0;
+        for txn in txns
+            .iter_mut()
+            .chain(pending.iter_mut())
+            .filter(|txn| txn.postings.is_empty())
+        {
+            if let Some(source) = store.txns().source(&txn.id.to_string()).await? {
+                let source: model::Transaction = serde_json::from_str(&source)?;
+                txn.postings.push(synthesize_posting(&source, default_currency));
+                synthesized += 1;
+            }
+        }
+
+        if synthesized > 0 {
+            warn!(
+                "Synthesized postings for {} transactions without stored postings.",
+                synthesized
             );
-            store.links().update(&updated_link).await?;
         }
     }
 
+    // Output from --since-last-export is meant to be appended to an existing
+    // journal which already starts with the header and preamble.
+    let appending = matches.is_present("since_last_export");
+    let opts = ledger::Options {
+        group_by: matches.value_of("group_by").map(str::parse).transpose()?,
+        generated_at: (settings.ledger.header && !appending).then_some(exported_at),
+        preamble: settings.ledger.preamble.clone().filter(|_| !appending),
+    };
+
+    let stdout = std::io::stdout().lock();
+    ledger::write_transactions(stdout, &txns, &accounts, &opts)?;
+
+    if let Some(path) = pending_output {
+        let opts = ledger::Options {
+            generated_at: settings.ledger.header.then_some(exported_at),
+            preamble: settings.ledger.preamble.clone(),
+            ..opts
+        };
+        ledger::write_transactions(File::create(path)?, &pending, &accounts, &opts)?;
+    }
+
+    store
+        .meta()
+        .set(LAST_EXPORTED_AT, &exported_at.to_rfc3339())
+        .await?;
+
+    Ok(())
+}
+
+// Plaid reports money leaving an account as a positive amount, the posting
+// against the account itself carries the opposite sign.
+fn synthesize_posting(source: &model::Transaction, default_currency: &'static Currency) -> Posting {
+    let commodity = resolve_currency(
+        source.iso_currency_code.as_deref(),
+        source.unofficial_currency_code.as_deref(),
+        default_currency,
+    );
+
+    Posting {
+        account: source.account_id.clone(),
+        amount: -source.amount,
+        currency: commodity.code().to_string(),
+    }
+}
+
+/// Writes every stored transaction as a Ledger journal. Unlike `txn export`
+/// this always writes the full journal and leaves the export marker alone.
+pub(crate) async fn journal(matches: &ArgMatches, settings: Settings) -> Result<()> {
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let txns = store.txns().list(&TransactionFilter::default()).await?;
+    let accounts = store.accounts().list().await?;
+
+    let opts = ledger::Options {
+        group_by: matches.value_of("group_by").map(str::parse).transpose()?,
+        generated_at: settings.ledger.header.then(Utc::now),
+        preamble: settings.ledger.preamble.clone(),
+    };
+
+    match matches.value_of("output") {
+        Some(path) => ledger::write_transactions(File::create(path)?, &txns, &accounts, &opts),
+        None => ledger::write_transactions(std::io::stdout().lock(), &txns, &accounts, &opts),
+    }
+}
+
+async fn stats(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let filter = TransactionFilter {
+        include_removed: matches.is_present("include_removed"),
+        ..TransactionFilter::default()
+    };
+
+    let mut tw = TabWriter::new(std::io::stdout().lock());
+    writeln!(tw, "Transactions:\t{}", store.txns().count(&filter).await?)?;
+
+    if matches.is_present("uncategorized") {
+        let uncategorized = [
+            settings.ledger.uncategorized_expenses.as_str(),
+            settings.ledger.uncategorized_income.as_str(),
+        ];
+        writeln!(
+            tw,
+            "Uncategorized:\t{}",
+            store.txns().count_posted_to(&filter, &uncategorized).await?
+        )?;
+    }
+
+    if matches.is_present("by_region") {
+        writeln!(tw, "\nCountry\tRegion\tTransactions")?;
+        for count in store.txns().count_by_region(&filter).await? {
+            writeln!(
+                tw,
+                "{}\t{}\t{}",
+                count.country.as_deref().unwrap_or("-"),
+                count.region.as_deref().unwrap_or("-"),
+                count.transactions
+            )?;
+        }
+    }
+
+    tw.flush()?;
+
+    Ok(())
+}
+
+async fn list(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    let filter = TransactionFilter {
+        account_id: matches.value_of("account").map(str::to_string),
+        since: parse_date(matches.value_of("since"))?,
+        until: parse_date(matches.value_of("until"))?,
+        category: matches.value_of("category").map(str::to_string),
+        ..TransactionFilter::default()
+    };
+    if let (Some(since), Some(until)) = (filter.since, filter.until) {
+        if since > until {
+            return Err(anyhow!("--since {} is after --until {}", since, until));
+        }
+    }
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let txns = store.txns().list(&filter).await?;
+
+    print_transactions(&mut store, &txns).await
+}
+
+async fn search(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    // SAFETY: query is a required positional argument.
+    let query = matches.value_of("query").unwrap();
+    let limit = matches.value_of("limit").map(str::parse).transpose()?;
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let txns = store.txns().search(query, limit).await?;
+
+    print_transactions(&mut store, &txns).await
+}
+
+async fn print_transactions(store: &mut SqliteStore, txns: &[Transaction]) -> Result<()> {
+    let accounts: HashMap<String, Account> = store
+        .accounts()
+        .list()
+        .await?
+        .into_iter()
+        .map(|account| (account.id.clone(), account))
+        .collect();
+
+    let mut tw = TabWriter::new(std::io::stdout().lock());
+    writeln!(tw, "Date\tPayee\tNarration\tAmount\tAccount")?;
+    for txn in txns {
+        // Show the side of the transaction posted to a tracked account, the
+        // counter posting only names a category.
+        let posting = txn
+            .postings
+            .iter()
+            .find(|p| accounts.contains_key(&p.account))
+            .or_else(|| txn.postings.first());
+        let (amount, account) = match posting {
+            Some(p) => (
+                format!("{} {}", p.amount, p.currency),
+                accounts
+                    .get(&p.account)
+                    .map_or(p.account.as_str(), |a| a.name.as_str()),
+            ),
+            None => ("-".to_string(), "-"),
+        };
+
+        writeln!(
+            tw,
+            "{}\t{}\t{}\t{}\t{}",
+            txn.date,
+            txn.payee.as_deref().unwrap_or("-"),
+            &txn.narration,
+            amount,
+            account
+        )?;
+    }
+    tw.flush()?;
+
+    Ok(())
+}
+
+pub(crate) fn parse_date(value: Option<&str>) -> Result<Option<NaiveDate>> {
+    value
+        .map(|v| {
+            NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                .map_err(|e| anyhow!("invalid date {}, expected YYYY-MM-DD: {}", v, e))
+        })
+        .transpose()
+}
+
+/// A hand-entered transaction, see `txn add`.
+#[derive(Debug)]
+struct ManualEntry {
+    account_id: String,
+    date: NaiveDate,
+    /// Signed from the account's point of view, negative when money left it.
+    amount: Decimal,
+    narration: String,
+    payee: Option<String>,
+    /// Defaults to the uncategorized expense or income account.
+    counter_account: Option<String>,
+    /// Defaults to `default_currency`.
+    currency: Option<String>,
+}
+
+/// Saves a manual entry as a balanced transaction against a stored account.
+/// Manual transactions belong to the local placeholder link and keep
+/// `{"manual": true}` as their source.
+async fn save_manual(
+    settings: &Settings,
+    store: &mut SqliteStore,
+    entry: ManualEntry,
+) -> Result<Transaction> {
+    if store.accounts().by_id(&entry.account_id).await?.is_none() {
+        return Err(anyhow!("no account found with id {}", entry.account_id));
+    }
+
+    let currency = resolve_currency(
+        entry.currency.as_deref(),
+        None,
+        find_currency(&settings.default_currency)?,
+    );
+    let counter_account = entry.counter_account.unwrap_or_else(|| {
+        if entry.amount.is_sign_negative() {
+            settings.ledger.uncategorized_expenses.clone()
+        } else {
+            settings.ledger.uncategorized_income.clone()
+        }
+    });
+    let canonical = Transaction {
+        id: Ulid::new(),
+        status: Status::Resolved,
+        date: entry.date,
+        payee: entry.payee,
+        narration: entry.narration,
+        postings: vec![
+            Posting {
+                account: entry.account_id.clone(),
+                amount: entry.amount,
+                currency: currency.code().to_string(),
+            },
+            Posting {
+                account: counter_account,
+                amount: -entry.amount,
+                currency: currency.code().to_string(),
+            },
+        ],
+        tags: vec![],
+        meta: BTreeMap::new(),
+    };
+
+    let upstream_id = canonical.id.to_string();
+    let stored = TransactionEntry {
+        canonical,
+        source: serde_json::json!({ "manual": true }),
+    };
+    store
+        .txns()
+        .save(LOCAL_ITEM_ID, &upstream_id, &entry.account_id, &stored)
+        .await?;
+
+    Ok(stored.canonical)
+}
+
+async fn add(settings: Settings, matches: &ArgMatches) -> Result<()> {
+    // SAFETY: The account, amount, and narration are required arguments.
+    let amount = matches.value_of("amount").unwrap();
+    let entry = ManualEntry {
+        account_id: matches.value_of("account").unwrap().to_string(),
+        date: parse_date(matches.value_of("date"))?.unwrap_or_else(|| Utc::now().date_naive()),
+        amount: Decimal::from_str(amount)
+            .map_err(|e| anyhow!("invalid amount {}: {}", amount, e))?,
+        narration: matches.value_of("narration").unwrap().to_string(),
+        payee: matches.value_of("payee").map(str::to_string),
+        counter_account: matches.value_of("counter_account").map(str::to_string),
+        currency: matches.value_of("currency").map(str::to_string),
+    };
+
+    let mut store = SqliteStore::new(&settings.db_file).await?;
+    let txn = save_manual(&settings, &mut store, entry).await?;
+    println!("Added transaction {}.", txn.id);
+
     Ok(())
 }
 
 pub(crate) async fn run(matches: &ArgMatches, settings: Settings) -> Result<()> {
     match matches.subcommand() {
-        Some(("sync", _link_matches)) => pull(settings).await,
+        Some(("sync", sync_matches)) => {
+            let opts = PullOptions {
+                accounts_first: !sync_matches.is_present("no_accounts_first"),
+                json: sync_matches.value_of("output") == Some("json"),
+                max_transactions: sync_matches
+                    .value_of("max_transactions")
+                    .map(str::parse)
+                    .transpose()?,
+                reset_cursors: sync_matches.is_present("reset_all"),
+                date_range: date_range(
+                    parse_date(sync_matches.value_of("since"))?,
+                    parse_date(sync_matches.value_of("until"))?,
+                    Utc::now().date_naive(),
+                )?,
+                detect_transfers: sync_matches.is_present("detect_transfers"),
+            };
+            let report = pull(&settings, &opts).await?;
+
+            // Keep stdout clean for the event stream when emitting JSON.
+            if opts.json {
+                print_report(std::io::stderr().lock(), &report)
+            } else {
+                print_report(std::io::stdout().lock(), &report)
+            }
+        }
+        Some(("doctor", doctor_matches)) => doctor(settings, doctor_matches).await,
+        Some(("export", export_matches)) => export(settings, export_matches).await,
+        Some(("stats", stats_matches)) => stats(settings, stats_matches).await,
+        Some(("list", list_matches)) => list(settings, list_matches).await,
+        Some(("search", search_matches)) => search(settings, search_matches).await,
+        Some(("add", add_matches)) => add(settings, add_matches).await,
         None => unreachable!("command is requires"),
         _ => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rusty_money::iso;
+
+    use super::*;
+    use crate::plaid::mock::{mock_account, transactions_page, MockPlaid};
+    use crate::plaid::sync::PlaidTransaction;
+    use crate::settings::test_settings;
+    use crate::store::test_support::{TestStore, TransactionBuilder};
+
+    #[tokio::test]
+    async fn sync_link_saves_transactions_and_cursor() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let txn = TransactionBuilder::new()
+            .account_id("mock-account")
+            .transaction_id("mock-txn")
+            .build();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_accounts(vec![mock_account("mock-account")])
+                .with_transactions(vec![txn.source], "cursor-1"),
+        );
+
+        let mut result = LinkSyncResult::new(&link);
+        let opts = PullOptions {
+            accounts_first: true,
+            ..Default::default()
+        };
+        sync_link(
+            &settings,
+            &client,
+            store.db(),
+            link.clone(),
+            &opts,
+            &mut result,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.added, 1);
+        assert_eq!(result.next_cursor.as_deref(), Some("cursor-1"));
+        assert!(store
+            .db()
+            .accounts()
+            .by_id("mock-account")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(store
+            .db()
+            .txns()
+            .by_upstream_id("mock-txn")
+            .await
+            .unwrap()
+            .is_some());
+
+        let link = store.db().links().by_id(&link.item_id).await.unwrap().unwrap();
+        assert_eq!(link.sync_cursor.as_deref(), Some("cursor-1"));
+    }
+
+    #[tokio::test]
+    async fn sync_link_stores_personal_finance_category() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let txn = TransactionBuilder::new()
+            .account_id("mock-account")
+            .category("FOOD_AND_DRINK", "FOOD_AND_DRINK_COFFEE")
+            .build();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_accounts(vec![mock_account("mock-account")])
+                .with_transactions(vec![txn.source], "cursor-1"),
+        );
+
+        let mut result = LinkSyncResult::new(&link);
+        let opts = PullOptions {
+            accounts_first: true,
+            ..Default::default()
+        };
+        sync_link(&settings, &client, store.db(), link, &opts, &mut result)
+            .await
+            .unwrap();
+
+        let filter = TransactionFilter {
+            category: Some("FOOD_AND_DRINK_COFFEE".into()),
+            ..TransactionFilter::default()
+        };
+        let txns = store.db().txns().list(&filter).await.unwrap();
+        assert_eq!(txns.len(), 1);
+
+        let categories = store
+            .db()
+            .txns()
+            .primary_categories(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(categories[&txns[0].id], "FOOD_AND_DRINK");
+    }
+
+    #[test]
+    fn synthesized_posting_flips_plaid_sign() {
+        let mut source = TransactionBuilder::new()
+            .account_id("mock-account")
+            .build()
+            .source;
+        source.unofficial_currency_code = Some("BTC".into());
+
+        assert_eq!(
+            synthesize_posting(&source, iso::USD),
+            Posting {
+                account: "mock-account".into(),
+                amount: (-33).into(),
+                currency: "BTC".into(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_link_refreshes_refetched_transactions() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let txn = TransactionBuilder::new()
+            .account_id("mock-account")
+            .transaction_id("mock-txn")
+            .build();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_accounts(vec![mock_account("mock-account")])
+                .with_transactions(vec![txn.source], "cursor-1"),
+        );
+        let opts = PullOptions {
+            accounts_first: true,
+            ..Default::default()
+        };
+
+        let mut result = LinkSyncResult::new(&link);
+        sync_link(
+            &settings,
+            &client,
+            store.db(),
+            link.clone(),
+            &opts,
+            &mut result,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.added, 1);
+
+        // The link still has no cursor, the same page is served again.
+        let mut result = LinkSyncResult::new(&link);
+        sync_link(
+            &settings,
+            &client,
+            store.db(),
+            link.clone(),
+            &opts,
+            &mut result,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.added, 0);
+        assert_eq!(result.refetched, 1);
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sync_link_stores_balanced_postings() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+
+        let mut card = mock_account("mock-card");
+        card.r#type = model::AccountType::Credit;
+        let txn = TransactionBuilder::new()
+            .account_id("mock-card")
+            .transaction_id("mock-txn")
+            .build();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_accounts(vec![card])
+                .with_transactions(vec![txn.source], "cursor-1"),
+        );
+        let opts = PullOptions {
+            accounts_first: true,
+            ..Default::default()
+        };
+
+        let mut result = LinkSyncResult::new(&link);
+        sync_link(&settings, &client, store.db(), link, &opts, &mut result)
+            .await
+            .unwrap();
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        let mut postings = txns[0].postings.clone();
+        postings.sort_by(|a, b| a.amount.cmp(&b.amount));
+        assert_eq!(
+            postings,
+            vec![
+                Posting {
+                    account: "mock-card".into(),
+                    amount: (-33).into(),
+                    currency: "USD".into(),
+                },
+                Posting {
+                    account: settings.ledger.uncategorized_expenses.clone(),
+                    amount: 33.into(),
+                    currency: "USD".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn date_range_validation() {
+        let day = |d| NaiveDate::from_ymd_opt(2022, 12, d).unwrap();
+
+        assert_eq!(date_range(None, None, day(31)).unwrap(), None);
+        assert_eq!(
+            date_range(Some(day(1)), None, day(31)).unwrap(),
+            Some((day(1), day(31)))
+        );
+        assert_eq!(
+            date_range(Some(day(1)), Some(day(1)), day(31)).unwrap(),
+            Some((day(1), day(1)))
+        );
+        assert!(date_range(Some(day(2)), Some(day(1)), day(31)).is_err());
+        assert!(date_range(None, Some(day(1)), day(31)).is_err());
+    }
+
+    #[tokio::test]
+    async fn sync_links_degrades_links_needing_login() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        store.new_link().await;
+        store.new_link().await;
+
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .fail(
+                    "/transactions/sync",
+                    400,
+                    "ITEM_ERROR",
+                    "ITEM_LOGIN_REQUIRED",
+                )
+                .respond(
+                    "/transactions/sync",
+                    transactions_page(Vec::<PlaidTransaction>::new(), "cursor-1", false),
+                ),
+        );
+        let opts = PullOptions::default();
+
+        let report = sync_links(&settings, &client, store.db(), &opts)
+            .await
+            .unwrap();
+        assert_eq!(report.failed().count(), 0);
+        let degraded: Vec<_> = report.login_required().collect();
+        assert_eq!(degraded.len(), 1);
+        assert_eq!(
+            report
+                .per_link
+                .iter()
+                .filter(|r| matches!(r.status, LinkSyncStatus::Synced))
+                .count(),
+            1
+        );
+
+        let link = store
+            .db()
+            .links()
+            .by_id(&degraded[0].item_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(link.state, LinkStatus::Degraded(_)));
+    }
+
+    #[tokio::test]
+    async fn join_transfers_is_idempotent() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let checking = store.new_account(&link.item_id).await;
+        let savings = store.new_account(&link.item_id).await;
+
+        let posting = |account: &str, amount: i64| Posting {
+            account: account.into(),
+            amount: amount.into(),
+            currency: "USD".into(),
+        };
+        let outflow = TransactionBuilder::new()
+            .transaction_id("outflow")
+            .postings(vec![
+                posting(&checking.id, -100),
+                posting("Expenses:Uncategorized", 100),
+            ])
+            .build();
+        let inflow = TransactionBuilder::new()
+            .transaction_id("inflow")
+            .postings(vec![
+                posting(&savings.id, 100),
+                posting("Income:Uncategorized", -100),
+            ])
+            .build();
+        for (entry, account) in [(&outflow, &checking), (&inflow, &savings)] {
+            store
+                .db()
+                .txns()
+                .save(
+                    &link.item_id,
+                    &entry.source.transaction_id,
+                    &account.id,
+                    entry,
+                )
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(join_transfers(store.db()).await.unwrap(), 1);
+        assert_eq!(join_transfers(store.db()).await.unwrap(), 0);
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].id, outflow.canonical.id);
+        assert_eq!(
+            txns[0].postings,
+            vec![posting(&checking.id, -100), posting(&savings.id, 100)]
+        );
+    }
+
+    #[tokio::test]
+    async fn joined_transfers_survive_updates() {
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let checking = store.new_account(&link.item_id).await;
+        let savings = store.new_account(&link.item_id).await;
+
+        let posting = |account: &str, amount: i64| Posting {
+            account: account.into(),
+            amount: amount.into(),
+            currency: "USD".into(),
+        };
+        let outflow = TransactionBuilder::new()
+            .transaction_id("outflow")
+            .postings(vec![
+                posting(&checking.id, -100),
+                posting("Expenses:Uncategorized", 100),
+            ])
+            .build();
+        let inflow = TransactionBuilder::new()
+            .transaction_id("inflow")
+            .postings(vec![
+                posting(&savings.id, 100),
+                posting("Income:Uncategorized", -100),
+            ])
+            .build();
+        for (entry, account) in [(&outflow, &checking), (&inflow, &savings)] {
+            store
+                .db()
+                .txns()
+                .save(
+                    &link.item_id,
+                    &entry.source.transaction_id,
+                    &account.id,
+                    entry,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(join_transfers(store.db()).await.unwrap(), 1);
+
+        // Modified events carry the original upstream postings.
+        for entry in [&outflow, &inflow] {
+            store
+                .db()
+                .txns()
+                .update(&entry.canonical.id.to_string(), entry)
+                .await
+                .unwrap();
+        }
+
+        let txns = store
+            .db()
+            .txns()
+            .list(&TransactionFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(
+            txns[0].postings,
+            vec![posting(&checking.id, -100), posting(&savings.id, 100)]
+        );
+        assert_eq!(
+            txns[0].postings.iter().map(|p| p.amount).sum::<Decimal>(),
+            Decimal::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn save_manual_balances_postings() {
+        let settings = test_settings();
+        let mut store = TestStore::new().await;
+        let link = store.new_link().await;
+        let account = store.new_account(&link.item_id).await;
+        let entry = |account_id: &str| ManualEntry {
+            account_id: account_id.to_string(),
+            date: NaiveDate::from_ymd_opt(2023, 1, 7).unwrap(),
+            amount: (-20).into(),
+            narration: "Farmers market".into(),
+            payee: None,
+            counter_account: None,
+            currency: None,
+        };
+
+        let txn = save_manual(&settings, store.db(), entry(&account.id))
+            .await
+            .unwrap();
+        assert_eq!(
+            txn.postings,
+            vec![
+                Posting {
+                    account: account.id.clone(),
+                    amount: (-20).into(),
+                    currency: "USD".into(),
+                },
+                Posting {
+                    account: settings.ledger.uncategorized_expenses.clone(),
+                    amount: 20.into(),
+                    currency: "USD".into(),
+                },
+            ]
+        );
+        assert_eq!(
+            store
+                .db()
+                .txns()
+                .source(&txn.id.to_string())
+                .await
+                .unwrap()
+                .as_deref(),
+            Some(r#"{"manual":true}"#)
+        );
+
+        assert!(save_manual(&settings, store.db(), entry("missing"))
+            .await
+            .is_err());
+    }
+}
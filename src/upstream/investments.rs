@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use axum::async_trait;
+use rplaid::client::Plaid;
+use rplaid::HttpClient;
+
+use crate::core::Holding;
+use crate::currency::{find_currency, resolve_currency};
+use crate::upstream::plaid::PlaidError;
+use crate::upstream::HoldingSource;
+
+/// Reads the positions held by a link's investment accounts from Plaid's
+/// `/investments/holdings/get`.
+pub struct Investments<'a, T: HttpClient> {
+    client: &'a Plaid<T>,
+    token: String,
+    default_currency: String,
+}
+
+impl<'a, T: HttpClient> Investments<'a, T> {
+    pub fn new(client: &'a Plaid<T>, token: String, default_currency: &str) -> Self {
+        Self {
+            client,
+            token,
+            default_currency: default_currency.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl<'a, T: HttpClient> HoldingSource for Investments<'a, T> {
+    async fn holdings(&self) -> Result<Vec<Holding>> {
+        let res = self
+            .client
+            .investments_holdings(&self.token)
+            .await
+            .map_err(PlaidError::from)?;
+        let default = find_currency(&self.default_currency)?;
+
+        let securities: HashMap<_, _> = res
+            .securities
+            .iter()
+            .map(|security| (security.security_id.as_str(), security))
+            .collect();
+
+        Ok(res
+            .holdings
+            .into_iter()
+            .map(|holding| {
+                let security = securities.get(holding.security_id.as_str());
+                let currency = resolve_currency(
+                    holding.iso_currency_code.as_deref(),
+                    holding.unofficial_currency_code.as_deref(),
+                    default,
+                );
+
+                Holding {
+                    name: security.and_then(|s| s.name.clone()),
+                    ticker: security.and_then(|s| s.ticker_symbol.clone()),
+                    account_id: holding.account_id,
+                    security_id: holding.security_id,
+                    quantity: holding.quantity,
+                    price: holding.institution_price,
+                    value: holding.institution_value,
+                    currency: currency.code().to_string(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use serde_json::json;
+
+    use super::*;
+    use crate::plaid::mock::{mock_account, MockPlaid};
+    use crate::plaid::plaid_client_with;
+    use crate::settings::test_settings;
+
+    #[tokio::test]
+    async fn holdings_join_securities() {
+        let settings = test_settings();
+        let holding = |security_id: &str, quantity: &str, price: &str, value: &str| {
+            json!({
+                "account_id": "brokerage",
+                "security_id": security_id,
+                "quantity": quantity.parse::<f64>().unwrap(),
+                "institution_price": price.parse::<f64>().unwrap(),
+                "institution_price_as_of": null,
+                "institution_value": value.parse::<f64>().unwrap(),
+                "cost_basis": null,
+                "iso_currency_code": "USD",
+                "unofficial_currency_code": null,
+            })
+        };
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().replace(
+                "/investments/holdings/get",
+                json!({
+                    "accounts": [mock_account("brokerage")],
+                    "holdings": [
+                        holding("sec-vti", "10", "200.5", "2005"),
+                        holding("sec-unknown", "1", "1", "1"),
+                    ],
+                    "securities": [{
+                        "security_id": "sec-vti",
+                        "name": "Vanguard Total Stock Market ETF",
+                        "ticker_symbol": "VTI",
+                        "type": "etf",
+                        "close_price": 200.5,
+                        "iso_currency_code": "USD",
+                        "unofficial_currency_code": null,
+                    }],
+                    "request_id": "mock",
+                }),
+            ),
+        );
+
+        let holdings = Investments::new(&plaid, "token".into(), "USD")
+            .holdings()
+            .await
+            .unwrap();
+
+        assert_eq!(holdings.len(), 2);
+        assert_eq!(
+            holdings[0],
+            Holding {
+                account_id: "brokerage".into(),
+                security_id: "sec-vti".into(),
+                name: Some("Vanguard Total Stock Market ETF".into()),
+                ticker: Some("VTI".into()),
+                quantity: Decimal::new(10, 0),
+                price: Decimal::new(2005, 1),
+                value: Decimal::new(2005, 0),
+                currency: "USD".into(),
+            }
+        );
+        assert_eq!(holdings[1].name, None);
+    }
+}
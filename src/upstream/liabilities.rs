@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use axum::async_trait;
+use chrono::NaiveDate;
+use rplaid::client::Plaid;
+use rplaid::HttpClient;
+use rust_decimal::Decimal;
+
+use crate::core::Liability;
+use crate::currency::{find_currency, resolve_currency};
+use crate::upstream::plaid::PlaidError;
+use crate::upstream::LiabilitySource;
+
+/// The APR Plaid reports for purchases, preferred over balance transfer or
+/// cash advance rates when a card reports several.
+const PURCHASE_APR: &str = "purchase_apr";
+
+/// Reads credit card, student loan, and mortgage details for a link's accounts
+/// from Plaid's `/liabilities/get`.
+pub struct Liabilities<'a, T: HttpClient> {
+    client: &'a Plaid<T>,
+    token: String,
+    default_currency: String,
+}
+
+impl<'a, T: HttpClient> Liabilities<'a, T> {
+    pub fn new(client: &'a Plaid<T>, token: String, default_currency: &str) -> Self {
+        Self {
+            client,
+            token,
+            default_currency: default_currency.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl<'a, T: HttpClient> LiabilitySource for Liabilities<'a, T> {
+    async fn liabilities(&self) -> Result<Vec<Liability>> {
+        let res = self
+            .client
+            .liabilities(&self.token)
+            .await
+            .map_err(PlaidError::from)?;
+        let default = find_currency(&self.default_currency)?;
+
+        // Liabilities don't report a currency, amounts are in the currency of
+        // the account's balances.
+        let currencies: HashMap<_, _> = res
+            .accounts
+            .iter()
+            .map(|account| {
+                let commodity = resolve_currency(
+                    account.balances.iso_currency_code.as_deref(),
+                    account.balances.unofficial_currency_code.as_deref(),
+                    default,
+                );
+                (account.account_id.as_str(), commodity.code().to_string())
+            })
+            .collect();
+        let liability = |account_id: String,
+                         apr: Option<Decimal>,
+                         minimum_payment: Option<Decimal>,
+                         next_due_date: Option<&str>,
+                         last_statement_balance: Option<Decimal>| {
+            Liability {
+                currency: currencies
+                    .get(account_id.as_str())
+                    .cloned()
+                    .unwrap_or_else(|| default.iso_alpha_code.to_string()),
+                account_id,
+                apr,
+                minimum_payment,
+                next_due_date: next_due_date.and_then(due_date),
+                last_statement_balance,
+            }
+        };
+
+        let mut liabilities = vec![];
+        for card in res.liabilities.credit.unwrap_or_default() {
+            let account_id = match card.account_id {
+                Some(id) => id,
+                None => continue,
+            };
+            let apr = card
+                .aprs
+                .iter()
+                .find(|apr| apr.apr_type == PURCHASE_APR)
+                .or_else(|| card.aprs.first())
+                .map(|apr| apr.apr_percentage);
+
+            liabilities.push(liability(
+                account_id,
+                apr,
+                card.minimum_payment_amount,
+                card.next_payment_due_date.as_deref(),
+                card.last_statement_balance,
+            ));
+        }
+        for loan in res.liabilities.student.unwrap_or_default() {
+            let account_id = match loan.account_id {
+                Some(id) => id,
+                None => continue,
+            };
+
+            liabilities.push(liability(
+                account_id,
+                Some(loan.interest_rate_percentage),
+                loan.minimum_payment_amount,
+                loan.next_payment_due_date.as_deref(),
+                loan.last_statement_balance,
+            ));
+        }
+        for mortgage in res.liabilities.mortgage.unwrap_or_default() {
+            // Mortgages have no statement balance, the next monthly payment is
+            // the minimum due.
+            liabilities.push(liability(
+                mortgage.account_id,
+                mortgage.interest_rate.percentage,
+                mortgage.next_monthly_payment,
+                mortgage.next_payment_due_date.as_deref(),
+                None,
+            ));
+        }
+
+        Ok(liabilities)
+    }
+}
+
+fn due_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::plaid::mock::{mock_account, MockPlaid};
+    use crate::plaid::plaid_client_with;
+    use crate::settings::test_settings;
+
+    #[tokio::test]
+    async fn liabilities_prefer_purchase_apr() {
+        let settings = test_settings();
+        let mut card = mock_account("card");
+        card.balances.iso_currency_code = Some("EUR".into());
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().replace(
+                "/liabilities/get",
+                json!({
+                    "accounts": [card, mock_account("loan")],
+                    "liabilities": {
+                        "credit": [{
+                            "account_id": "card",
+                            "aprs": [
+                                {
+                                    "apr_percentage": 24.99,
+                                    "apr_type": "cash_apr",
+                                    "balance_subject_to_apr": null,
+                                    "interest_charge_amount": null,
+                                },
+                                {
+                                    "apr_percentage": 15.24,
+                                    "apr_type": "purchase_apr",
+                                    "balance_subject_to_apr": null,
+                                    "interest_charge_amount": null,
+                                },
+                            ],
+                            "is_overdue": false,
+                            "last_payment_amount": null,
+                            "last_payment_date": null,
+                            "last_statement_issue_date": "2022-12-10",
+                            "last_statement_balance": 1708.77,
+                            "minimum_payment_amount": 20,
+                            "next_payment_due_date": "2023-01-05",
+                        }],
+                        "student": [{
+                            "account_id": "loan",
+                            "interest_rate_percentage": 5.25,
+                            "minimum_payment_amount": null,
+                            "next_payment_due_date": null,
+                            "last_statement_balance": null,
+                        }],
+                        "mortgage": null,
+                    },
+                    "request_id": "mock",
+                }),
+            ),
+        );
+
+        let liabilities = Liabilities::new(&plaid, "token".into(), "USD")
+            .liabilities()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            liabilities,
+            vec![
+                Liability {
+                    account_id: "card".into(),
+                    apr: Some(Decimal::new(1524, 2)),
+                    minimum_payment: Some(Decimal::new(20, 0)),
+                    next_due_date: NaiveDate::from_ymd_opt(2023, 1, 5),
+                    last_statement_balance: Some(Decimal::new(170877, 2)),
+                    currency: "EUR".into(),
+                },
+                Liability {
+                    account_id: "loan".into(),
+                    apr: Some(Decimal::new(525, 2)),
+                    minimum_payment: None,
+                    next_due_date: None,
+                    last_statement_balance: None,
+                    currency: "USD".into(),
+                },
+            ]
+        );
+    }
+}
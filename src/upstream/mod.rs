@@ -1,10 +1,13 @@
+pub mod investments;
+pub mod liabilities;
+pub mod ofx;
 pub mod plaid;
 
 use anyhow::Result;
 use axum::async_trait;
 use serde::Serialize;
 
-use crate::core::Transaction;
+use crate::core::{Holding, Liability, Transaction};
 use rplaid::model::Account;
 
 #[async_trait]
@@ -12,12 +15,25 @@ pub trait AccountSource {
     async fn accounts(&self) -> Result<Vec<Account>>;
 }
 
+#[async_trait]
+pub trait HoldingSource {
+    async fn holdings(&self) -> Result<Vec<Holding>>;
+}
+
+#[async_trait]
+pub trait LiabilitySource {
+    async fn liabilities(&self) -> Result<Vec<Liability>>;
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "lowercase")]
 pub enum TransactionEvent<T> {
     Added(TransactionEntry<T>),
     Modified(TransactionEntry<T>),
     Removed(String),
 }
 
+#[derive(Serialize)]
 pub struct TransactionEntry<T> {
     pub canonical: Transaction,
     pub source: T,
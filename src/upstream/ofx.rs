@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use axum::async_trait;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::core::{Posting, Status, Transaction};
+use crate::currency::{find_currency, resolve_currency};
+use crate::upstream::plaid::{clean_narration, SyncOptions};
+use crate::upstream::{TransactionEntry, TransactionEvent, TransactionSource};
+
+/// A transaction read from an OFX statement's `<STMTTRN>` record, kept as the
+/// stored transaction's source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OfxTransaction {
+    /// The institution's id for the transaction, unique within the account.
+    pub fitid: String,
+    pub trntype: String,
+    pub date: NaiveDate,
+    /// Negative when money left the account.
+    pub amount: Decimal,
+    pub name: String,
+    pub memo: Option<String>,
+}
+
+/// The parts of an OFX bank or credit card statement clerk uses.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Statement {
+    /// The institution's account number, `<ACCTID>`.
+    pub account_id: Option<String>,
+    /// The statement's default currency, `<CURDEF>`.
+    pub currency: Option<String>,
+    /// Whether this is a credit card statement, `<CCSTMTRS>`.
+    pub credit_card: bool,
+    pub transactions: Vec<OfxTransaction>,
+}
+
+/// Parses an OFX or QFX file. Both the SGML flavor of OFX 1.x, where leaf
+/// elements aren't closed, and the XML flavor of OFX 2.x are accepted.
+pub fn parse(input: &str) -> Result<Statement> {
+    let mut statement = Statement::default();
+    let mut record: Option<HashMap<String, String>> = None;
+
+    // Everything before the first tag is the OFX 1.x header.
+    for element in input.split('<').skip(1) {
+        let (tag, text) = element
+            .split_once('>')
+            .ok_or_else(|| anyhow!("malformed OFX element <{}", element.trim()))?;
+        let tag = tag.trim().to_ascii_uppercase();
+        let text = unescape(text.trim());
+
+        match tag.as_str() {
+            "STMTTRN" => record = Some(HashMap::new()),
+            "/STMTTRN" => {
+                let fields = record
+                    .take()
+                    .ok_or_else(|| anyhow!("</STMTTRN> without a matching <STMTTRN>"))?;
+                statement.transactions.push(transaction(fields)?);
+            }
+            "CCSTMTRS" => statement.credit_card = true,
+            "ACCTID" if statement.account_id.is_none() => statement.account_id = Some(text),
+            "CURDEF" => statement.currency = Some(text),
+            _ if tag.starts_with('/') || text.is_empty() => {}
+            _ => {
+                if let Some(fields) = record.as_mut() {
+                    fields.insert(tag, text);
+                }
+            }
+        }
+    }
+
+    Ok(statement)
+}
+
+fn transaction(mut fields: HashMap<String, String>) -> Result<OfxTransaction> {
+    let mut field = |name: &str| {
+        fields
+            .remove(name)
+            .ok_or_else(|| anyhow!("<STMTTRN> is missing <{}>", name))
+    };
+
+    let fitid = field("FITID")?;
+    let posted = field("DTPOSTED")?;
+    // Dates are YYYYMMDD optionally followed by a time and timezone.
+    let date = posted
+        .get(..8)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok())
+        .ok_or_else(|| anyhow!("invalid <DTPOSTED> {} for {}", posted, fitid))?;
+    let amount = field("TRNAMT")?;
+    let amount = Decimal::from_str(&amount)
+        .map_err(|e| anyhow!("invalid <TRNAMT> {} for {}: {}", amount, fitid, e))?;
+    let trntype = field("TRNTYPE").unwrap_or_default();
+    let memo = field("MEMO").ok();
+    let name = field("NAME")
+        .or_else(|_| field("PAYEE"))
+        .ok()
+        .or_else(|| memo.clone())
+        .unwrap_or_default();
+
+    Ok(OfxTransaction {
+        fitid,
+        trntype,
+        date,
+        amount,
+        name,
+        memo,
+    })
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Transactions of an OFX statement imported into a local account.
+pub struct Source {
+    statement: Statement,
+    account_id: String,
+    options: SyncOptions,
+}
+
+impl Source {
+    pub fn new(statement: Statement, account_id: &str, options: SyncOptions) -> Self {
+        Self {
+            statement,
+            account_id: account_id.to_string(),
+            options,
+        }
+    }
+
+    /// The id a statement transaction is stored under. FITIDs are only
+    /// unique within an account so they're scoped to the local account.
+    pub fn upstream_id(&self, tx: &OfxTransaction) -> String {
+        format!("{}:{}", self.account_id, tx.fitid)
+    }
+
+    fn to_canonical_txn(&self, tx: &OfxTransaction) -> Result<Transaction> {
+        let commodity = resolve_currency(
+            self.statement.currency.as_deref(),
+            None,
+            find_currency(&self.options.default_currency)?,
+        );
+        let counter_account = if tx.amount.is_sign_negative() {
+            &self.options.uncategorized_expenses
+        } else {
+            &self.options.uncategorized_income
+        };
+
+        Ok(Transaction {
+            id: ulid::Ulid::new(),
+            date: tx.date,
+            narration: clean_narration(tx.memo.as_deref().unwrap_or(&tx.name), &self.options),
+            status: Status::Resolved,
+            payee: Some(tx.name.clone()).filter(|name| !name.is_empty()),
+            // OFX amounts are signed from the account's point of view, the
+            // opposite of Plaid's.
+            postings: vec![
+                Posting {
+                    account: self.account_id.clone(),
+                    amount: tx.amount,
+                    currency: commodity.code().to_string(),
+                },
+                Posting {
+                    account: counter_account.clone(),
+                    amount: -tx.amount,
+                    currency: commodity.code().to_string(),
+                },
+            ],
+            tags: vec![],
+            meta: BTreeMap::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl TransactionSource<OfxTransaction> for Source {
+    async fn transactions(&mut self) -> Result<Vec<TransactionEvent<OfxTransaction>>> {
+        self.statement
+            .transactions
+            .iter()
+            .map(|tx| {
+                Ok(TransactionEvent::Added(TransactionEntry {
+                    canonical: self.to_canonical_txn(tx)?,
+                    source: tx.clone(),
+                }))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SGML: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+
+<OFX>
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<CURDEF>EUR
+<BANKACCTFROM><BANKID>12345<ACCTID>000123<ACCTTYPE>CHECKING</BANKACCTFROM>
+<BANKTRANLIST>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20230105120000.000[-5:EST]
+<TRNAMT>-12.50
+<FITID>F-1
+<NAME>Corner Bakery
+<MEMO>CARD 1234 BREAD &amp; COFFEE
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>CREDIT
+<DTPOSTED>20230106
+<TRNAMT>1000
+<FITID>F-2
+<NAME>Payroll
+</STMTTRN>
+</BANKTRANLIST>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>";
+
+    #[test]
+    fn parses_sgml_statements() {
+        let statement = parse(SGML).unwrap();
+
+        assert_eq!(statement.account_id.as_deref(), Some("000123"));
+        assert_eq!(statement.currency.as_deref(), Some("EUR"));
+        assert!(!statement.credit_card);
+        assert_eq!(
+            statement.transactions,
+            vec![
+                OfxTransaction {
+                    fitid: "F-1".into(),
+                    trntype: "DEBIT".into(),
+                    date: NaiveDate::from_ymd_opt(2023, 1, 5).unwrap(),
+                    amount: Decimal::new(-1250, 2),
+                    name: "Corner Bakery".into(),
+                    memo: Some("CARD 1234 BREAD & COFFEE".into()),
+                },
+                OfxTransaction {
+                    fitid: "F-2".into(),
+                    trntype: "CREDIT".into(),
+                    date: NaiveDate::from_ymd_opt(2023, 1, 6).unwrap(),
+                    amount: Decimal::new(1000, 0),
+                    name: "Payroll".into(),
+                    memo: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_xml_credit_card_statements() {
+        let statement = parse(
+            r#"<?xml version="1.0"?><OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+            <CURDEF>USD</CURDEF><CCACCTFROM><ACCTID>4111</ACCTID></CCACCTFROM>
+            <BANKTRANLIST><STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20230107</DTPOSTED>
+            <TRNAMT>-5.00</TRNAMT><FITID>X-1</FITID><NAME>Coffee</NAME></STMTTRN>
+            </BANKTRANLIST></CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>"#,
+        )
+        .unwrap();
+
+        assert!(statement.credit_card);
+        assert_eq!(statement.account_id.as_deref(), Some("4111"));
+        assert_eq!(statement.transactions.len(), 1);
+        assert_eq!(statement.transactions[0].amount, Decimal::new(-500, 2));
+    }
+
+    #[test]
+    fn rejects_transactions_without_fitid() {
+        let err = parse("<OFX><STMTTRN><DTPOSTED>20230105<TRNAMT>1</STMTTRN></OFX>").unwrap_err();
+        assert!(err.to_string().contains("FITID"));
+    }
+
+    #[tokio::test]
+    async fn postings_follow_ofx_sign() {
+        let mut source = Source::new(parse(SGML).unwrap(), "local-1", SyncOptions::default());
+
+        let events = source.transactions().await.unwrap();
+        let entry = match &events[0] {
+            TransactionEvent::Added(entry) => entry,
+            _ => unreachable!(),
+        };
+        assert_eq!(source.upstream_id(&entry.source), "local-1:F-1");
+        assert_eq!(entry.canonical.payee.as_deref(), Some("Corner Bakery"));
+        assert_eq!(
+            entry.canonical.postings,
+            vec![
+                Posting {
+                    account: "local-1".into(),
+                    amount: Decimal::new(-1250, 2),
+                    currency: "EUR".into(),
+                },
+                Posting {
+                    account: "Expenses:Uncategorized".into(),
+                    amount: Decimal::new(1250, 2),
+                    currency: "EUR".into(),
+                },
+            ]
+        );
+    }
+}
@@ -1,115 +1,795 @@
+use std::collections::BTreeMap;
+
 use anyhow::Result;
 use axum::async_trait;
 use chrono::NaiveDate;
-use futures_lite::{pin, stream::StreamExt};
-use rplaid::client::Plaid;
-use rplaid::model::{
-    self, Account, SyncTransactionsRequest, SyncTransactionsRequestOptions, TransactionStream,
-};
+use rplaid::client::ClientError;
+use rplaid::model::{self, Account};
+use rplaid::HttpClient;
+use serde::Deserialize;
+use thiserror::Error;
 
-use crate::core::{Status, Transaction};
+use crate::core::{Posting, Status, Transaction};
+use crate::currency::{find_currency, resolve_currency};
+use crate::plaid::sync::{PlaidTransaction, SyncClient, TransactionOptions};
+use crate::retry::{with_retry_when, RetryPolicy};
+use crate::settings::{Settings, MAX_SYNC_PAGE_SIZE};
 use crate::upstream::{AccountSource, TransactionEntry, TransactionEvent, TransactionSource};
 
-pub struct Source<'a> {
-    pub(crate) client: &'a Plaid,
+/// A failed Plaid request, keeping the details Plaid reported so failures can
+/// be told apart, e.g. an item needing re-authentication from rate limiting.
+#[derive(Debug, Error)]
+pub enum PlaidError {
+    #[error("{error_type}/{error_code}: {message} (request_id={})", request_id.as_deref().unwrap_or("none"))]
+    Api {
+        error_type: String,
+        error_code: String,
+        message: String,
+        request_id: Option<String>,
+    },
+    #[error(transparent)]
+    Client(ClientError),
+}
+
+impl PlaidError {
+    /// The Plaid error code, e.g. "ITEM_LOGIN_REQUIRED".
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            PlaidError::Api { error_code, .. } => Some(error_code),
+            PlaidError::Client(_) => None,
+        }
+    }
+}
+
+impl From<ClientError> for PlaidError {
+    fn from(err: ClientError) -> Self {
+        match err {
+            ClientError::App(res) => PlaidError::Api {
+                error_type: res.error_type.unwrap_or_else(|| "UNKNOWN".into()),
+                error_code: res.error_code.unwrap_or_else(|| "UNKNOWN".into()),
+                message: res.error_message.unwrap_or_default(),
+                request_id: res.request_id,
+            },
+            err => PlaidError::Client(err),
+        }
+    }
+}
+
+/// Plaid error codes for failures that are expected to clear up on their own.
+const TRANSIENT_ERROR_CODES: [&str; 5] = [
+    "RATE_LIMIT_EXCEEDED",
+    "PRODUCT_NOT_READY",
+    "INTERNAL_SERVER_ERROR",
+    "INSTITUTION_DOWN",
+    "INSTITUTION_NOT_RESPONDING",
+];
+
+/// Whether retrying a failed request might succeed, i.e. Plaid is rate
+/// limiting or still preparing data, or the failure was on Plaid's end.
+/// Anything else, like an item needing to be re-authenticated, is permanent.
+pub(crate) fn is_transient(err: &ClientError) -> bool {
+    match err {
+        ClientError::App(res) => {
+            matches!(
+                res.error_type.as_deref(),
+                Some("RATE_LIMIT_EXCEEDED" | "API_ERROR")
+            ) || res
+                .error_code
+                .as_deref()
+                .map_or(false, |code| TRANSIENT_ERROR_CODES.contains(&code))
+        }
+        ClientError::Http(err) => err.status().is_server_error(),
+        #[allow(unreachable_patterns)]
+        _ => false,
+    }
+}
+
+/// Fields of a Plaid transaction that can be used for a canonical
+/// transaction's payee or narration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionField {
+    MerchantName,
+    Name,
+    OriginalDescription,
+    /// The most general of the Plaid categories, e.g. "Transfer".
+    Category,
+}
+
+impl TransactionField {
+    fn value<'t>(&self, tx: &'t model::Transaction) -> Option<&'t str> {
+        let value = match self {
+            TransactionField::MerchantName => tx.merchant_name.as_deref(),
+            TransactionField::Name => Some(tx.name.as_str()),
+            TransactionField::OriginalDescription => tx.original_description.as_deref(),
+            TransactionField::Category => tx
+                .category
+                .as_ref()
+                .and_then(|c| c.first())
+                .map(String::as_str),
+        };
+
+        value.filter(|v| !v.trim().is_empty())
+    }
+}
+
+/// Returns the first of `fields` with a non-empty value.
+fn first_field<'t>(tx: &'t model::Transaction, fields: &[TransactionField]) -> Option<&'t str> {
+    fields.iter().find_map(|field| field.value(tx))
+}
+
+/// Options controlling what data is requested from the transactions sync
+/// endpoint.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Requests the raw description reported by the institution.
+    pub include_original_description: bool,
+    /// Requests Plaid's primary/detailed personal finance category labels.
+    pub include_personal_finance_category: bool,
+    /// Truncates narrations longer than the given number of characters.
+    pub narration_max_length: Option<usize>,
+    /// Strips trailing reference numbers and whitespace from narrations.
+    pub trim_narration: bool,
+    /// Stops requesting pages once this many transactions have been pulled,
+    /// the cursor is left after the page that reached the limit so the next
+    /// sync resumes from there.
+    pub max_transactions: Option<usize>,
+    /// Fields tried in order for the payee, left empty if none have a value.
+    pub payee_fields: Vec<TransactionField>,
+    /// Fields tried in order for the narration, falling back to the name.
+    pub narration_fields: Vec<TransactionField>,
+    /// Retries for pulls failing with transient errors, see [`is_transient`].
+    pub retry: RetryPolicy,
+    /// Currency for postings of transactions Plaid reports none for.
+    pub default_currency: String,
+    /// Counter-account for outflows.
+    pub uncategorized_expenses: String,
+    /// Counter-account for inflows.
+    pub uncategorized_income: String,
+    /// Transactions requested per page when syncing.
+    pub page_size: u32,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            include_original_description: false,
+            include_personal_finance_category: true,
+            narration_max_length: None,
+            trim_narration: false,
+            max_transactions: None,
+            payee_fields: vec![TransactionField::MerchantName],
+            narration_fields: vec![TransactionField::Name],
+            retry: RetryPolicy::default(),
+            default_currency: "USD".into(),
+            uncategorized_expenses: "Expenses:Uncategorized".into(),
+            uncategorized_income: "Income:Uncategorized".into(),
+            page_size: MAX_SYNC_PAGE_SIZE,
+        }
+    }
+}
+
+impl From<&Settings> for SyncOptions {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            include_original_description: settings.plaid.include_original_description,
+            include_personal_finance_category: settings.plaid.include_personal_finance_category,
+            narration_max_length: settings.narration_max_length,
+            trim_narration: settings.trim_narration,
+            max_transactions: None,
+            payee_fields: settings.payee_fields.clone(),
+            narration_fields: settings.narration_fields.clone(),
+            retry: settings.plaid.retry.clone(),
+            default_currency: settings.default_currency.clone(),
+            uncategorized_expenses: settings.ledger.uncategorized_expenses.clone(),
+            uncategorized_income: settings.ledger.uncategorized_income.clone(),
+            page_size: settings.plaid.sync_page_size,
+        }
+    }
+}
+
+pub struct Source<'a, T: HttpClient> {
+    pub(crate) client: &'a SyncClient<T>,
     pub(crate) token: String,
     cursor: Option<String>,
+    options: SyncOptions,
 }
 
-impl<'a> Source<'a> {
-    pub fn new(client: &'a Plaid, token: String, cursor: Option<String>) -> Self {
+impl<'a, T: HttpClient> Source<'a, T> {
+    pub fn new(client: &'a SyncClient<T>, token: String, cursor: Option<String>) -> Self {
         Self {
             client,
             token,
             cursor,
+            options: SyncOptions::default(),
+        }
+    }
+
+    pub fn with_options(self, options: SyncOptions) -> Self {
+        Self { options, ..self }
+    }
+
+    fn transaction_options(&self) -> TransactionOptions {
+        TransactionOptions {
+            include_original_description: self.options.include_original_description,
+            include_personal_finance_category: self.options.include_personal_finance_category,
         }
     }
 }
 
 #[async_trait]
-impl<'a> AccountSource for Source<'a> {
+impl<'a, T: HttpClient> AccountSource for Source<'a, T> {
     async fn accounts(&self) -> Result<Vec<Account>> {
-        Ok(self.client.accounts(&self.token).await?)
+        Ok(self
+            .client
+            .accounts(&self.token)
+            .await
+            .map_err(PlaidError::from)?)
     }
 }
 
-fn to_canonical_txn(tx: &model::Transaction) -> Result<Transaction> {
+// The untouched name remains available in the transaction source.
+pub(crate) fn clean_narration(name: &str, options: &SyncOptions) -> String {
+    let mut narration = name;
+    if options.trim_narration {
+        let trimmed = name
+            .trim()
+            .trim_end_matches(|c: char| c.is_ascii_digit() || c.is_whitespace() || c == '#');
+        if !trimmed.is_empty() {
+            narration = trimmed;
+        }
+    }
+
+    match options.narration_max_length {
+        Some(max) if narration.chars().count() > max => narration
+            .chars()
+            .take(max)
+            .collect::<String>()
+            .trim_end()
+            .to_string(),
+        _ => narration.to_string(),
+    }
+}
+
+/// Balances the transaction's account against the uncategorized account for
+/// the direction money moved. Plaid amounts are positive when money leaves the
+/// account for every account type, e.g. a purchase on a credit card is
+/// positive and grows the (negative) liability balance, so the account posting
+/// is always the negated amount regardless of whether the account is debit or
+/// credit normal.
+fn postings(tx: &model::Transaction, options: &SyncOptions) -> Result<Vec<Posting>> {
+    let commodity = resolve_currency(
+        tx.iso_currency_code.as_deref(),
+        tx.unofficial_currency_code.as_deref(),
+        find_currency(&options.default_currency)?,
+    );
+    let counter_account = if tx.amount.is_sign_negative() {
+        &options.uncategorized_income
+    } else {
+        &options.uncategorized_expenses
+    };
+
+    Ok(vec![
+        Posting {
+            account: tx.account_id.clone(),
+            amount: -tx.amount,
+            currency: commodity.code().to_string(),
+        },
+        Posting {
+            account: counter_account.clone(),
+            amount: tx.amount,
+            currency: commodity.code().to_string(),
+        },
+    ])
+}
+
+fn to_canonical_txn(tx: &model::Transaction, options: &SyncOptions) -> Result<Transaction> {
     Ok(Transaction {
         id: ulid::Ulid::new(),
         date: NaiveDate::parse_from_str(&tx.date, "%Y-%m-%d").unwrap(),
-        narration: tx.name.clone(),
+        narration: clean_narration(
+            first_field(tx, &options.narration_fields).unwrap_or(&tx.name),
+            options,
+        ),
         status: if tx.pending {
             Status::Pending
         } else {
             Status::Resolved
         },
-        payee: tx.merchant_name.clone(),
+        payee: first_field(tx, &options.payee_fields).map(str::to_string),
+        postings: postings(tx, options)?,
+        tags: vec![],
+        meta: BTreeMap::new(),
     })
 }
 
-impl<'a> Source<'a> {
+impl<'a, T: HttpClient> Source<'a, T> {
     pub fn next_cursor(self) -> String {
         self.cursor
             .expect("must call transactions on source before checking cursor")
     }
 }
 
-type PlaidTransactionEvent = TransactionEvent<model::Transaction>;
+type PlaidTransactionEvent = TransactionEvent<PlaidTransaction>;
 
-#[async_trait]
-impl<'a> TransactionSource<model::Transaction> for Source<'a> {
-    async fn transactions(&mut self) -> Result<Vec<PlaidTransactionEvent>> {
-        let tx_pages = self.client.transactions_sync_iter(SyncTransactionsRequest {
-            access_token: self.token.clone(),
-            cursor: self.cursor.clone(),
-            count: Some(500),
-            options: Some(SyncTransactionsRequestOptions {
-                include_personal_finance_category: Some(true),
-                include_original_description: Some(false),
-            }),
-        });
-        pin!(tx_pages);
+/// A change to an item's transactions pulled by a sync.
+enum Change {
+    Added(PlaidTransaction),
+    Modified(PlaidTransaction),
+    Removed(String),
+}
+
+impl<'a, T: HttpClient> Source<'a, T> {
+    /// Pulls every transaction dated within `since` and `until`, inclusive,
+    /// from `/transactions/get` instead of the sync cursor, which is left
+    /// untouched. Every transaction is reported as added, it's up to the
+    /// caller to refresh ones that are already stored.
+    pub async fn transactions_between(
+        &self,
+        since: NaiveDate,
+        until: NaiveDate,
+    ) -> Result<Vec<PlaidTransactionEvent>> {
+        let since = since.format("%Y-%m-%d").to_string();
+        let until = until.format("%Y-%m-%d").to_string();
+        let txns = with_retry_when(&self.options.retry, is_transient, || {
+            self.dated_pages(&since, &until)
+        })
+        .await
+        .map_err(PlaidError::from)?;
+
+        txns.into_iter()
+            .map(|txn| {
+                Ok(PlaidTransactionEvent::Added(TransactionEntry {
+                    canonical: to_canonical_txn(&txn, &self.options)?,
+                    source: txn,
+                }))
+            })
+            .collect()
+    }
 
-        let mut tx_list = vec![];
-        while let Some(txn_page) = tx_pages.next().await {
-            tx_list.extend(txn_page?);
+    async fn dated_pages(
+        &self,
+        since: &str,
+        until: &str,
+    ) -> Result<Vec<PlaidTransaction>, ClientError> {
+        let mut txns = vec![];
+        loop {
+            let page = self
+                .client
+                .transactions(
+                    &self.token,
+                    since,
+                    until,
+                    self.options.page_size,
+                    txns.len(),
+                    self.transaction_options(),
+                )
+                .await?;
+
+            let exhausted = page.transactions.is_empty();
+            txns.extend(page.transactions);
+            if exhausted || txns.len() >= page.total_transactions {
+                return Ok(txns);
+            }
         }
+    }
+
+    /// Pulls pages from the current cursor until Plaid has no more or the
+    /// page reaching `max_transactions`, returning the changes along with the
+    /// cursor the next sync resumes from. Nothing is kept from a failed pull,
+    /// retrying starts again from the same cursor.
+    async fn sync_pages(&self) -> Result<(Vec<Change>, String), ClientError> {
+        let mut cursor = self.cursor.clone();
+        let mut changes = vec![];
+        loop {
+            // The last page only asks for what's left under the limit.
+            let count = match self.options.max_transactions {
+                Some(max) => max
+                    .saturating_sub(changes.len())
+                    .clamp(1, self.options.page_size as usize) as u32,
+                None => self.options.page_size,
+            };
+            let page = self
+                .client
+                .sync(
+                    &self.token,
+                    cursor.as_deref(),
+                    count,
+                    self.transaction_options(),
+                )
+                .await?;
 
-        if let Some(next_cursor) = tx_list.last() {
-            assert!(matches!(next_cursor, TransactionStream::Done(_)));
+            changes.extend(page.added.into_iter().map(Change::Added));
+            changes.extend(page.modified.into_iter().map(Change::Modified));
+            changes.extend(
+                page.removed
+                    .into_iter()
+                    .map(|removed| Change::Removed(removed.transaction_id)),
+            );
 
-            match next_cursor {
-                TransactionStream::Done(cursor) => self.cursor = Some(cursor.clone()),
-                _ => unreachable!(),
+            let limit_reached =
+                matches!(self.options.max_transactions, Some(max) if changes.len() >= max);
+            if !page.has_more || limit_reached {
+                return Ok((changes, page.next_cursor));
             }
+            cursor = Some(page.next_cursor);
         }
+    }
+}
 
-        Ok(tx_list
+#[async_trait]
+impl<'a, T: HttpClient> TransactionSource<PlaidTransaction> for Source<'a, T> {
+    async fn transactions(&mut self) -> Result<Vec<PlaidTransactionEvent>> {
+        let (changes, next_cursor) =
+            with_retry_when(&self.options.retry, is_transient, || self.sync_pages())
+                .await
+                .map_err(PlaidError::from)?;
+        self.cursor = Some(next_cursor);
+
+        let options = &self.options;
+        changes
             .into_iter()
-            .filter_map(|e| match e {
-                TransactionStream::Added(txn) => {
-                    let entry = PlaidTransactionEvent::Added(TransactionEntry {
-                        canonical: to_canonical_txn(&txn).unwrap(),
-                        source: txn,
-                    });
-
-                    Some(entry)
-                }
-                TransactionStream::Modified(txn) => {
-                    let entry = PlaidTransactionEvent::Modified(TransactionEntry {
-                        canonical: to_canonical_txn(&txn).unwrap(),
-                        source: txn,
-                    });
-
-                    Some(entry)
-                }
-                TransactionStream::Removed(id) => Some(PlaidTransactionEvent::Removed(id)),
-                TransactionStream::Done(cursor) => {
-                    self.cursor = Some(cursor);
-
-                    None
-                }
+            .map(|change| match change {
+                Change::Added(txn) => Ok(PlaidTransactionEvent::Added(TransactionEntry {
+                    canonical: to_canonical_txn(&txn, options)?,
+                    source: txn,
+                })),
+                Change::Modified(txn) => Ok(PlaidTransactionEvent::Modified(TransactionEntry {
+                    canonical: to_canonical_txn(&txn, options)?,
+                    source: txn,
+                })),
+                Change::Removed(id) => Ok(PlaidTransactionEvent::Removed(id)),
             })
-            .collect::<Vec<PlaidTransactionEvent>>())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plaid::mock::{transactions_page, MockPlaid};
+    use crate::plaid::plaid_client_with;
+    use crate::settings::test_settings;
+    use crate::store::test_support::TransactionBuilder;
+
+    fn no_backoff() -> SyncOptions {
+        SyncOptions {
+            retry: RetryPolicy {
+                attempts: 2,
+                backoff_ms: 0,
+            },
+            ..SyncOptions::default()
+        }
+    }
+
+    fn options(max: Option<usize>, trim: bool) -> SyncOptions {
+        SyncOptions {
+            narration_max_length: max,
+            trim_narration: trim,
+            ..SyncOptions::default()
+        }
+    }
+
+    #[test]
+    fn narration_unchanged_by_default() {
+        let name = "AMAZON MKTPLACE PMTS 8754321 ";
+
+        assert_eq!(clean_narration(name, &SyncOptions::default()), name);
+    }
+
+    #[test]
+    fn narration_trims_trailing_references() {
+        let tests = vec![
+            ("AMAZON MKTPLACE PMTS 8754321", "AMAZON MKTPLACE PMTS"),
+            ("SHELL OIL #5743 ", "SHELL OIL"),
+            ("7-ELEVEN 32145", "7-ELEVEN"),
+            ("123456", "123456"),
+        ];
+
+        for (name, expected) in tests {
+            assert_eq!(clean_narration(name, &options(None, true)), expected);
+        }
+    }
+
+    #[test]
+    fn narration_truncation_boundaries() {
+        let tests = vec![
+            ("Coffee", Some(6), "Coffee"),
+            ("Coffee Shop", Some(6), "Coffee"),
+            ("Coffee Shop", Some(7), "Coffee"),
+            ("Café au lait", Some(4), "Café"),
+            ("Coffee", Some(0), ""),
+        ];
+
+        for (name, max, expected) in tests {
+            assert_eq!(clean_narration(name, &options(max, false)), expected);
+        }
+    }
+
+    #[test]
+    fn payee_and_narration_fall_back_through_fields() {
+        let mut tx = TransactionBuilder::new().build().source;
+        tx.name = "ONLINE TRANSFER TO SAVINGS".into();
+        tx.merchant_name = None;
+        tx.category = Some(vec!["Transfer".into(), "Debit".into()]);
+
+        let opts = SyncOptions {
+            payee_fields: vec![TransactionField::MerchantName, TransactionField::Category],
+            narration_fields: vec![TransactionField::OriginalDescription],
+            ..SyncOptions::default()
+        };
+        let txn = to_canonical_txn(&tx, &opts).unwrap();
+        assert_eq!(txn.payee.as_deref(), Some("Transfer"));
+        assert_eq!(txn.narration, "ONLINE TRANSFER TO SAVINGS");
+
+        tx.merchant_name = Some("Acme Bank".into());
+        let txn = to_canonical_txn(&tx, &opts).unwrap();
+        assert_eq!(txn.payee.as_deref(), Some("Acme Bank"));
+    }
+
+    #[test]
+    fn counter_posting_follows_amount_sign() {
+        let mut tx = TransactionBuilder::new()
+            .account_id("mock-account")
+            .build()
+            .source;
+
+        let txn = to_canonical_txn(&tx, &SyncOptions::default()).unwrap();
+        assert_eq!(
+            txn.postings,
+            vec![
+                Posting {
+                    account: "mock-account".into(),
+                    amount: (-33).into(),
+                    currency: "USD".into(),
+                },
+                Posting {
+                    account: "Expenses:Uncategorized".into(),
+                    amount: 33.into(),
+                    currency: "USD".into(),
+                },
+            ]
+        );
+
+        tx.amount = (-33).into();
+        let txn = to_canonical_txn(&tx, &SyncOptions::default()).unwrap();
+        assert_eq!(txn.postings[1].account, "Income:Uncategorized");
+        assert_eq!(txn.postings[1].amount, (-33).into());
+    }
+
+    #[test]
+    fn postings_carry_transaction_currency() {
+        let mut tx = TransactionBuilder::new()
+            .account_id("mock-account")
+            .build()
+            .source;
+        tx.iso_currency_code = Some("EUR".into());
+
+        let txn = to_canonical_txn(&tx, &SyncOptions::default()).unwrap();
+        assert!(txn.postings.iter().all(|p| p.currency == "EUR"));
+
+        // Crypto and other unofficial currencies are only reported as an
+        // unofficial code.
+        tx.iso_currency_code = None;
+        tx.unofficial_currency_code = Some("btc".into());
+        let txn = to_canonical_txn(&tx, &SyncOptions::default()).unwrap();
+        assert!(txn.postings.iter().all(|p| p.currency == "BTC"));
+    }
+
+    #[tokio::test]
+    async fn sync_retries_transient_errors() {
+        let settings = test_settings();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .fail(
+                    "/transactions/sync",
+                    429,
+                    "RATE_LIMIT_EXCEEDED",
+                    "TRANSACTIONS_LIMIT",
+                )
+                .respond(
+                    "/transactions/sync",
+                    transactions_page(Vec::<PlaidTransaction>::new(), "cursor-1", false),
+                ),
+        );
+
+        let mut source = Source::new(&client, "token".into(), None).with_options(no_backoff());
+        source.transactions().await.unwrap();
+        assert_eq!(source.next_cursor(), "cursor-1");
+    }
+
+    #[tokio::test]
+    async fn sync_fails_fast_on_permanent_errors() {
+        let settings = test_settings();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .fail(
+                    "/transactions/sync",
+                    400,
+                    "ITEM_ERROR",
+                    "ITEM_LOGIN_REQUIRED",
+                )
+                .respond(
+                    "/transactions/sync",
+                    transactions_page(Vec::<PlaidTransaction>::new(), "cursor-1", false),
+                ),
+        );
+
+        let mut source = Source::new(&client, "token".into(), None).with_options(no_backoff());
+        assert!(source.transactions().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sync_stops_after_the_page_reaching_the_limit() {
+        let settings = test_settings();
+        let first = TransactionBuilder::new().build().source;
+        let second = TransactionBuilder::new().build().source;
+        let mock = MockPlaid::new()
+            .replace(
+                "/transactions/sync",
+                transactions_page(vec![first], "cursor-1", true),
+            )
+            .respond(
+                "/transactions/sync",
+                transactions_page(vec![second], "cursor-2", false),
+            );
+
+        let client = SyncClient::new(&settings.plaid, mock.clone());
+        let mut source = Source::new(&client, "token".into(), None).with_options(SyncOptions {
+            max_transactions: Some(1),
+            ..SyncOptions::default()
+        });
+        assert_eq!(source.transactions().await.unwrap().len(), 1);
+        assert_eq!(source.next_cursor(), "cursor-1");
+
+        let client = SyncClient::new(&settings.plaid, mock);
+        let mut source = Source::new(&client, "token".into(), Some("cursor-1".into()));
+        assert_eq!(source.transactions().await.unwrap().len(), 1);
+        assert_eq!(source.next_cursor(), "cursor-2");
+    }
+
+    #[tokio::test]
+    async fn sync_requests_no_more_than_the_limit() {
+        let settings = test_settings();
+        let page = || {
+            vec![
+                TransactionBuilder::new().build().source,
+                TransactionBuilder::new().build().source,
+            ]
+        };
+        let mock = MockPlaid::new()
+            .replace(
+                "/transactions/sync",
+                transactions_page(page(), "cursor-1", true),
+            )
+            .respond(
+                "/transactions/sync",
+                transactions_page(page(), "cursor-2", true),
+            );
+
+        let client = SyncClient::new(&settings.plaid, mock.clone());
+        let mut source = Source::new(&client, "token".into(), None).with_options(SyncOptions {
+            max_transactions: Some(3),
+            page_size: 2,
+            ..SyncOptions::default()
+        });
+        source.transactions().await.unwrap();
+
+        let counts: Vec<u64> = mock
+            .requests("/transactions/sync")
+            .iter()
+            .map(|body| body["count"].as_u64().unwrap())
+            .collect();
+        assert_eq!(counts, [2, 1]);
+    }
+
+    #[tokio::test]
+    async fn sync_pulls_every_page_without_a_limit() {
+        let settings = test_settings();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .replace(
+                    "/transactions/sync",
+                    transactions_page(
+                        vec![TransactionBuilder::new().build().source],
+                        "cursor-1",
+                        true,
+                    ),
+                )
+                .respond(
+                    "/transactions/sync",
+                    transactions_page(
+                        vec![TransactionBuilder::new().build().source],
+                        "cursor-2",
+                        false,
+                    ),
+                ),
+        );
+
+        let mut source = Source::new(&client, "token".into(), None);
+        assert_eq!(source.transactions().await.unwrap().len(), 2);
+        assert_eq!(source.next_cursor(), "cursor-2");
+    }
+
+    #[tokio::test]
+    async fn sync_fails_on_unknown_default_currency() {
+        let settings = test_settings();
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .with_transactions(vec![TransactionBuilder::new().build().source], "cursor-1"),
+        );
+
+        let mut source = Source::new(&client, "token".into(), None).with_options(SyncOptions {
+            default_currency: "XYZ".into(),
+            ..SyncOptions::default()
+        });
+        assert!(source.transactions().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dated_pull_pages_until_every_transaction_is_pulled() {
+        let settings = test_settings();
+        let page = |txn: PlaidTransaction| {
+            serde_json::json!({
+                "transactions": [txn],
+                "total_transactions": 2,
+                "accounts": [],
+                "request_id": "mock",
+            })
+        };
+        let client = SyncClient::new(
+            &settings.plaid,
+            MockPlaid::new()
+                .replace(
+                    "/transactions/get",
+                    page(
+                        TransactionBuilder::new()
+                            .transaction_id("first")
+                            .build()
+                            .source,
+                    ),
+                )
+                .respond(
+                    "/transactions/get",
+                    page(
+                        TransactionBuilder::new()
+                            .transaction_id("second")
+                            .build()
+                            .source,
+                    ),
+                ),
+        );
+
+        let source = Source::new(&client, "token".into(), None);
+        let date = NaiveDate::from_ymd_opt(2022, 5, 1).unwrap();
+        let events = source.transactions_between(date, date).await.unwrap();
+        let ids: Vec<&str> = events
+            .iter()
+            .map(|event| match event {
+                TransactionEvent::Added(entry) => entry.source.transaction_id.as_str(),
+                _ => panic!("dated pulls only add transactions"),
+            })
+            .collect();
+        assert_eq!(ids, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn plaid_error_keeps_error_details() {
+        let settings = test_settings();
+        let plaid = plaid_client_with(
+            &settings.plaid,
+            MockPlaid::new().fail("/item/get", 400, "ITEM_ERROR", "ITEM_LOGIN_REQUIRED"),
+        );
+
+        let err = PlaidError::from(plaid.item("token").await.unwrap_err());
+        assert_eq!(err.code(), Some("ITEM_LOGIN_REQUIRED"));
+        assert_eq!(
+            err.to_string(),
+            "ITEM_ERROR/ITEM_LOGIN_REQUIRED: mock ITEM_LOGIN_REQUIRED (request_id=mock-request)"
+        );
     }
 }